@@ -0,0 +1,50 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use subtune::signals::SignalSample;
+use subtune::signals::transform::{TransformParameters, WaveletSource, wavelet_transform};
+use subtune::signals::wavelets::{morlet, MORLET_HALF_LENGTH};
+use subtune::utils::math::FloatType;
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// A few seconds of a chord, long enough for the fft sizes to be realistic
+fn signal(seconds: u32) -> SignalSample<FloatType> {
+    SignalSample {
+        sample_rate: SAMPLE_RATE,
+        samples: (0..seconds * SAMPLE_RATE)
+            .map(|n| {
+                let t = n as FloatType / SAMPLE_RATE as FloatType;
+                [110.0, 277.2, 329.6, 880.0].iter()
+                    .map(|frequency: &FloatType| (2.0 * std::f32::consts::PI * frequency * t).sin())
+                    .sum()
+            })
+            .collect(),
+    }
+}
+
+/// Two notes per semitone over 4 octaves starting from C2
+fn frequencies() -> Vec<FloatType> {
+    (0..96).map(|i| 65.41 * (i as FloatType / 24.0).exp2()).collect()
+}
+
+fn transform(criterion: &mut Criterion) {
+    let signal = signal(5);
+    let frequencies = frequencies();
+    let wavelet_factory = |frequency: FloatType, sample_rate: u32|
+        SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / frequency, sample_rate, &morlet(frequency));
+
+    let mut group = criterion.benchmark_group("wavelet_transform");
+    group.sample_size(10);
+    for (name, multirate, spectral, hop_size) in [("full rate", false, false, 1), ("full rate", false, false, 128),
+                                                  ("multirate", true, false, 1), ("multirate", true, false, 128),
+                                                  ("spectral", false, true, 128), ("multirate spectral", true, true, 128)] {
+        let parameters = TransformParameters { multirate, hop_size, analytic: true };
+        let wavelets = if spectral { WaveletSource::Spectral } else { WaveletSource::Sampled(&wavelet_factory) };
+        group.bench_with_input(BenchmarkId::new(name, format!("hop {}", hop_size)), &parameters, |bencher, parameters| {
+            bencher.iter(|| wavelet_transform(&signal, &wavelets, &frequencies, parameters));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, transform);
+criterion_main!(benches);
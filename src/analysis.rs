@@ -0,0 +1,161 @@
+use num_complex::Complex;
+use crate::signals::Scalogram;
+use crate::signals::wavelets::morlet_center;
+use crate::utils::math::Float;
+
+pub mod alignment;
+pub mod chords;
+pub mod chroma;
+pub mod difference;
+pub mod fingerprint;
+pub mod key;
+pub mod pitch;
+pub mod rhythm;
+pub mod separation;
+pub mod tuning;
+pub mod transcription;
+
+/// Magnitudes of the scalogram of the given frequencies (in ascending order) per frame, from the lowest frequency.
+/// Rows are shifted by the delay of their wavelet, so that all the values of a frame describe the same time.
+pub(crate) fn aligned_magnitudes<T: Float>(scalogram: &Scalogram<Complex<T>>, frequencies: &[T]) -> Vec<Vec<f64>> {
+    let rows = frequencies.len();
+    let delays = delays(scalogram, frequencies);
+    (0..scalogram.frames)
+        .map(|frame| {
+            (0..rows)
+                .map(|bin| match frame.checked_sub(delays[bin]) {
+                    Some(frame) => scalogram.row(rows - 1 - bin)[frame].norm().to_f64().unwrap(),
+                    None => 0.0,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Delay in frames of the wavelet of each frequency: aligned frames before it are zero, as no coefficient describes them
+pub(crate) fn delays<T: Float>(scalogram: &Scalogram<Complex<T>>, frequencies: &[T]) -> Vec<usize> {
+    frequencies.iter()
+        .map(|frequency| (morlet_center(frequency.to_f64().unwrap()) / scalogram.frame_duration()).round() as usize)
+        .collect()
+}
+
+/// Magnitude at any frequency, interpolated linearly in log frequency between the rows, 0 outside of them
+pub(crate) fn magnitude_at(values: &[f64], frequencies: &[f64], frequency: f64) -> f64 {
+    let above = frequencies.partition_point(|row| *row < frequency);
+    if above == frequencies.len() || (above == 0 && frequency < frequencies[0]) {
+        return 0.0;
+    }
+    if above == 0 {
+        return values[0];
+    }
+    let position = (frequency / frequencies[above - 1]).ln() / (frequencies[above] / frequencies[above - 1]).ln();
+    values[above - 1] + position * (values[above] - values[above - 1])
+}
+
+/// Whether the bin is a local maximum of the values: above the one below and at least the one above, the first and last
+/// bins having a single neighbour
+pub(crate) fn is_peak(values: &[f64], bin: usize) -> bool {
+    let value = values[bin];
+    (bin == 0 || value > values[bin - 1]) && (bin + 1 == values.len() || value >= values[bin + 1])
+}
+
+/// Offset in bins (between -0.5 and 0.5 for a peak) and magnitude of the top of the parabola through the log
+/// magnitudes of a peak and its neighbours, as the wavelet response is close to a gaussian in log frequency. None when
+/// a neighbour is zero, whose log would send the parabola to infinity
+pub(crate) fn parabolic_peak(below: f64, peak: f64, above: f64) -> Option<(f64, f64)> {
+    if below <= 0.0 || above <= 0.0 {
+        return None;
+    }
+    let (below, peak, above) = (below.ln(), peak.ln(), above.ln());
+    let shift = 0.5 * (below - above) / (below - 2.0 * peak + above);
+    Some((shift, (peak - 0.25 * (below - above) * shift).exp()))
+}
+
+/// Sequence of states maximizing the sum of their scores minus the penalty for every change of state
+pub(crate) fn viterbi(scores: &[Vec<f64>], switch_penalty: f64) -> Vec<usize> {
+    let Some(first) = scores.first() else { return Vec::new() };
+    let mut totals = first.clone();
+    // for every block after the first and every state, the state of the previous block on the best path to it
+    let mut previous_states: Vec<Vec<usize>> = Vec::with_capacity(scores.len());
+    for block_scores in &scores[1..] {
+        let best = (0..totals.len()).max_by(|a, b| totals[*a].total_cmp(&totals[*b])).unwrap();
+        let mut previous = Vec::with_capacity(totals.len());
+        let mut next_totals = Vec::with_capacity(totals.len());
+        for (state, score) in block_scores.iter().enumerate() {
+            let (from, total) = if totals[state] >= totals[best] - switch_penalty {
+                (state, totals[state])
+            } else {
+                (best, totals[best] - switch_penalty)
+            };
+            previous.push(from);
+            next_totals.push(total + score);
+        }
+        previous_states.push(previous);
+        totals = next_totals;
+    }
+    let mut state = (0..totals.len()).max_by(|a, b| totals[*a].total_cmp(&totals[*b])).unwrap();
+    let mut states = vec![state];
+    for previous in previous_states.iter().rev() {
+        state = previous[state];
+        states.push(state);
+    }
+    states.reverse();
+    states
+}
+
+/// Sample rate of the signals of the tests
+#[cfg(test)]
+pub(crate) const TEST_SAMPLE_RATE: u32 = 8000;
+
+/// Multi-rate analytic transform of samples at TEST_SAMPLE_RATE, on the equal-tempered notes of the octaves from
+/// start_octave, and its frequencies
+#[cfg(test)]
+pub(crate) fn test_scalogram(samples: Vec<f32>, start_octave: i32, octaves: u32, hop_size: usize) -> (Scalogram<Complex<f32>>, Vec<f32>) {
+    use crate::notes::grid::FrequencyGrid;
+    use crate::notes::tuning::Tuning;
+    use crate::signals::SignalSample;
+    use crate::signals::transform::{TransformParameters, WaveletSource, wavelet_transform};
+
+    let grid = FrequencyGrid::Notes { start_octave, octaves, frequencies_per_note: 1, tuning: Tuning::default() };
+    let frequencies = grid.frequencies::<f32>().unwrap();
+    let signal = SignalSample { sample_rate: TEST_SAMPLE_RATE, samples };
+    let scalogram = wavelet_transform(&signal, &WaveletSource::Spectral, &frequencies,
+                                      &TransformParameters { multirate: true, hop_size, analytic: true });
+    (scalogram, frequencies)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::{is_peak, magnitude_at, parabolic_peak, viterbi};
+
+    #[test]
+    fn interpolates_magnitudes() {
+        let frequencies = [100.0, 200.0, 400.0];
+        let values = [1.0, 2.0, 4.0];
+        assert_eq!(magnitude_at(&values, &frequencies, 50.0), 0.0);
+        assert_eq!(magnitude_at(&values, &frequencies, 100.0), 1.0);
+        assert!((magnitude_at(&values, &frequencies, 282.84) - 3.0).abs() < 1e-3);
+        assert_eq!(magnitude_at(&values, &frequencies, 500.0), 0.0);
+    }
+
+    #[test]
+    fn finds_and_interpolates_peaks() {
+        let values = [3.0, 1.0, 2.0, 2.0, 0.5, 4.0];
+        let peaks: Vec<usize> = (0..values.len()).filter(|bin| is_peak(&values, *bin)).collect();
+        assert_eq!(peaks, vec![0, 2, 5]);
+
+        // samples of a gaussian in log magnitude centered a quarter bin above the middle one
+        let gaussian = |bin: f64| (-(bin - 0.25f64).powi(2)).exp() * 2.0;
+        let (shift, magnitude) = parabolic_peak(gaussian(-1.0), gaussian(0.0), gaussian(1.0)).unwrap();
+        assert!((shift - 0.25).abs() < 1e-9);
+        assert!((magnitude - 2.0).abs() < 1e-9);
+        assert_eq!(parabolic_peak(0.0, 1.0, 0.5), None);
+    }
+
+    #[test]
+    fn viterbi_penalizes_changes() {
+        let scores = vec![vec![1.0, 0.0], vec![0.4, 0.6], vec![1.0, 0.0], vec![0.0, 1.0], vec![0.0, 1.0]];
+        assert_eq!(viterbi(&scores, 0.5), vec![0, 0, 0, 1, 1]);
+        assert_eq!(viterbi(&scores, 0.05), vec![0, 1, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,207 @@
+use std::str::FromStr;
+use num_complex::Complex;
+use crate::analysis::aligned_magnitudes;
+use crate::analysis::chroma::{chroma, ChromaNormalization, ChromaParameters};
+use crate::notes::tuning::Tuning;
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// Magnitudes are compressed as log(1 + COMPRESSION * magnitude / max) in the scalogram features, so that the loudest
+/// notes don't outweigh the rest
+const COMPRESSION: f64 = 100.0;
+
+#[derive(Clone, Copy)]
+pub enum AlignmentFeatures {
+    /// The 12 pitch classes, robust to differences of timbre and octave
+    Chroma,
+    /// The compressed magnitudes of all the rows
+    Scalogram,
+}
+
+impl FromStr for AlignmentFeatures {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chroma" => Ok(AlignmentFeatures::Chroma),
+            "scalogram" => Ok(AlignmentFeatures::Scalogram),
+            _ => Err(format!("Invalid alignment features '{}'", s)),
+        }
+    }
+}
+
+pub struct AlignmentParameters {
+    pub features: AlignmentFeatures,
+    /// Duration in seconds of the blocks of frames whose features are averaged and aligned
+    pub block_duration: f64,
+}
+
+impl Default for AlignmentParameters {
+    fn default() -> Self {
+        AlignmentParameters {
+            features: AlignmentFeatures::Chroma,
+            block_duration: 0.1,
+        }
+    }
+}
+
+pub struct Alignment {
+    /// Cosine distance between the features of every block of the reference (rows) and of the performance (columns)
+    pub cost: Vec<Vec<f64>>,
+    /// Pairs of blocks (reference, performance) from the first to the last ones, each step advancing either or both
+    pub path: Vec<(usize, usize)>,
+    /// Average cost of the blocks along the path
+    pub average_cost: f64,
+    /// In seconds
+    pub block_duration: f64,
+}
+
+/// Features of every block of the scalogram of the given frequencies (in ascending order)
+pub fn alignment_features<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                                    frequencies: &[T],
+                                    tuning: &Tuning,
+                                    parameters: &AlignmentParameters) -> Vec<Vec<f64>> {
+    let frames: Vec<Vec<f64>> = match parameters.features {
+        AlignmentFeatures::Chroma => {
+            let chroma_parameters = ChromaParameters { normalization: ChromaNormalization::None, ..ChromaParameters::default() };
+            chroma(scalogram, frequencies, tuning, &chroma_parameters).iter().map(|frame| frame.to_vec()).collect()
+        }
+        AlignmentFeatures::Scalogram => {
+            let magnitudes = aligned_magnitudes(scalogram, frequencies);
+            let max = magnitudes.iter().flatten().copied().fold(0.0, f64::max).max(f64::MIN_POSITIVE);
+            magnitudes.iter()
+                .map(|values| values.iter().map(|value| (COMPRESSION * value / max).ln_1p()).collect())
+                .collect()
+        }
+    };
+    let frame_duration = scalogram.frame_duration();
+    let frames_per_block = ((parameters.block_duration / frame_duration).round() as usize).max(1);
+    frames.chunks(frames_per_block)
+        .map(|block| {
+            let mut sum = vec![0.0; block[0].len()];
+            for frame in block {
+                sum.iter_mut().zip(frame).for_each(|(sum, value)| *sum += value);
+            }
+            sum
+        })
+        .collect()
+}
+
+/// Aligns the blocks of the performance to the ones of the reference by dynamic time warping: the path from the first
+/// to the last pair of blocks, stepping forward in either or both, with the least total cosine distance
+pub fn align(reference: &[Vec<f64>], performance: &[Vec<f64>], block_duration: f64) -> Alignment {
+    let cost: Vec<Vec<f64>> = reference.iter()
+        .map(|a| performance.iter().map(|b| cosine_distance(a, b)).collect())
+        .collect();
+    let path = warping_path(&cost);
+    let average_cost = path.iter().map(|(i, j)| cost[*i][*j]).sum::<f64>() / path.len().max(1) as f64;
+    Alignment { cost, path, average_cost, block_duration }
+}
+
+/// 1 - the cosine similarity, 0 between two silences and 1 between a silence and sound
+fn cosine_distance(a: &[f64], b: &[f64]) -> f64 {
+    let (norm_a, norm_b) = (a.iter().map(|x| x * x).sum::<f64>().sqrt(), b.iter().map(|x| x * x).sum::<f64>().sqrt());
+    match (norm_a == 0.0, norm_b == 0.0) {
+        (true, true) => 0.0,
+        (true, false) | (false, true) => 1.0,
+        (false, false) => 1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>() / (norm_a * norm_b),
+    }
+}
+
+/// Path of least accumulated cost through the matrix, from its first to its last cell
+fn warping_path(cost: &[Vec<f64>]) -> Vec<(usize, usize)> {
+    let (rows, columns) = (cost.len(), cost.first().map(|row| row.len()).unwrap_or(0));
+    if rows == 0 || columns == 0 {
+        return Vec::new();
+    }
+    let mut accumulated = vec![vec![f64::INFINITY; columns]; rows];
+    for i in 0..rows {
+        for j in 0..columns {
+            let previous = if i == 0 && j == 0 {
+                0.0
+            } else {
+                let diagonal = if i > 0 && j > 0 { accumulated[i - 1][j - 1] } else { f64::INFINITY };
+                let above = if i > 0 { accumulated[i - 1][j] } else { f64::INFINITY };
+                let left = if j > 0 { accumulated[i][j - 1] } else { f64::INFINITY };
+                diagonal.min(above).min(left)
+            };
+            accumulated[i][j] = cost[i][j] + previous;
+        }
+    }
+
+    let (mut i, mut j) = (rows - 1, columns - 1);
+    let mut path = vec![(i, j)];
+    while i > 0 || j > 0 {
+        (i, j) = if i == 0 {
+            (0, j - 1)
+        } else if j == 0 {
+            (i - 1, 0)
+        } else {
+            // the diagonal wins ties, so that equal costs don't make the path stairs
+            [(i - 1, j - 1), (i - 1, j), (i, j - 1)].into_iter()
+                .min_by(|a, b| accumulated[a.0][a.1].total_cmp(&accumulated[b.0][b.1]))
+                .unwrap()
+        };
+        path.push((i, j));
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::alignment::{align, alignment_features, AlignmentParameters, warping_path};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+    use crate::notes::tuning::Tuning;
+
+    #[test]
+    fn warps_around_costly_cells() {
+        let cost = vec![
+            vec![0.0, 1.0, 1.0],
+            vec![0.0, 1.0, 1.0],
+            vec![1.0, 0.0, 0.0],
+        ];
+        assert_eq!(warping_path(&cost), vec![(0, 0), (1, 0), (2, 1), (2, 2)]);
+        assert_eq!(warping_path(&[vec![0.5]]), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn aligns_a_slower_performance() {
+        // the same melody, the performance holding its notes for varying durations
+        let sample_rate = TEST_SAMPLE_RATE;
+        let melody = [60, 64, 67, 72, 69, 65];
+        let render = |durations: &[f64]| -> (Vec<f32>, Vec<f64>) {
+            let onsets: Vec<f64> = durations.iter().scan(0.0, |time, duration| { *time += duration; Some(*time - duration) }).collect();
+            let total = onsets.last().unwrap() + durations.last().unwrap();
+            let samples = (0..(total * sample_rate as f64) as usize)
+                .map(|n| {
+                    let t = n as f64 / sample_rate as f64;
+                    let note = onsets.iter().rposition(|onset| *onset <= t).unwrap();
+                    let frequency = 440.0 * ((melody[note] - 69) as f64 / 12.0).exp2();
+                    ((TAU * frequency * t).sin() + 0.3 * (TAU * 2.0 * frequency * t).sin()) as f32
+                })
+                .collect();
+            (samples, onsets)
+        };
+        let (reference, reference_onsets) = render(&[0.5; 6]);
+        let (performance, performance_onsets) = render(&[0.6, 0.9, 0.4, 0.7, 0.5, 0.8]);
+
+        let parameters = AlignmentParameters::default();
+        let features = |samples: Vec<f32>| {
+            let (scalogram, frequencies) = test_scalogram(samples, 3, 3, 40);
+            alignment_features(&scalogram, &frequencies, &Tuning::default(), &parameters)
+        };
+
+        let alignment = align(&features(reference), &features(performance), parameters.block_duration);
+
+        assert_eq!(alignment.path.first(), Some(&(0, 0)));
+        for (reference_onset, performance_onset) in reference_onsets.iter().zip(&performance_onsets).skip(1) {
+            let block = (reference_onset / alignment.block_duration).round() as usize;
+            let (_, aligned) = alignment.path.iter().find(|(i, _)| *i == block).unwrap();
+            let aligned = *aligned as f64 * alignment.block_duration;
+            assert!((aligned - performance_onset).abs() <= 0.15, "{} aligned to {} instead of {}", reference_onset, aligned, performance_onset);
+        }
+        assert!(alignment.average_cost < 0.1, "Average cost {}", alignment.average_cost);
+    }
+}
@@ -0,0 +1,266 @@
+use num_complex::Complex;
+use crate::analysis::{aligned_magnitudes, is_peak, viterbi};
+use crate::analysis::chroma::{fold, pitch_classes};
+use crate::notes::NOTE_NAMES;
+use crate::notes::tuning::Tuning;
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// Score of the no chord state in blocks with sound: chords whose template matches the chroma less are not reported
+const NO_CHORD_SCORE: f64 = 0.5;
+/// Blocks quieter than this fraction of the loudest one are silent
+const SILENCE_THRESHOLD: f64 = 0.01;
+/// The lowest peak of a frame at least this fraction of the strongest one is its bass note
+const BASS_PEAK_THRESHOLD: f64 = 0.2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Dominant7,
+    Major7,
+    Minor7,
+    Diminished,
+    Sus2,
+    Sus4,
+}
+
+impl ChordQuality {
+    const ALL: [ChordQuality; 8] = [ChordQuality::Major, ChordQuality::Minor, ChordQuality::Dominant7, ChordQuality::Major7,
+        ChordQuality::Minor7, ChordQuality::Diminished, ChordQuality::Sus2, ChordQuality::Sus4];
+
+    /// Semitones of the chord tones above the root
+    pub fn intervals(&self) -> &'static [usize] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Major7 => &[0, 4, 7, 11],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+            ChordQuality::Diminished => &[0, 3, 6],
+            ChordQuality::Sus2 => &[0, 2, 7],
+            ChordQuality::Sus4 => &[0, 5, 7],
+        }
+    }
+
+    /// Weight of the template similarity: the third harmonics of the notes add fifths to the chroma, which make the
+    /// rarer suspended and diminished chords match as well as the triads without a lower prior
+    fn prior(&self) -> f64 {
+        match self {
+            ChordQuality::Major | ChordQuality::Minor => 1.0,
+            ChordQuality::Dominant7 | ChordQuality::Major7 | ChordQuality::Minor7 => 0.95,
+            ChordQuality::Diminished | ChordQuality::Sus2 | ChordQuality::Sus4 => 0.9,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "maj",
+            ChordQuality::Minor => "min",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Major7 => "maj7",
+            ChordQuality::Minor7 => "min7",
+            ChordQuality::Diminished => "dim",
+            ChordQuality::Sus2 => "sus2",
+            ChordQuality::Sus4 => "sus4",
+        }
+    }
+
+    /// Suffix of the chord symbol, after the root
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Major7 => "maj7",
+            ChordQuality::Minor7 => "m7",
+            ChordQuality::Diminished => "dim",
+            ChordQuality::Sus2 => "sus2",
+            ChordQuality::Sus4 => "sus4",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chord {
+    /// Pitch class of the root, 0 for C
+    pub root: usize,
+    pub quality: ChordQuality,
+    /// Pitch class of the lowest chord tone, the root unless the chord is inverted
+    pub bass: usize,
+}
+
+impl Chord {
+    /// Chord symbol such as "Am7" or "C/E" for an inversion
+    pub fn symbol(&self) -> String {
+        let symbol = format!("{}{}", NOTE_NAMES[self.root], self.quality.suffix());
+        if self.bass == self.root { symbol } else { format!("{}/{}", symbol, NOTE_NAMES[self.bass]) }
+    }
+}
+
+/// Time span with a single chord, or none
+pub struct ChordSegment {
+    /// In seconds
+    pub start: f64,
+    pub end: f64,
+    pub chord: Option<Chord>,
+    /// Average similarity of the chroma to the chord template, from 0 to 1
+    pub confidence: f64,
+}
+
+pub struct ChordParameters {
+    /// Duration in seconds of the blocks of frames the chroma is averaged over
+    pub block_duration: f64,
+    /// Cost of changing chords, as a similarity to the template: higher values give fewer and longer segments
+    pub switch_penalty: f64,
+}
+
+impl Default for ChordParameters {
+    fn default() -> Self {
+        ChordParameters {
+            block_duration: 0.1,
+            switch_penalty: 0.5,
+        }
+    }
+}
+
+/// Chord segments of the scalogram of the given frequencies (in ascending order): the chroma of blocks of frames is
+/// matched against the templates of every root and quality, and the best sequence of chords is found with Viterbi,
+/// each change of chord costing the switch penalty. Inversions are found from the lowest peaks of the frames.
+pub fn recognize_chords<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                                  frequencies: &[T],
+                                  tuning: &Tuning,
+                                  parameters: &ChordParameters) -> Vec<ChordSegment> {
+    let magnitudes = aligned_magnitudes(scalogram, frequencies);
+    let pitch_classes = pitch_classes(&frequencies.iter().map(|frequency| frequency.to_f64().unwrap()).collect::<Vec<f64>>(), tuning);
+    let frame_duration = scalogram.frame_duration();
+    let frames_per_block = ((parameters.block_duration / frame_duration).round() as usize).max(1);
+
+    // (chroma, bass votes) of every block
+    let blocks: Vec<([f64; 12], [f64; 12])> = magnitudes.chunks(frames_per_block)
+        .map(|frames| {
+            let (mut chroma, mut bass) = ([0.0; 12], [0.0; 12]);
+            for values in frames {
+                let folded = fold(values, &pitch_classes);
+                chroma.iter_mut().zip(folded).for_each(|(sum, value)| *sum += value);
+                if let Some((pitch_class, magnitude)) = lowest_peak(values, &pitch_classes) {
+                    bass[pitch_class] += magnitude;
+                }
+            }
+            (chroma, bass)
+        })
+        .collect();
+    let loudest = blocks.iter().map(|(chroma, _)| chroma.iter().sum::<f64>()).fold(0.0, f64::max);
+
+    let chords: Vec<(usize, ChordQuality)> = (0..12)
+        .flat_map(|root| ChordQuality::ALL.into_iter().map(move |quality| (root, quality)))
+        .collect();
+    // state 0 is no chord, state i the chord i - 1
+    let scores: Vec<Vec<f64>> = blocks.iter()
+        .map(|(chroma, _)| {
+            let energy: f64 = chroma.iter().sum();
+            if energy == 0.0 || energy < SILENCE_THRESHOLD * loudest {
+                let mut scores = vec![0.0; chords.len() + 1];
+                scores[0] = 1.0;
+                return scores;
+            }
+            let norm = chroma.iter().map(|value| value * value).sum::<f64>().sqrt();
+            std::iter::once(NO_CHORD_SCORE)
+                .chain(chords.iter().map(|(root, quality)| {
+                    let tones = quality.intervals();
+                    let similarity = tones.iter().map(|interval| chroma[(root + interval) % 12]).sum::<f64>() / (norm * (tones.len() as f64).sqrt());
+                    quality.prior() * similarity
+                }))
+                .collect()
+        })
+        .collect();
+    let states = viterbi(&scores, parameters.switch_penalty);
+
+    let block_duration = frames_per_block as f64 * frame_duration;
+    let end = magnitudes.len() as f64 * frame_duration;
+    let mut segments: Vec<ChordSegment> = Vec::new();
+    let mut first_block = 0;
+    for block in 1..=states.len() {
+        if block < states.len() && states[block] == states[first_block] {
+            continue;
+        }
+        let state = states[first_block];
+        let confidence = (first_block..block).map(|block| scores[block][state]).sum::<f64>() / (block - first_block) as f64;
+        let chord = (state > 0).then(|| {
+            let (root, quality) = chords[state - 1];
+            let mut bass_votes = [0.0; 12];
+            for (_, bass) in &blocks[first_block..block] {
+                bass_votes.iter_mut().zip(bass).for_each(|(sum, value)| *sum += value);
+            }
+            // reversed so that the root wins ties, max_by returning the last maximum
+            let bass = quality.intervals().iter().rev()
+                .map(|interval| (root + interval) % 12)
+                .max_by(|a, b| bass_votes[*a].total_cmp(&bass_votes[*b]))
+                .filter(|bass| bass_votes[*bass] > 0.0)
+                .unwrap_or(root);
+            Chord { root, quality, bass }
+        });
+        segments.push(ChordSegment {
+            start: first_block as f64 * block_duration,
+            end: (block as f64 * block_duration).min(end),
+            chord,
+            confidence,
+        });
+        first_block = block;
+    }
+    segments
+}
+
+/// Pitch class and magnitude of the lowest local maximum of a frame that is strong enough to be a bass note
+fn lowest_peak(values: &[f64], pitch_classes: &[usize]) -> Option<(usize, f64)> {
+    let max = values.iter().copied().fold(0.0, f64::max);
+    if max == 0.0 {
+        return None;
+    }
+    (0..values.len())
+        .find(|bin| values[*bin] >= BASS_PEAK_THRESHOLD * max && is_peak(values, *bin))
+        .map(|bin| (pitch_classes[bin], values[bin]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::chords::{Chord, ChordParameters, ChordQuality, recognize_chords};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+    use crate::notes::tuning::Tuning;
+
+    #[test]
+    fn symbols() {
+        assert_eq!(Chord { root: 9, quality: ChordQuality::Minor7, bass: 9 }.symbol(), "Am7");
+        assert_eq!(Chord { root: 0, quality: ChordQuality::Major, bass: 4 }.symbol(), "C/E");
+        assert_eq!(Chord { root: 6, quality: ChordQuality::Diminished, bass: 6 }.symbol(), "F#dim");
+    }
+
+    #[test]
+    fn recognizes_chords_and_inversions() {
+        // C major, A minor, G7 with B in the bass, one second each, each note with two harmonics
+        let chords: [&[i32]; 3] = [&[48, 64, 67, 72], &[45, 60, 64, 69], &[47, 62, 65, 67]];
+        let sample_rate = TEST_SAMPLE_RATE;
+        let samples: Vec<f32> = (0..3 * sample_rate)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64;
+                chords[(t as usize).min(2)].iter()
+                    .map(|pitch| {
+                        let frequency = 440.0 * ((*pitch - 69) as f64 / 12.0).exp2();
+                        (TAU * frequency * t).sin() + 0.3 * (TAU * 2.0 * frequency * t).sin()
+                    })
+                    .sum::<f64>() as f32
+            })
+            .collect();
+        let (scalogram, frequencies) = test_scalogram(samples, 2, 4, 80);
+
+        let segments = recognize_chords(&scalogram, &frequencies, &Tuning::default(), &ChordParameters::default());
+
+        let symbols: Vec<String> = segments.iter().filter_map(|segment| segment.chord.map(|chord| chord.symbol())).collect();
+        assert_eq!(symbols, vec!["C", "Am", "G7/B"]);
+        let starts: Vec<f64> = segments.iter().filter(|segment| segment.chord.is_some()).map(|segment| segment.start).collect();
+        for (start, expected) in starts.iter().zip([0.0, 1.0, 2.0]) {
+            assert!((start - expected).abs() <= 0.2, "Segment starts at {} instead of {}", start, expected);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::str::FromStr;
+use num_complex::Complex;
+use crate::analysis::{aligned_magnitudes, magnitude_at};
+use crate::notes::tuning::Tuning;
+use crate::signals::Scalogram;
+use crate::utils::math::{cast, Float};
+
+/// Frames quieter than this fraction of the loudest one are left at zero rather than normalized, so that noise in
+/// silences doesn't show as loud as the music
+const SILENCE_THRESHOLD: f64 = 0.01;
+
+pub enum ChromaNormalization {
+    None,
+    Max,
+    Sum,
+    Euclidean,
+}
+
+impl FromStr for ChromaNormalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ChromaNormalization::None),
+            "max" => Ok(ChromaNormalization::Max),
+            "sum" => Ok(ChromaNormalization::Sum),
+            "l2" => Ok(ChromaNormalization::Euclidean),
+            _ => Err(format!("Invalid chroma normalization '{}'", s)),
+        }
+    }
+}
+
+pub struct ChromaParameters {
+    /// Number of partials (including the fundamental) whose magnitudes are added to the one of each row, 1 to only
+    /// fold the rows
+    pub harmonics: usize,
+    /// The n-th partial is weighted by harmonic_decay^(n-1)
+    pub harmonic_decay: f64,
+    pub normalization: ChromaNormalization,
+}
+
+impl Default for ChromaParameters {
+    fn default() -> Self {
+        ChromaParameters {
+            harmonics: 1,
+            harmonic_decay: 0.8,
+            normalization: ChromaNormalization::Max,
+        }
+    }
+}
+
+impl ChromaParameters {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.harmonic_decay >= 0.0 && self.harmonic_decay.is_finite()) {
+            return Err(format!("Invalid harmonic decay {}, should be finite and at least 0", self.harmonic_decay));
+        }
+        Ok(())
+    }
+}
+
+/// Pitch class (0 for C) of the nearest note of the tuning to each frequency
+pub fn pitch_classes(frequencies: &[f64], tuning: &Tuning) -> Vec<usize> {
+    frequencies.iter().map(|frequency| tuning.nearest_note(*frequency).rem_euclid(12) as usize).collect()
+}
+
+/// Sum of the magnitudes of one aligned frame per pitch class
+pub(crate) fn fold(values: &[f64], pitch_classes: &[usize]) -> [f64; 12] {
+    let mut chroma = [0.0; 12];
+    for (value, pitch_class) in values.iter().zip(pitch_classes) {
+        chroma[*pitch_class] += value;
+    }
+    chroma
+}
+
+/// Chroma of every frame of the scalogram of the given frequencies (in ascending order): the magnitudes of the rows,
+/// optionally summed with the ones of their harmonics, folded onto the 12 pitch classes of the tuning and normalized
+pub fn chroma<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                        frequencies: &[T],
+                        tuning: &Tuning,
+                        parameters: &ChromaParameters) -> Vec<[f64; 12]> {
+    let magnitudes = aligned_magnitudes(scalogram, frequencies);
+    let frequencies: Vec<f64> = frequencies.iter().map(|frequency| frequency.to_f64().unwrap()).collect();
+    let pitch_classes = pitch_classes(&frequencies, tuning);
+    let mut chroma: Vec<[f64; 12]> = magnitudes.iter()
+        .map(|values| {
+            if parameters.harmonics <= 1 {
+                return fold(values, &pitch_classes);
+            }
+            let weighted: Vec<f64> = frequencies.iter()
+                .map(|frequency| (1..=parameters.harmonics)
+                    .map(|partial| parameters.harmonic_decay.powi(partial as i32 - 1) * magnitude_at(values, &frequencies, partial as f64 * frequency))
+                    .sum())
+                .collect();
+            fold(&weighted, &pitch_classes)
+        })
+        .collect();
+
+    let loudest = chroma.iter().map(|frame| frame.iter().sum::<f64>()).fold(0.0, f64::max);
+    for frame in &mut chroma {
+        let norm = match parameters.normalization {
+            ChromaNormalization::None => continue,
+            ChromaNormalization::Max => frame.iter().copied().fold(0.0, f64::max),
+            ChromaNormalization::Sum => frame.iter().sum(),
+            ChromaNormalization::Euclidean => frame.iter().map(|value| value * value).sum::<f64>().sqrt(),
+        };
+        if frame.iter().sum::<f64>() < SILENCE_THRESHOLD * loudest || norm == 0.0 {
+            *frame = [0.0; 12];
+        } else {
+            frame.iter_mut().for_each(|value| *value /= norm);
+        }
+    }
+    chroma
+}
+
+/// Chromagram as a scalogram of 12 rows from B to C, to be drawn like one
+pub fn chroma_scalogram<T: Float>(chroma: &[[f64; 12]], sample_rate: u32, hop_size: usize) -> Scalogram<Complex<T>> {
+    Scalogram {
+        sample_rate,
+        hop_size,
+        frames: chroma.len(),
+        multirate: false,
+        coefficients: (0..12).rev()
+            .flat_map(|pitch_class| chroma.iter().map(move |frame| Complex::new(cast(frame[pitch_class]), T::zero())))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::chroma::{chroma, chroma_scalogram, ChromaNormalization, ChromaParameters};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+    use crate::notes::tuning::Tuning;
+
+    #[test]
+    fn folds_notes_and_weights_harmonics() {
+        // an A3 with strong harmonics, the third of which is an E
+        let sample_rate = TEST_SAMPLE_RATE;
+        let samples: Vec<f32> = (0..sample_rate)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64;
+                (1..=4).map(|partial| (TAU * 220.0 * partial as f64 * t).sin()).sum::<f64>() as f32
+            })
+            .collect();
+        let tuning = Tuning::default();
+        let (scalogram, frequencies) = test_scalogram(samples, 2, 5, 80);
+
+        let folded = chroma(&scalogram, &frequencies, &tuning, &ChromaParameters::default());
+        let frame = folded[folded.len() / 2];
+        assert_eq!(frame[9], 1.0);
+        assert!(frame[4] > 0.3, "{:?}", frame);
+
+        // with harmonics, the A also collects the magnitudes of its partials and the E stands out less
+        let weighted = chroma(&scalogram, &frequencies, &tuning,
+                              &ChromaParameters { harmonics: 4, harmonic_decay: 0.8, normalization: ChromaNormalization::Sum });
+        let weighted_frame = weighted[weighted.len() / 2];
+        assert!((weighted_frame.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        let max = weighted_frame.iter().copied().fold(0.0, f64::max);
+        assert_eq!(weighted_frame[9], max);
+        assert!(weighted_frame[4] / max < frame[4], "{:?}", weighted_frame);
+
+        // silence before the transform has filled is left at zero
+        assert_eq!(folded[0], [0.0; 12]);
+    }
+
+    #[test]
+    fn harmonic_decay_is_validated() {
+        assert!(ChromaParameters::default().validate().is_ok());
+        assert!(ChromaParameters { harmonic_decay: 0.0, ..ChromaParameters::default() }.validate().is_ok());
+        for harmonic_decay in [f64::NAN, f64::INFINITY, -0.5] {
+            assert!(ChromaParameters { harmonic_decay, ..ChromaParameters::default() }.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn chromagram_rows_from_b_to_c() {
+        let mut frame = [0.0; 12];
+        frame[0] = 1.0;
+        frame[11] = 0.5;
+        let scalogram = chroma_scalogram::<f32>(&[frame, [0.0; 12]], 8000, 10);
+        assert_eq!(scalogram.rows().len(), 12);
+        assert_eq!(scalogram.row(0)[0].re, 0.5);
+        assert_eq!(scalogram.row(11)[0].re, 1.0);
+        assert_eq!(scalogram.row(11)[1].re, 0.0);
+    }
+}
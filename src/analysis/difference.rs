@@ -0,0 +1,67 @@
+use num_complex::Complex;
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// Magnitudes of the second scalogram minus the ones of the first, as real coefficients, over the frames where both
+/// are defined. The second recording is compared `offset` seconds later than the first one (earlier if negative), so
+/// the result starts at max(0, -offset) seconds in the first one. Both scalograms must come from transforms with the
+/// same sample rate, hop size and frequencies.
+pub fn difference<T: Float>(first: &Scalogram<Complex<T>>,
+                            second: &Scalogram<Complex<T>>,
+                            offset: f64) -> Result<Scalogram<Complex<T>>, String> {
+    if first.sample_rate != second.sample_rate || first.hop_size != second.hop_size {
+        return Err(format!("Can't compare transforms at {} Hz every {} samples and at {} Hz every {} samples",
+                           first.sample_rate, first.hop_size, second.sample_rate, second.hop_size));
+    }
+    if first.rows().len() != second.rows().len() {
+        return Err(format!("Can't compare transforms of {} and {} frequencies", first.rows().len(), second.rows().len()));
+    }
+    let shift = (offset / first.frame_duration()).round() as isize;
+    let (first_start, second_start) = (shift.min(0).unsigned_abs(), shift.max(0) as usize);
+    let frames = first.frames.saturating_sub(first_start).min(second.frames.saturating_sub(second_start));
+    if frames == 0 {
+        return Err(format!("The recordings don't overlap with an offset of {} s", offset));
+    }
+
+    let coefficients = first.rows().zip(second.rows())
+        .flat_map(|(first_row, second_row)| {
+            first_row[first_start..first_start + frames].iter().zip(&second_row[second_start..second_start + frames])
+                .map(|(a, b)| Complex::new(b.norm() - a.norm(), T::zero()))
+        })
+        .collect();
+    Ok(Scalogram { sample_rate: first.sample_rate, hop_size: first.hop_size, frames, multirate: first.multirate, coefficients })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::difference::difference;
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+
+    #[test]
+    fn compares_shifted_recordings() {
+        // an A4 then an A5, the second recording half a second late with a louder A5
+        let sample_rate = TEST_SAMPLE_RATE;
+        let render = |delay: f64, loudness: f64| (0..3 * sample_rate)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64 - delay;
+                match t {
+                    t if (0.0..1.0).contains(&t) => (TAU * 440.0 * t).sin() as f32,
+                    t if (1.0..2.0).contains(&t) => (loudness * (TAU * 880.0 * t).sin()) as f32,
+                    _ => 0.0,
+                }
+            })
+            .collect();
+        let (first, frequencies) = test_scalogram(render(0.0, 1.0), 3, 3, 40);
+        let (second, _) = test_scalogram(render(0.5, 2.0), 3, 3, 40);
+
+        let diff = difference(&first, &second, 0.5).unwrap();
+
+        assert_eq!(diff.frames, first.frames - 100);
+        let (a4, a5) = (frequencies.len() - 1 - 21, frequencies.len() - 1 - 33);
+        let (during_a4, during_a5) = (100, 300);
+        assert!(diff.row(a4)[during_a4].re.abs() < 0.01, "{}", diff.row(a4)[during_a4].re);
+        assert!((diff.row(a5)[during_a5].re - first.row(a5)[during_a5].norm()).abs() < 0.01 * first.row(a5)[during_a5].norm());
+        assert!(difference(&first, &second, 10.0).is_err());
+    }
+}
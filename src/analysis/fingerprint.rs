@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use num_complex::Complex;
+use crate::analysis::aligned_magnitudes;
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// A peak is the strongest coefficient within this many seconds before and after it in its row
+const PEAK_TIME_WINDOW: f64 = 0.1;
+/// ... and within this many octaves below and above it in its frame
+const PEAK_FREQUENCY_WINDOW: f64 = 1.0 / 6.0;
+/// Peaks weaker than this fraction of the strongest coefficient are ignored
+const PEAK_THRESHOLD: f64 = 0.01;
+/// Only the strongest peaks of every second are kept, so that the fingerprints stay compact
+const PEAKS_PER_SECOND: usize = 30;
+/// Every peak is paired with this many of the following ones
+const FAN_OUT: usize = 5;
+/// Largest time in seconds between the two peaks of a pair
+const MAX_PAIR_INTERVAL: f64 = 2.0;
+/// Times are quantized to this many seconds in the hashes and the match offsets
+const TIME_UNIT: f64 = 0.01;
+/// Fewer aligned hashes than this are a coincidence rather than a match
+const MIN_MATCHES: usize = 5;
+
+/// Hash of a pair of peaks at the time of the first one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fingerprint {
+    /// Rows of the two peaks and the time between them in TIME_UNIT, 16 bits each
+    pub hash: u64,
+    /// In seconds
+    pub time: f64,
+}
+
+pub struct ClipMatch {
+    /// Time in seconds of the recording where the clip starts
+    pub offset: f64,
+    /// Number of fingerprints of the clip found at this offset in the recording
+    pub matches: usize,
+    /// Share of the fingerprints of the clip found at this offset, from 0 to 1
+    pub confidence: f64,
+}
+
+/// Fingerprints of the scalogram of the given frequencies (in ascending order), by constellation hashing (Wang, 2003):
+/// the peaks of the magnitudes are paired with the next few peaks, and each pair is hashed from the rows of its peaks
+/// and the time between them, which don't depend on the loudness or the position in the recording. Recordings must be
+/// transformed on the same frequencies to be compared.
+pub fn fingerprint<T: Float>(scalogram: &Scalogram<Complex<T>>, frequencies: &[T]) -> Vec<Fingerprint> {
+    let magnitudes = aligned_magnitudes(scalogram, frequencies);
+    let frequencies: Vec<f64> = frequencies.iter().map(|frequency| frequency.to_f64().unwrap()).collect();
+    let frame_duration = scalogram.frame_duration();
+    let peaks = peaks(&magnitudes, &frequencies, frame_duration);
+
+    let mut fingerprints = Vec::new();
+    for (index, (time, bin)) in peaks.iter().enumerate() {
+        for (other_time, other_bin) in peaks[index + 1..].iter()
+            .filter(|(other_time, _)| other_time > time)
+            .take_while(|(other_time, _)| other_time - time <= MAX_PAIR_INTERVAL)
+            .take(FAN_OUT) {
+            let interval = ((other_time - time) / TIME_UNIT).round() as u64;
+            fingerprints.push(Fingerprint { hash: (*bin as u64) << 32 | (*other_bin as u64) << 16 | interval, time: *time });
+        }
+    }
+    fingerprints
+}
+
+/// (time, bin) of the local maxima of the magnitudes, in chronological order
+fn peaks(magnitudes: &[Vec<f64>], frequencies: &[f64], frame_duration: f64) -> Vec<(f64, usize)> {
+    let max = magnitudes.iter().flatten().copied().fold(0.0, f64::max);
+    if max == 0.0 {
+        return Vec::new();
+    }
+    let frames = magnitudes.len();
+    let half_window = (PEAK_TIME_WINDOW / frame_duration).round() as usize;
+    // maximum over the time window, then over the frequency window
+    let along_rows: Vec<Vec<f64>> = (0..frames)
+        .map(|frame| {
+            let window = &magnitudes[frame.saturating_sub(half_window)..(frame + half_window + 1).min(frames)];
+            (0..frequencies.len()).map(|bin| window.iter().map(|values| values[bin]).fold(0.0, f64::max)).collect()
+        })
+        .collect();
+    let ratio = PEAK_FREQUENCY_WINDOW.exp2();
+    let ranges: Vec<(usize, usize)> = frequencies.iter()
+        .map(|frequency| (frequencies.partition_point(|other| *other < frequency / ratio),
+                          frequencies.partition_point(|other| *other <= frequency * ratio)))
+        .collect();
+
+    let mut candidates: Vec<(usize, usize, f64)> = Vec::new();
+    for (frame, values) in magnitudes.iter().enumerate() {
+        for (bin, value) in values.iter().enumerate() {
+            let (low, high) = ranges[bin];
+            if *value >= PEAK_THRESHOLD * max && *value >= along_rows[frame][low..high].iter().copied().fold(0.0, f64::max) {
+                candidates.push((frame, bin, *value));
+            }
+        }
+    }
+    // the strongest peaks of every second
+    let frames_per_second = ((1.0 / frame_duration).round() as usize).max(1);
+    let mut peaks = Vec::new();
+    for second in candidates.chunk_by(|a, b| a.0 / frames_per_second == b.0 / frames_per_second) {
+        let mut second = second.to_vec();
+        second.sort_by(|a, b| b.2.total_cmp(&a.2));
+        second.truncate(PEAKS_PER_SECOND);
+        second.sort_by_key(|(frame, bin, _)| (*frame, *bin));
+        peaks.extend(second.iter().map(|(frame, bin, _)| (*frame as f64 * frame_duration, *bin)));
+    }
+    peaks
+}
+
+/// Where the clip best occurs in the recording: the offset shared by the most pairs of identical hashes, None if too
+/// few hashes agree
+pub fn find_clip(clip: &[Fingerprint], recording: &[Fingerprint]) -> Option<ClipMatch> {
+    let mut times: HashMap<u64, Vec<f64>> = HashMap::new();
+    for fingerprint in recording {
+        times.entry(fingerprint.hash).or_default().push(fingerprint.time);
+    }
+    let mut offsets: HashMap<i64, usize> = HashMap::new();
+    for fingerprint in clip {
+        for time in times.get(&fingerprint.hash).into_iter().flatten() {
+            *offsets.entry(((time - fingerprint.time) / TIME_UNIT).round() as i64).or_default() += 1;
+        }
+    }
+    // counted with the neighbouring units, as the peaks of the clip and the recording may fall a frame apart
+    let count = |offset: i64| (offset - 1..=offset + 1).map(|offset| offsets.get(&offset).copied().unwrap_or(0)).sum::<usize>();
+    let (offset, matches) = offsets.keys()
+        .map(|offset| (*offset, count(*offset)))
+        .max_by(|a, b| a.1.cmp(&b.1).then(offsets[&a.0].cmp(&offsets[&b.0])).then(b.0.cmp(&a.0)))?;
+    (matches >= MIN_MATCHES).then(|| ClipMatch {
+        offset: offset as f64 * TIME_UNIT,
+        matches,
+        confidence: (matches as f64 / clip.len() as f64).min(1.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::fingerprint::{find_clip, fingerprint};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+
+    /// Notes of a pseudo-random melody with a chord below, a new one every 0.2 seconds
+    fn melody(seed: u64, seconds: f64, sample_rate: u32) -> Vec<f32> {
+        let mut state = seed;
+        let notes: Vec<(f64, f64)> = (0..(seconds / 0.2) as usize)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let pitch = (state >> 33) % 24;
+                (440.0 * (pitch as f64 / 12.0).exp2(), 220.0 * (((state >> 40) % 12) as f64 / 12.0).exp2())
+            })
+            .collect();
+        (0..(seconds * sample_rate as f64) as usize)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64;
+                let (high, low) = notes[((t / 0.2) as usize).min(notes.len() - 1)];
+                ((TAU * high * t).sin() + 0.5 * (TAU * low * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_clip_in_recording() {
+        let sample_rate = TEST_SAMPLE_RATE;
+        let recording = melody(7, 8.0, sample_rate);
+        // 2 seconds from 3.3, a bit quieter
+        let clip: Vec<f32> = recording[(3.3 * sample_rate as f64) as usize..(5.3 * sample_rate as f64) as usize].iter()
+            .map(|sample| 0.5 * sample)
+            .collect();
+        let other = melody(11, 2.0, sample_rate);
+
+        let fingerprints = |samples: Vec<f32>| {
+            let (scalogram, frequencies) = test_scalogram(samples, 3, 4, 40);
+            fingerprint(&scalogram, &frequencies)
+        };
+        let (recording, clip, other) = (fingerprints(recording), fingerprints(clip), fingerprints(other));
+
+        let found = find_clip(&clip, &recording).unwrap();
+        assert!((found.offset - 3.3).abs() <= 0.02, "Found at {}", found.offset);
+        assert!(found.confidence > 0.2, "Confidence {}", found.confidence);
+        let unrelated = find_clip(&other, &recording).map(|found| found.confidence).unwrap_or(0.0);
+        assert!(unrelated < found.confidence / 2.0, "Unrelated clip found with confidence {}", unrelated);
+    }
+}
@@ -0,0 +1,276 @@
+use std::str::FromStr;
+use num_complex::Complex;
+use crate::analysis::chroma::{chroma, ChromaParameters};
+use crate::analysis::viterbi;
+use crate::notes::{pitch_class, spell};
+use crate::notes::tuning::Tuning;
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// Krumhansl-Kessler probe tone ratings of the pitch classes in major and minor keys, from the tonic
+const MAJOR_PROFILE: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Major => "major",
+            Mode::Minor => "minor",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Key {
+    /// Pitch class of the tonic, 0 for C
+    pub tonic: usize,
+    pub mode: Mode,
+}
+
+impl Key {
+    /// All 24 keys, major ones first
+    fn all() -> impl Iterator<Item=Key> {
+        [Mode::Major, Mode::Minor].into_iter().flat_map(|mode| (0..12).map(move |tonic| Key { tonic, mode }))
+    }
+
+    /// Name such as "Eb major" or "F# minor"
+    pub fn name(&self) -> String {
+        format!("{} {}", self.tonic_name(), self.mode.name())
+    }
+
+    /// Tonic spelled as in the key signature, e.g. Eb rather than D#
+    pub fn tonic_name(&self) -> String {
+        let (step, alter, _) = spell(self.tonic as i32, self.fifths());
+        format!("{}{}", step, match alter { 1 => "#", -1 => "b", _ => "" })
+    }
+
+    /// Number of sharps of the key signature, or minus the number of flats, from -5 (Db major) to 6 (F# major)
+    pub fn fifths(&self) -> i32 {
+        let major_tonic = match self.mode { Mode::Major => self.tonic, Mode::Minor => (self.tonic + 3) % 12 };
+        let fifths = (major_tonic * 7 % 12) as i32;
+        if fifths > 6 { fifths - 12 } else { fifths }
+    }
+
+    /// Pearson correlation of a pitch class histogram with the profile of the key
+    fn correlation(&self, histogram: &[f64; 12]) -> f64 {
+        let profile = match self.mode { Mode::Major => &MAJOR_PROFILE, Mode::Minor => &MINOR_PROFILE };
+        let profile: Vec<f64> = (0..12).map(|pitch_class| profile[(pitch_class + 12 - self.tonic) % 12]).collect();
+        let (histogram_mean, profile_mean) = (histogram.iter().sum::<f64>() / 12.0, profile.iter().sum::<f64>() / 12.0);
+        let (mut covariance, mut histogram_variance, mut profile_variance) = (0.0, 0.0, 0.0);
+        for (value, weight) in histogram.iter().zip(&profile) {
+            covariance += (value - histogram_mean) * (weight - profile_mean);
+            histogram_variance += (value - histogram_mean).powi(2);
+            profile_variance += (weight - profile_mean).powi(2);
+        }
+        if histogram_variance == 0.0 { 0.0 } else { covariance / (histogram_variance * profile_variance).sqrt() }
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    /// Tonic and mode, e.g. "Eb major" or "F# minor"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid key '{}'", s);
+        let (tonic, mode) = s.split_once(' ').ok_or_else(invalid)?;
+        let tonic = pitch_class(tonic).ok_or_else(invalid)? as usize;
+        let mode = match mode {
+            "major" => Mode::Major,
+            "minor" => Mode::Minor,
+            _ => return Err(invalid()),
+        };
+        Ok(Key { tonic, mode })
+    }
+}
+
+pub struct KeyEstimate {
+    pub key: Key,
+    /// Correlation of the pitch class histogram with the profile of the key, from -1 to 1
+    pub correlation: f64,
+    /// How much better the key fits than the best other one: the difference of their correlations, negative for a
+    /// segment whose key was chosen in the context of its neighbours while another key fits it alone better
+    pub confidence: f64,
+}
+
+/// Time span in a single key
+pub struct KeySegment {
+    /// In seconds
+    pub start: f64,
+    pub end: f64,
+    pub estimate: KeyEstimate,
+}
+
+pub struct KeyParameters {
+    /// Duration in seconds of the blocks between which the key can change
+    pub block_duration: f64,
+    /// Duration in seconds of the histogram of each block, centered on it
+    pub window_duration: f64,
+    /// Cost of changing keys, as a correlation: higher values give fewer key changes
+    pub switch_penalty: f64,
+}
+
+impl Default for KeyParameters {
+    fn default() -> Self {
+        KeyParameters {
+            block_duration: 2.0,
+            window_duration: 8.0,
+            switch_penalty: 1.0,
+        }
+    }
+}
+
+/// Key whose profile correlates the most with the pitch class histogram, None if the histogram is flat (silence)
+pub fn estimate_key(histogram: &[f64; 12]) -> Option<KeyEstimate> {
+    let key = Key::all().max_by(|a, b| a.correlation(histogram).total_cmp(&b.correlation(histogram))).unwrap();
+    estimate(key, histogram)
+}
+
+/// Correlation and confidence of the key for the histogram, None if the histogram is flat
+fn estimate(key: Key, histogram: &[f64; 12]) -> Option<KeyEstimate> {
+    let correlation = key.correlation(histogram);
+    let best_other = Key::all().filter(|other| *other != key).map(|other| other.correlation(histogram)).fold(f64::MIN, f64::max);
+    (correlation != 0.0).then_some(KeyEstimate { key, correlation, confidence: correlation - best_other })
+}
+
+/// Global key of the scalogram of the given frequencies (in ascending order), from the histogram of its chroma, and
+/// its segments in a single key. Each block is scored with the correlations of the histogram of the window around it,
+/// and Viterbi finds the best sequence of keys, every change costing the switch penalty.
+pub fn detect_keys<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                             frequencies: &[T],
+                             tuning: &Tuning,
+                             parameters: &KeyParameters) -> (Option<KeyEstimate>, Vec<KeySegment>) {
+    let chroma = chroma(scalogram, frequencies, tuning, &ChromaParameters::default());
+    let frame_duration = scalogram.frame_duration();
+    (estimate_key(&histogram(&chroma)), key_segments(&chroma, frame_duration, parameters))
+}
+
+fn histogram(chroma: &[[f64; 12]]) -> [f64; 12] {
+    let mut histogram = [0.0; 12];
+    for frame in chroma {
+        histogram.iter_mut().zip(frame).for_each(|(sum, value)| *sum += value);
+    }
+    histogram
+}
+
+fn key_segments(chroma: &[[f64; 12]], frame_duration: f64, parameters: &KeyParameters) -> Vec<KeySegment> {
+    let frames_per_block = ((parameters.block_duration / frame_duration).round() as usize).max(1);
+    let half_window = (parameters.window_duration / frame_duration / 2.0).round() as usize;
+    let blocks = chroma.len().div_ceil(frames_per_block);
+    let keys: Vec<Key> = Key::all().collect();
+    let scores: Vec<Vec<f64>> = (0..blocks)
+        .map(|block| {
+            let center = block * frames_per_block + frames_per_block / 2;
+            let window = histogram(&chroma[center.saturating_sub(half_window)..(center + half_window).min(chroma.len())]);
+            keys.iter().map(|key| key.correlation(&window)).collect()
+        })
+        .collect();
+    let states = viterbi(&scores, parameters.switch_penalty);
+
+    let end = chroma.len() as f64 * frame_duration;
+    let mut segments = Vec::new();
+    let mut first_block = 0;
+    for block in 1..=states.len() {
+        if block < states.len() && states[block] == states[first_block] {
+            continue;
+        }
+        let frames = &chroma[first_block * frames_per_block..(block * frames_per_block).min(chroma.len())];
+        if let Some(estimate) = estimate(keys[states[first_block]], &histogram(frames)) {
+            let start = first_block as f64 * frames_per_block as f64 * frame_duration;
+            let segment_end = (block as f64 * frames_per_block as f64 * frame_duration).min(end);
+            segments.push(KeySegment { start, end: segment_end, estimate });
+        }
+        first_block = block;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::analysis::key::{estimate_key, Key, key_segments, KeyParameters, MAJOR_PROFILE, Mode};
+
+    /// Frames of the notes of a scale, each repeated
+    fn scale_frames(notes: &[usize], repeat: usize) -> Vec<[f64; 12]> {
+        notes.iter()
+            .flat_map(|note| {
+                let mut frame = [0.0; 12];
+                frame[note % 12] = 1.0;
+                vec![frame; repeat]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn profiles_are_their_own_key() {
+        let mut histogram = [0.0; 12];
+        for (pitch_class, weight) in MAJOR_PROFILE.iter().enumerate() {
+            histogram[(pitch_class + 7) % 12] = *weight;
+        }
+        let estimate = estimate_key(&histogram).unwrap();
+        assert_eq!(estimate.key, Key { tonic: 7, mode: Mode::Major });
+        assert!((estimate.correlation - 1.0).abs() < 1e-9);
+        assert!(estimate.confidence > 0.0);
+        assert_eq!(estimate.key.name(), "G major");
+        assert!(estimate_key(&[0.0; 12]).is_none());
+    }
+
+    #[test]
+    fn key_signatures() {
+        let fifths = |key: &str| Key::from_str(key).unwrap().fifths();
+        assert_eq!(fifths("C major"), 0);
+        assert_eq!(fifths("A minor"), 0);
+        assert_eq!(fifths("E major"), 4);
+        assert_eq!(fifths("F# major"), 6);
+        assert_eq!(fifths("Eb major"), -3);
+        assert_eq!(fifths("C minor"), -3);
+        assert_eq!(fifths("D minor"), -1);
+        assert_eq!(fifths("C# minor"), 4);
+        assert_eq!(Key::from_str("D# major").unwrap().name(), "Eb major");
+        assert_eq!(Key::from_str("Gb minor").unwrap().name(), "F# minor");
+        assert_eq!(Key::from_str("H major").err(), Some("Invalid key 'H major'".to_string()));
+        assert!(Key::from_str("C dorian").is_err());
+        assert!(Key::from_str("C").is_err());
+    }
+
+    #[test]
+    fn estimates_keys_of_melodies() {
+        // C major scale with a cadence, A harmonic minor scale with its tonic triad
+        let c_major = [0, 2, 4, 5, 7, 9, 11, 12, 7, 4, 0, 0];
+        let a_minor = [9, 11, 12, 14, 16, 17, 20, 21, 16, 12, 9, 9];
+        let histogram = |notes: &[usize]| {
+            let mut histogram = [0.0; 12];
+            notes.iter().for_each(|note| histogram[note % 12] += 1.0);
+            histogram
+        };
+        assert_eq!(estimate_key(&histogram(&c_major)).unwrap().key, Key { tonic: 0, mode: Mode::Major });
+        assert_eq!(estimate_key(&histogram(&a_minor)).unwrap().key, Key { tonic: 9, mode: Mode::Minor });
+    }
+
+    #[test]
+    fn finds_modulations() {
+        // 20 seconds in C major then 20 in E major, at 10 frames per second
+        let c_major = [0, 2, 4, 5, 7, 9, 11, 12, 7, 4, 0, 0];
+        let e_major = [4, 6, 8, 9, 11, 13, 15, 16, 11, 8, 4, 4];
+        let mut frames = Vec::new();
+        for _ in 0..10 {
+            frames.extend(scale_frames(&c_major, 2));
+        }
+        for _ in 0..10 {
+            frames.extend(scale_frames(&e_major, 2));
+        }
+        frames.extend(vec![[0.0; 12]; 5]);
+
+        let segments = key_segments(&frames, 0.1, &KeyParameters::default());
+
+        let keys: Vec<String> = segments.iter().map(|segment| segment.estimate.key.name()).collect();
+        assert_eq!(keys, vec!["C major", "E major"]);
+        assert!((segments[1].start - 24.0).abs() <= 2.0, "Modulation at {}", segments[1].start);
+        assert!((segments[1].end - 48.5).abs() < 1e-9);
+    }
+}
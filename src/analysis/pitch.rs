@@ -0,0 +1,140 @@
+use num_complex::Complex;
+use crate::analysis::{aligned_magnitudes, is_peak, magnitude_at, parabolic_peak};
+use crate::analysis::transcription::is_harmonic;
+use crate::notes::tuning::Tuning;
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// Peaks weaker than this fraction of the strongest coefficient are not candidate fundamentals
+const PEAK_THRESHOLD: f64 = 0.01;
+/// The n-th harmonic of a candidate fundamental adds its magnitude times HARMONIC_WEIGHT^(n-1) to its salience
+const HARMONIC_WEIGHT: f64 = 0.8;
+/// Frame levels under the strongest one mapped to loudnesses from 1 to 0 in the voicing confidence
+const LOUDNESS_RANGE_DB: f64 = 60.0;
+
+pub struct PitchFrame {
+    /// In seconds
+    pub time: f64,
+    /// Fundamental frequency in Hz, None when the frame is unvoiced
+    pub frequency: Option<f64>,
+    /// Nearest note of the tuning (0 for C0) and the deviation of the frequency from it in cents
+    pub note: Option<(i32, f64)>,
+    /// Voicing confidence from 0 to 1: the share of the peaks explained by the fundamental, times the loudness
+    pub confidence: f64,
+}
+
+pub struct PitchParameters {
+    /// Frames whose confidence is below this are unvoiced
+    pub voicing_threshold: f64,
+    /// Number of partials of a fundamental (including itself) counted in its salience
+    pub harmonics: usize,
+}
+
+impl Default for PitchParameters {
+    fn default() -> Self {
+        PitchParameters {
+            voicing_threshold: 0.5,
+            harmonics: 8,
+        }
+    }
+}
+
+/// Fundamental frequency of every frame of the scalogram of the given frequencies (in ascending order), assuming a
+/// single voice: among the spectral peaks, the one whose harmonics are the strongest, interpolated between the rows
+pub fn track_pitch<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                             frequencies: &[T],
+                             tuning: &Tuning,
+                             parameters: &PitchParameters) -> Vec<PitchFrame> {
+    let magnitudes = aligned_magnitudes(scalogram, frequencies);
+    let frequencies: Vec<f64> = frequencies.iter().map(|frequency| frequency.to_f64().unwrap()).collect();
+    let max = magnitudes.iter().flatten().copied().fold(0.0, f64::max);
+    let frame_duration = scalogram.frame_duration();
+
+    magnitudes.iter().enumerate()
+        .map(|(frame, values)| {
+            let time = frame as f64 * frame_duration;
+            let peaks: Vec<(f64, f64)> = (0..values.len())
+                .filter(|bin| values[*bin] > 0.0 && values[*bin] >= PEAK_THRESHOLD * max && is_peak(values, *bin))
+                .map(|bin| interpolate_peak(values, &frequencies, bin))
+                .collect();
+            let salience = |fundamental: f64| (1..=parameters.harmonics)
+                .map(|partial| HARMONIC_WEIGHT.powi(partial as i32 - 1) * magnitude_at(values, &frequencies, partial as f64 * fundamental))
+                .sum::<f64>();
+            let best = peaks.iter().enumerate()
+                .map(|(index, (frequency, _))| (index, *frequency, salience(*frequency)))
+                .max_by(|a, b| a.2.total_cmp(&b.2));
+            let Some((index, frequency, _)) = best else {
+                return PitchFrame { time, frequency: None, note: None, confidence: 0.0 };
+            };
+
+            let total: f64 = peaks.iter().map(|(_, magnitude)| magnitude).sum();
+            let explained: f64 = peaks.iter().enumerate()
+                .filter(|(other, (peak, _))| *other == index || is_harmonic(*peak, frequency, parameters.harmonics))
+                .map(|(_, (_, magnitude))| magnitude)
+                .sum();
+            let level = peaks.iter().map(|(_, magnitude)| *magnitude).fold(0.0, f64::max) / max;
+            let loudness = (1.0 + 20.0 * level.log10() / LOUDNESS_RANGE_DB).clamp(0.0, 1.0);
+            let confidence = explained / total * loudness;
+            if confidence < parameters.voicing_threshold {
+                return PitchFrame { time, frequency: None, note: None, confidence };
+            }
+            let note = tuning.nearest_note(frequency);
+            let cents = 1200.0 * (frequency / tuning.frequency(note)).log2();
+            PitchFrame { time, frequency: Some(frequency), note: Some((note, cents)), confidence }
+        })
+        .collect()
+}
+
+/// Frequency and magnitude of the top of the parabolic peak, with the frequency interpolated geometrically
+fn interpolate_peak(values: &[f64], frequencies: &[f64], bin: usize) -> (f64, f64) {
+    if bin == 0 || bin + 1 == values.len() {
+        return (frequencies[bin], values[bin]);
+    }
+    let Some((shift, magnitude)) = parabolic_peak(values[bin - 1], values[bin], values[bin + 1]) else {
+        return (frequencies[bin], values[bin]);
+    };
+    let frequency = if shift >= 0.0 {
+        frequencies[bin] * (frequencies[bin + 1] / frequencies[bin]).powf(shift)
+    } else {
+        frequencies[bin] * (frequencies[bin - 1] / frequencies[bin]).powf(-shift)
+    };
+    (frequency, magnitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::pitch::{PitchParameters, track_pitch};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+    use crate::notes::tuning::Tuning;
+
+    #[test]
+    fn tracks_fundamental_between_rows() {
+        // half a second of silence, then a tone between A3 and A#3 whose second harmonic is the strongest
+        let sample_rate = TEST_SAMPLE_RATE;
+        let fundamental = 224.0;
+        let samples: Vec<f32> = (0..2 * sample_rate)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64;
+                if t < 0.5 { return 0.0; }
+                ((TAU * fundamental * t).sin() * 0.6 + (TAU * 2.0 * fundamental * t).sin()
+                    + (TAU * 3.0 * fundamental * t).sin() * 0.4) as f32
+            })
+            .collect();
+        let (scalogram, frequencies) = test_scalogram(samples, 2, 4, 80);
+
+        let track = track_pitch(&scalogram, &frequencies, &Tuning::default(), &PitchParameters::default());
+
+        for frame in &track {
+            if frame.time < 0.4 {
+                assert!(frame.frequency.is_none(), "{} voiced at {:?}", frame.time, frame.frequency);
+            } else if frame.time > 0.7 && frame.time < 1.8 {
+                let frequency = frame.frequency.unwrap_or_else(|| panic!("{} unvoiced, confidence {}", frame.time, frame.confidence));
+                assert!((1200.0 * (frequency / fundamental).log2()).abs() < 10.0, "{} Hz at {}", frequency, frame.time);
+                let (note, cents) = frame.note.unwrap();
+                assert_eq!(note, 45);
+                assert!((cents - 31.2).abs() < 10.0, "{} cents from A3", cents);
+            }
+        }
+    }
+}
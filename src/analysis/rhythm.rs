@@ -0,0 +1,205 @@
+use num_complex::Complex;
+use crate::analysis::{aligned_magnitudes, delays};
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// Magnitudes are compressed as log(1 + COMPRESSION * magnitude / max) before taking their differences, so that
+/// quiet notes count as well as loud ones
+const COMPRESSION: f64 = 1000.0;
+/// An onset is the strongest frame within this many seconds before and after it
+const PEAK_WINDOW: f64 = 0.03;
+/// An onset is stronger than the average over this many seconds before and after it
+const AVERAGE_WINDOW: f64 = 0.1;
+/// ... by this fraction of the strongest frame
+const ONSET_DELTA: f64 = 0.05;
+/// Minimum time in seconds between two onsets
+const MIN_ONSET_INTERVAL: f64 = 0.05;
+const MIN_TEMPO: f64 = 40.0;
+const MAX_TEMPO: f64 = 240.0;
+/// Tempos are weighted by a log-normal prior centered on this tempo, with a standard deviation of an octave
+const PREFERRED_TEMPO: f64 = 120.0;
+/// Cost of beat intervals deviating from the tempo in the beat tracking, per squared log of their ratio
+const TIGHTNESS: f64 = 100.0;
+
+/// Onset strength of every frame of the scalogram of the given frequencies (in ascending order): the spectral flux,
+/// i.e. the average increase of the compressed magnitudes of the rows from the previous frame
+pub fn onset_strength<T: Float>(scalogram: &Scalogram<Complex<T>>, frequencies: &[T]) -> Vec<f64> {
+    let magnitudes = aligned_magnitudes(scalogram, frequencies);
+    let delays = delays(scalogram, frequencies);
+    let max = magnitudes.iter().flatten().copied().fold(0.0, f64::max);
+    if max == 0.0 {
+        return vec![0.0; magnitudes.len()];
+    }
+    let compressed: Vec<Vec<f64>> = magnitudes.iter()
+        .map(|values| values.iter().map(|value| (COMPRESSION * value / max).ln_1p()).collect())
+        .collect();
+    let frames = compressed.len();
+    (0..frames)
+        .map(|frame| {
+            // rows start from zero at their delay rather than at an onset, and their wavelets see the end of the signal
+            // as a click from their delay before it
+            let differences: Vec<f64> = (0..delays.len())
+                .filter(|row| frame > delays[*row] && frame + delays[*row] < frames)
+                .map(|row| (compressed[frame][row] - compressed[frame - 1][row]).max(0.0))
+                .collect();
+            if differences.is_empty() { 0.0 } else { differences.iter().sum::<f64>() / differences.len() as f64 }
+        })
+        .collect()
+}
+
+/// Times in seconds of the peaks of the onset strength that stand out of their surroundings
+pub fn pick_onsets(strength: &[f64], frame_duration: f64) -> Vec<f64> {
+    let max = strength.iter().copied().fold(0.0, f64::max);
+    let peak_window = (PEAK_WINDOW / frame_duration).round() as usize;
+    let average_window = (AVERAGE_WINDOW / frame_duration).round() as usize;
+    let min_interval = (MIN_ONSET_INTERVAL / frame_duration).round() as usize;
+    let around = |frame: usize, window: usize| &strength[frame.saturating_sub(window)..(frame + window + 1).min(strength.len())];
+
+    let mut onsets: Vec<usize> = Vec::new();
+    for (frame, value) in strength.iter().enumerate() {
+        let neighbours = around(frame, average_window);
+        let average = neighbours.iter().sum::<f64>() / neighbours.len() as f64;
+        if *value > 0.0 && *value >= around(frame, peak_window).iter().copied().fold(0.0, f64::max)
+            && *value >= average + ONSET_DELTA * max
+            && onsets.last().is_none_or(|last| frame - last >= min_interval) {
+            onsets.push(frame);
+        }
+    }
+    onsets.iter().map(|frame| *frame as f64 * frame_duration).collect()
+}
+
+/// Tempo in beats per minute whose period maximizes the autocorrelation of the onset strength, weighted by a prior
+/// favouring tempos around 120, None if there are no onsets
+pub fn estimate_tempo(strength: &[f64], frame_duration: f64) -> Option<f64> {
+    let min_lag = ((60.0 / MAX_TEMPO / frame_duration).floor() as usize).max(1);
+    let max_lag = ((60.0 / MIN_TEMPO / frame_duration).ceil() as usize).min(strength.len().saturating_sub(1));
+    if min_lag + 2 > max_lag {
+        return None;
+    }
+    let mean = strength.iter().sum::<f64>() / strength.len() as f64;
+    let centered: Vec<f64> = strength.iter().map(|value| value - mean).collect();
+    let scores: Vec<f64> = (min_lag..=max_lag)
+        .map(|lag| {
+            let autocorrelation: f64 = centered.iter().zip(&centered[lag..]).map(|(a, b)| a * b).sum();
+            let tempo = 60.0 / (lag as f64 * frame_duration);
+            autocorrelation * (-0.5 * (tempo / PREFERRED_TEMPO).log2().powi(2)).exp()
+        })
+        .collect();
+    let best = (0..scores.len()).max_by(|a, b| scores[*a].total_cmp(&scores[*b]))?;
+    if scores[best] <= 0.0 {
+        return None;
+    }
+    // parabolic interpolation of the lag between the neighbouring ones
+    let shift = if best > 0 && best + 1 < scores.len() {
+        let (below, peak, above) = (scores[best - 1], scores[best], scores[best + 1]);
+        let curvature = below - 2.0 * peak + above;
+        if curvature < 0.0 { 0.5 * (below - above) / curvature } else { 0.0 }
+    } else { 0.0 };
+    Some(60.0 / ((min_lag as f64 + best as f64 + shift) * frame_duration))
+}
+
+/// Times in seconds of the beats at the tempo that fall on the strongest onsets, with dynamic programming: the score
+/// of a beat at a frame is its onset strength plus the best score of a previous beat, minus the cost of the interval
+/// from it deviating from the tempo
+pub fn track_beats(strength: &[f64], frame_duration: f64, tempo: f64) -> Vec<f64> {
+    let period = 60.0 / tempo / frame_duration;
+    let mean = strength.iter().sum::<f64>() / strength.len().max(1) as f64;
+    let deviation = (strength.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / strength.len().max(1) as f64).sqrt();
+    if deviation == 0.0 || period < 1.0 {
+        return Vec::new();
+    }
+    let normalized: Vec<f64> = strength.iter().map(|value| value / deviation).collect();
+
+    let (min_interval, max_interval) = ((period / 2.0).round() as usize, (2.0 * period).round() as usize);
+    let mut scores = normalized.clone();
+    let mut previous_beats: Vec<Option<usize>> = vec![None; strength.len()];
+    for frame in min_interval..strength.len() {
+        let best = (frame.saturating_sub(max_interval)..=frame - min_interval)
+            .map(|previous| {
+                let cost = TIGHTNESS * ((frame - previous) as f64 / period).ln().powi(2);
+                (previous, scores[previous] - cost)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((previous, score)) = best {
+            if score > 0.0 {
+                scores[frame] += score;
+                previous_beats[frame] = Some(previous);
+            }
+        }
+    }
+
+    // the last beat is the best scoring frame of the last period
+    let last_period = strength.len().saturating_sub(period.ceil() as usize);
+    let mut beat = (last_period..strength.len()).max_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+    let mut beats = Vec::new();
+    while let Some(frame) = beat {
+        beats.push(frame as f64 * frame_duration);
+        beat = previous_beats[frame];
+    }
+    beats.reverse();
+    beats
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::rhythm::{estimate_tempo, onset_strength, pick_onsets, track_beats};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+
+    /// Onset strength at 100 frames per second with a pulse every period frames from the offset, and weaker
+    /// off-beat pulses
+    fn pulses(frames: usize, period: usize, offset: usize) -> Vec<f64> {
+        (0..frames)
+            .map(|frame| if frame >= offset && (frame - offset).is_multiple_of(period) {
+                1.0
+            } else if frame >= offset && (frame - offset) % period == period / 2 {
+                0.3
+            } else {
+                0.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimates_tempo() {
+        let tempo = estimate_tempo(&pulses(1000, 50, 7), 0.01).unwrap();
+        assert!((tempo - 120.0).abs() < 1.0, "{} BPM", tempo);
+        let tempo = estimate_tempo(&pulses(1000, 70, 0), 0.01).unwrap();
+        assert!((tempo - 85.7).abs() < 1.0, "{} BPM", tempo);
+        assert!(estimate_tempo(&[0.0; 1000], 0.01).is_none());
+    }
+
+    #[test]
+    fn tracks_beats_on_the_pulses() {
+        let beats = track_beats(&pulses(1000, 50, 7), 0.01, 120.0);
+        let expected: Vec<f64> = (0..20).map(|beat| 0.07 + beat as f64 * 0.5).collect();
+        assert_eq!(beats.len(), expected.len(), "{:?}", beats);
+        for (beat, expected) in beats.iter().zip(expected) {
+            assert!((beat - expected).abs() < 1e-9, "Beat at {} instead of {}", beat, expected);
+        }
+    }
+
+    #[test]
+    fn detects_onsets_of_notes() {
+        // notes struck at 0.2, 0.7, 1.0 and 1.6 seconds, decaying
+        let sample_rate = TEST_SAMPLE_RATE;
+        let strikes = [(0.2, 220.0), (0.7, 330.0), (1.0, 440.0), (1.6, 262.0)];
+        let samples: Vec<f32> = (0..2 * sample_rate)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64;
+                strikes.iter()
+                    .filter(|(onset, _)| t >= *onset)
+                    .map(|(onset, frequency)| (TAU * frequency * t).sin() * (-(t - onset) * 8.0).exp())
+                    .sum::<f64>() as f32
+            })
+            .collect();
+        let (scalogram, frequencies) = test_scalogram(samples, 2, 4, 40);
+
+        let onsets = pick_onsets(&onset_strength(&scalogram, &frequencies), 40.0 / sample_rate as f64);
+
+        assert_eq!(onsets.len(), strikes.len(), "{:?}", onsets);
+        for (onset, (expected, _)) in onsets.iter().zip(strikes) {
+            assert!((onset - expected).abs() < 0.04, "Onset at {} instead of {}", onset, expected);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use num_complex::Complex;
+use rayon::prelude::*;
+use crate::analysis::{aligned_magnitudes, delays};
+use crate::signals::Scalogram;
+use crate::utils::math::{cast, Float};
+
+pub struct SeparationParameters {
+    /// Duration in seconds of the median filter along the rows, longer than percussive sounds
+    pub harmonic_window: f64,
+    /// Width in octaves of the median filter across the rows, wider than the peaks of harmonic sounds
+    pub percussive_window: f64,
+    /// Exponent of the soft masks, higher values separating more sharply
+    pub power: f64,
+}
+
+impl Default for SeparationParameters {
+    fn default() -> Self {
+        SeparationParameters {
+            harmonic_window: 0.4,
+            percussive_window: 1.0,
+            power: 2.0,
+        }
+    }
+}
+
+/// Harmonic and percussive parts of the scalogram of the given frequencies (in ascending order), by median filtering
+/// (Fitzgerald, 2010): harmonic sounds are steady along the rows and percussive ones along the frames, so the median
+/// of the magnitudes along a row keeps the first and the median across the rows keeps the second. The coefficients are
+/// split with soft masks H^p / (H^p + P^p) and P^p / (H^p + P^p), so the two parts add up to the scalogram.
+pub fn separate<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                          frequencies: &[T],
+                          parameters: &SeparationParameters) -> (Scalogram<Complex<T>>, Scalogram<Complex<T>>) {
+    let magnitudes = aligned_magnitudes(scalogram, frequencies);
+    let delays = delays(scalogram, frequencies);
+    let frequencies: Vec<f64> = frequencies.iter().map(|frequency| frequency.to_f64().unwrap()).collect();
+    let frames = magnitudes.len();
+    let frame_duration = scalogram.frame_duration();
+    // a window wider than the scalogram covers all of it
+    let half_window = ((parameters.harmonic_window / frame_duration / 2.0).round() as usize).min(frames);
+
+    // bins × frames, filtered along the frames
+    let harmonic: Vec<Vec<f64>> = (0..frequencies.len()).into_par_iter()
+        .map(|bin| {
+            let row: Vec<f64> = magnitudes.iter().map(|values| values[bin]).collect();
+            let mut window = Vec::with_capacity(2 * half_window + 1);
+            (0..frames)
+                .map(|frame| {
+                    window.clear();
+                    window.extend_from_slice(&row[frame.saturating_sub(half_window)..(frame + half_window + 1).min(frames)]);
+                    median(&mut window)
+                })
+                .collect()
+        })
+        .collect();
+    // frames × bins, filtered across the bins within half the window below and above each one
+    let ratio = (parameters.percussive_window / 2.0).exp2();
+    let ranges: Vec<(usize, usize)> = frequencies.iter()
+        .map(|frequency| (frequencies.partition_point(|other| *other < frequency / ratio),
+                          frequencies.partition_point(|other| *other <= frequency * ratio)))
+        .collect();
+    let percussive: Vec<Vec<f64>> = magnitudes.par_iter()
+        .map(|values| ranges.iter().map(|(low, high)| median(&mut values[*low..*high].to_vec())).collect())
+        .collect();
+
+    let power = parameters.power;
+    let mut harmonic_part = Vec::with_capacity(scalogram.coefficients.len());
+    let mut percussive_part = Vec::with_capacity(scalogram.coefficients.len());
+    for (row, coefficients) in scalogram.rows().enumerate() {
+        let bin = frequencies.len() - 1 - row;
+        for (frame, coefficient) in coefficients.iter().enumerate() {
+            // the raw frame describes the signal at the aligned frame its delay later
+            let aligned = (frame + delays[bin]).min(frames - 1);
+            let (h, p) = (harmonic[bin][aligned].powf(power), percussive[aligned][bin].powf(power));
+            let mask: T = cast(if h + p == 0.0 { 0.5 } else { h / (h + p) });
+            harmonic_part.push(coefficient * mask);
+            percussive_part.push(coefficient * (T::one() - mask));
+        }
+    }
+    let part = |coefficients| Scalogram { sample_rate: scalogram.sample_rate, hop_size: scalogram.hop_size, frames: scalogram.frames,
+                                           multirate: scalogram.multirate, coefficients };
+    (part(harmonic_part), part(percussive_part))
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    let middle = values.len() / 2;
+    *values.select_nth_unstable_by(middle, f64::total_cmp).1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::analysis::aligned_magnitudes;
+    use crate::analysis::separation::{separate, SeparationParameters};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+
+    #[test]
+    fn separates_a_tone_from_clicks() {
+        // a steady A4 with a click every quarter second
+        let sample_rate = TEST_SAMPLE_RATE;
+        let samples: Vec<f32> = (0..2 * sample_rate)
+            .map(|n| {
+                let click = if n % (sample_rate / 4) == 0 { 20.0 } else { 0.0 };
+                ((TAU * 440.0 * n as f64 / sample_rate as f64).sin() + click) as f32
+            })
+            .collect();
+        let (scalogram, frequencies) = test_scalogram(samples, 2, 5, 40);
+
+        let (harmonic, percussive) = separate(&scalogram, &frequencies, &SeparationParameters::default());
+
+        for ((h, p), coefficient) in harmonic.coefficients.iter().zip(&percussive.coefficients).zip(&scalogram.coefficients) {
+            assert!((h + p - coefficient).norm() <= 1e-6 * coefficient.norm().max(1.0));
+        }
+        let (harmonic, percussive) = (aligned_magnitudes(&harmonic, &frequencies), aligned_magnitudes(&percussive, &frequencies));
+        let (a4, c6) = (33, 51);
+        // between two clicks the tone is harmonic, on a click far from the tone the energy is percussive
+        let (between, click) = (((1.0 + 0.125) * 200.0) as usize, 200);
+        assert!(harmonic[between][a4] > 4.0 * percussive[between][a4], "{} {}", harmonic[between][a4], percussive[between][a4]);
+        assert!(percussive[click][c6] > 4.0 * harmonic[click][c6], "{} {}", percussive[click][c6], harmonic[click][c6]);
+
+        // a window longer than the recording takes the median of whole rows
+        let parameters = SeparationParameters { harmonic_window: 1e30, ..SeparationParameters::default() };
+        let (harmonic, _) = separate(&scalogram, &frequencies, &parameters);
+        assert_eq!(harmonic.coefficients.len(), scalogram.coefficients.len());
+    }
+}
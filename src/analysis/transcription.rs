@@ -0,0 +1,238 @@
+use num_complex::Complex;
+use crate::analysis::{aligned_magnitudes, is_peak};
+use crate::notes::tuning::Tuning;
+use crate::signals::Scalogram;
+use crate::utils::math::Float;
+
+/// MIDI note number of C0, the note 0 of the tunings
+pub const MIDI_C0: i32 = 12;
+/// A note stops when its pitch has no peak for this long, in seconds
+const MAX_GAP: f64 = 0.03;
+/// A sounding note is struck again when its salience jumps by this factor from one peak to the next
+const ONSET_RATIO: f64 = 2.0;
+/// Dynamic range mapped to velocities 1 to 127
+const VELOCITY_RANGE_DB: f64 = 60.0;
+/// Maximum distance in cents between a peak and a harmonic of a fundamental for the peak to be attributed to it
+const HARMONIC_TOLERANCE_CENTS: f64 = 35.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    /// MIDI note number, 60 for C4
+    pub pitch: u8,
+    /// Start time in seconds
+    pub onset: f64,
+    /// Duration in seconds
+    pub duration: f64,
+    /// MIDI velocity, from 1 to 127
+    pub velocity: u8,
+}
+
+pub struct TranscriptionParameters {
+    /// Peaks weaker than this fraction of the strongest coefficient are ignored
+    pub threshold: f64,
+    /// Notes shorter than this in seconds are dropped
+    pub min_duration: f64,
+    /// Number of partials of a fundamental (including itself) whose peaks are attributed to it
+    pub harmonics: usize,
+}
+
+impl Default for TranscriptionParameters {
+    fn default() -> Self {
+        TranscriptionParameters {
+            threshold: 0.1,
+            min_duration: 0.05,
+            harmonics: 8,
+        }
+    }
+}
+
+/// Notes played in the scalogram of the given frequencies (in ascending order), sorted by onset: peaks are picked in
+/// every frame, grouped with the harmonics of lower peaks, then tracked over time per pitch of the tuning
+pub fn transcribe<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                            frequencies: &[T],
+                            tuning: &Tuning,
+                            parameters: &TranscriptionParameters) -> Vec<Note> {
+    let salience = pitch_salience(scalogram, frequencies, tuning, parameters);
+    let max = salience.iter().flatten().copied().fold(0.0, f64::max);
+    if max == 0.0 {
+        return Vec::new();
+    }
+
+    let frame_duration = scalogram.frame_duration();
+    let max_gap = (MAX_GAP / frame_duration).ceil() as usize;
+    let min_frames = (parameters.min_duration / frame_duration).ceil() as usize;
+    let mut notes = Vec::new();
+    for pitch in 0..128 {
+        let mut tracker = NoteTracker { pitch: pitch as u8, frame_duration, min_frames, max, active: None };
+        for (frame, frame_salience) in salience.iter().enumerate() {
+            let value = frame_salience[pitch];
+            match &mut tracker.active {
+                Some(active) if value > 0.0 => {
+                    if value > ONSET_RATIO * active.recent && frame - active.start >= min_frames {
+                        tracker.close(&mut notes);
+                        tracker.start(frame, value);
+                    } else {
+                        active.last = frame;
+                        active.recent = value;
+                        active.peak = active.peak.max(value);
+                    }
+                }
+                Some(active) if frame - active.last > max_gap => tracker.close(&mut notes),
+                None if value > 0.0 => tracker.start(frame, value),
+                _ => {}
+            }
+        }
+        tracker.close(&mut notes);
+    }
+    let mut notes = remove_harmonic_notes(notes, parameters.harmonics);
+    notes.sort_by(|a, b| a.onset.total_cmp(&b.onset).then(a.pitch.cmp(&b.pitch)));
+    notes
+}
+
+/// Per frame, the salience of the 128 MIDI pitches: the magnitude of the peak at the pitch plus the ones of its
+/// harmonics, 0 if there's no peak
+fn pitch_salience<T: Float>(scalogram: &Scalogram<Complex<T>>,
+                            frequencies: &[T],
+                            tuning: &Tuning,
+                            parameters: &TranscriptionParameters) -> Vec<[f64; 128]> {
+    let magnitudes = aligned_magnitudes(scalogram, frequencies);
+    let max = magnitudes.iter().flatten().copied().fold(0.0, f64::max);
+
+    magnitudes.iter()
+        .map(|frame| {
+            let mut fundamentals: Vec<(f64, f64)> = Vec::new();
+            for (bin, frequency) in frequencies.iter().enumerate() {
+                let value = frame[bin];
+                if !is_peak(frame, bin) || value < parameters.threshold * max {
+                    continue;
+                }
+                let frequency = frequency.to_f64().unwrap();
+                let fundamental = fundamentals.iter_mut()
+                    .find(|(fundamental, strength)| value < *strength && is_harmonic(frequency, *fundamental, parameters.harmonics));
+                match fundamental {
+                    Some((_, strength)) => *strength += value,
+                    None => fundamentals.push((frequency, value)),
+                }
+            }
+
+            let mut salience = [0.0; 128];
+            for (frequency, strength) in fundamentals {
+                let pitch = tuning.nearest_note(frequency) + MIDI_C0;
+                if (0..128).contains(&pitch) {
+                    salience[pitch as usize] = f64::max(salience[pitch as usize], strength);
+                }
+            }
+            salience
+        })
+        .collect()
+}
+
+/// Drops the notes that are a harmonic of a longer and louder note sounding at the same time. They appear when the
+/// shorter wavelets of the harmonics respond to an onset before the fundamental is a peak.
+fn remove_harmonic_notes(notes: Vec<Note>, harmonics: usize) -> Vec<Note> {
+    notes.iter()
+        .filter(|note| !notes.iter().any(|other| {
+            let ratio = ((note.pitch as f64 - other.pitch as f64) / 12.0).exp2();
+            other.duration > note.duration && other.velocity >= note.velocity && is_harmonic(ratio, 1.0, harmonics)
+                && other.onset < note.onset + note.duration && note.onset < other.onset + other.duration
+        }))
+        .cloned()
+        .collect()
+}
+
+/// Whether frequency is close to the 2nd to harmonics-th partial of fundamental
+pub(crate) fn is_harmonic(frequency: f64, fundamental: f64, harmonics: usize) -> bool {
+    let ratio = frequency / fundamental;
+    let partial = ratio.round();
+    partial >= 2.0 && partial <= harmonics as f64 && (1200.0 * (ratio / partial).log2()).abs() < HARMONIC_TOLERANCE_CENTS
+}
+
+struct ActiveNote {
+    start: usize,
+    last: usize,
+    /// Salience of the latest peak, to detect the note being struck again
+    recent: f64,
+    peak: f64,
+}
+
+/// Note being tracked for one pitch, turned into a Note when it ends
+struct NoteTracker {
+    pitch: u8,
+    frame_duration: f64,
+    min_frames: usize,
+    /// Highest salience of all pitches, the reference of the velocities
+    max: f64,
+    active: Option<ActiveNote>,
+}
+
+impl NoteTracker {
+    fn start(&mut self, frame: usize, salience: f64) {
+        self.active = Some(ActiveNote { start: frame, last: frame, recent: salience, peak: salience });
+    }
+
+    fn close(&mut self, notes: &mut Vec<Note>) {
+        let Some(active) = self.active.take() else { return };
+        let frames = active.last + 1 - active.start;
+        if frames < self.min_frames {
+            return;
+        }
+        let decibels = 20.0 * (active.peak / self.max).log10();
+        notes.push(Note {
+            pitch: self.pitch,
+            onset: active.start as f64 * self.frame_duration,
+            duration: frames as f64 * self.frame_duration,
+            velocity: (127.0 * (1.0 + decibels / VELOCITY_RANGE_DB)).round().clamp(1.0, 127.0) as u8,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+    use crate::analysis::transcription::{is_harmonic, transcribe, TranscriptionParameters};
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+    use crate::notes::tuning::Tuning;
+
+    /// Each note as (midi pitch, onset, duration), with a decaying envelope and 3 harmonics
+    fn notes_samples(seconds: f32, notes: &[(i32, f32, f32)]) -> Vec<f32> {
+        let sample_rate = TEST_SAMPLE_RATE;
+        (0..(seconds * sample_rate as f32) as usize)
+            .map(|n| {
+                let t = n as f32 / sample_rate as f32;
+                notes.iter()
+                    .filter(|(_, onset, duration)| t >= *onset && t < onset + duration)
+                    .map(|(pitch, onset, _)| {
+                        let frequency = 440.0 * ((*pitch - 69) as f32 / 12.0).exp2();
+                        let envelope = (-(t - onset) * 2.0).exp();
+                        (1..=3).map(|partial| (TAU * frequency * partial as f32 * t).sin() / partial as f32).sum::<f32>() * envelope
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn harmonics() {
+        assert!(is_harmonic(440.0, 220.0, 8));
+        assert!(is_harmonic(659.3, 220.0, 8));
+        assert!(!is_harmonic(220.0, 220.0, 8));
+        assert!(!is_harmonic(523.3, 220.0, 8));
+        assert!(!is_harmonic(2200.0, 220.0, 8));
+    }
+
+    #[test]
+    fn transcribes_melody_and_chord() {
+        let played = [(57, 0.1, 0.5), (60, 0.7, 0.5), (57, 1.3, 0.7), (64, 1.3, 0.7)];
+        let (scalogram, frequencies) = test_scalogram(notes_samples(2.2, &played), 2, 4, 40);
+
+        let notes = transcribe(&scalogram, &frequencies, &Tuning::default(), &TranscriptionParameters::default());
+
+        let pitches: Vec<u8> = notes.iter().map(|note| note.pitch).collect();
+        assert_eq!(pitches, vec![57, 60, 57, 64], "{:?}", notes);
+        for (note, (_, onset, duration)) in notes.iter().zip(played) {
+            assert!((note.onset - onset as f64).abs() < 0.08, "{:?} expected at {}", note, onset);
+            assert!((note.duration - duration as f64).abs() < 0.15, "{:?} expected to last {}", note, duration);
+            assert!(note.velocity > 64);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use num_complex::Complex;
+use crate::analysis::{is_peak, parabolic_peak};
+use crate::notes::A4;
+use crate::signals::{Scalogram, SignalSample};
+use crate::signals::transform::{TransformParameters, WaveletSource, wavelet_transform};
+use crate::utils::math::{cast, Float};
+
+/// Analysis grid resolution, peaks are then interpolated between the bins
+const BINS_PER_SEMITONE: i32 = 5;
+/// Analyzed range in semitones around A4, from A1 to A7
+const SEMITONES_AROUND_A4: i32 = 36;
+/// Peaks below this fraction of the strongest coefficient are too close to the noise to place the notes
+const PEAK_THRESHOLD: f64 = 0.05;
+const FRAMES_PER_SECOND: u32 = 50;
+
+pub struct TuningEstimate {
+    /// Offset from the reference pitch, between -50 and 50 cents
+    pub cents: f64,
+    /// Between 0 (peaks spread evenly around the notes) and 1 (all peaks at exactly the same offset)
+    pub confidence: f64,
+    pub peaks: usize,
+}
+
+/// Estimates how far the notes of the signal are from the equal-tempered notes of the reference A4, by transforming
+/// it on a grid finer than a semitone and averaging the offsets of the spectral peaks. None when the sample rate is too
+/// low for the grid to reach A1
+pub fn estimate_tuning<T: Float>(signal: &SignalSample<T>, reference: f64) -> Option<TuningEstimate> {
+    let frequencies: Vec<T> = (-SEMITONES_AROUND_A4 * BINS_PER_SEMITONE..=SEMITONES_AROUND_A4 * BINS_PER_SEMITONE)
+        .map(|bin| reference * (bin as f64 / (12 * BINS_PER_SEMITONE) as f64).exp2())
+        .take_while(|frequency| *frequency < signal.sample_rate as f64 / 4.0)
+        .map(cast)
+        .collect();
+    if frequencies.is_empty() {
+        return None;
+    }
+    let scalogram = wavelet_transform(signal, &WaveletSource::Spectral, &frequencies, &TransformParameters {
+        multirate: true,
+        hop_size: (signal.sample_rate / FRAMES_PER_SECOND).max(1) as usize,
+        analytic: true,
+    });
+    Some(estimate_from_scalogram(&scalogram, cast(frequencies[0]), reference))
+}
+
+/// Same as estimate_tuning, for a scalogram of frequencies BINS_PER_SEMITONE per semitone from lowest_frequency
+fn estimate_from_scalogram<T: Float>(scalogram: &Scalogram<Complex<T>>, lowest_frequency: f64, reference: f64) -> TuningEstimate {
+    let rows = scalogram.rows().len();
+    let max = scalogram.coefficients.iter().map(|c| c.norm()).fold(T::zero(), T::max).to_f64().unwrap();
+
+    let mut offsets = Vec::new();
+    for frame in 0..scalogram.frames {
+        let values: Vec<f64> = (0..rows).map(|bin| scalogram.row(rows - 1 - bin)[frame].norm().to_f64().unwrap()).collect();
+        for bin in 1..rows.saturating_sub(1) {
+            let peak = values[bin];
+            if peak < PEAK_THRESHOLD * max || !is_peak(&values, bin) {
+                continue;
+            }
+            let Some((shift, _)) = parabolic_peak(values[bin - 1], peak, values[bin + 1]) else { continue };
+            let frequency = lowest_frequency * ((bin as f64 + shift) / (12 * BINS_PER_SEMITONE) as f64).exp2();
+            offsets.push((cents_from_note(frequency, reference), peak));
+        }
+    }
+    let (cents, confidence) = circular_mean(&offsets);
+    TuningEstimate { cents, confidence, peaks: offsets.len() }
+}
+
+/// Offset in cents of the frequency from the closest equal-tempered note of the reference, between -50 and 50
+fn cents_from_note(frequency: f64, reference: f64) -> f64 {
+    let semitones = 12.0 * (frequency / reference).log2() + A4 as f64;
+    100.0 * (semitones - semitones.round())
+}
+
+/// Weighted mean of (cents, weight) offsets on the circle of period 100 cents, so that +49 and -49 average to 50
+/// rather than 0, and the length of the mean vector
+fn circular_mean(offsets: &[(f64, f64)]) -> (f64, f64) {
+    let (mut x, mut y, mut total) = (0.0, 0.0, 0.0);
+    for (cents, weight) in offsets {
+        let angle = std::f64::consts::TAU * cents / 100.0;
+        x += weight * angle.cos();
+        y += weight * angle.sin();
+        total += weight;
+    }
+    if total == 0.0 {
+        return (0.0, 0.0);
+    }
+    (y.atan2(x) * 100.0 / std::f64::consts::TAU, (x * x + y * y).sqrt() / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use num_complex::Complex;
+    use crate::analysis::tuning::{cents_from_note, circular_mean, estimate_from_scalogram, estimate_tuning};
+    use crate::signals::{Scalogram, SignalSample};
+
+    /// A few seconds of notes of an equal-tempered scale with the given A4, with harmonics
+    fn tuned_signal(a4: f64) -> SignalSample<f32> {
+        let sample_rate = 8000;
+        let notes = [-9.0, -5.0, -2.0, 0.0, 3.0, 7.0];
+        SignalSample {
+            sample_rate,
+            samples: (0..3 * sample_rate)
+                .map(|n| {
+                    let t = n as f64 / sample_rate as f64;
+                    let note = notes[(t * 2.0) as usize];
+                    let frequency = a4 * (note / 12.0f64).exp2();
+                    ((TAU * frequency * t).sin() + 0.5 * (TAU * 2.0 * frequency * t).sin()) as f32
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn offsets_from_notes() {
+        assert!((cents_from_note(440.0, 440.0)).abs() < 1e-9);
+        assert!((cents_from_note(446.0, 440.0) - 23.44).abs() < 0.01);
+        assert!((cents_from_note(261.63 * 0.99, 440.0) + 17.4).abs() < 0.1);
+    }
+
+    #[test]
+    fn circular_mean_wraps_around() {
+        let (cents, confidence) = circular_mean(&[(49.0, 1.0), (-49.0, 1.0)]);
+        assert!((cents.abs() - 50.0).abs() < 1e-9);
+        assert!(confidence > 0.99);
+
+        let (cents, confidence) = circular_mean(&[(10.0, 3.0), (20.0, 1.0)]);
+        assert!((cents - 12.5).abs() < 0.2);
+        assert!(confidence > 0.9);
+    }
+
+    #[test]
+    fn estimates_detuned_recordings() {
+        for (a4, expected_cents) in [(440.0, 0.0), (446.0, 23.44), (432.0, -31.77)] {
+            let estimate = estimate_tuning(&tuned_signal(a4), 440.0).unwrap();
+            assert!((estimate.cents - expected_cents).abs() < 2.0, "Estimated {} cents for A4 = {}", estimate.cents, a4);
+            assert!(estimate.confidence > 0.8, "Confidence {} for A4 = {}", estimate.confidence, a4);
+            assert!(estimate.peaks > 0);
+        }
+    }
+
+    #[test]
+    fn low_sample_rates_and_silent_neighbours() {
+        assert!(estimate_tuning(&SignalSample { sample_rate: 200, samples: vec![0.0f32; 400] }, 440.0).is_none());
+
+        // a single peak between a silent bin and a quieter one, rows from the highest frequency
+        let scalogram = Scalogram {
+            sample_rate: 8000,
+            hop_size: 80,
+            frames: 1,
+            multirate: false,
+            coefficients: vec![Complex::new(0.5f32, 0.0), Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)],
+        };
+        let estimate = estimate_from_scalogram(&scalogram, 55.0, 440.0);
+        assert_eq!(estimate.peaks, 0);
+        assert!(estimate.cents.is_finite());
+    }
+}
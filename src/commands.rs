@@ -0,0 +1,123 @@
+use std::str::FromStr;
+use std::thread::available_parallelism;
+use num_complex::Complex;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use subtune::notes::grid::{FrequencyGrid, GridType, validate_frequencies};
+use subtune::notes::tuning::{Tuning, TuningSystem};
+use subtune::signals::{Scalogram, SignalSample};
+use subtune::signals::transform::{FftPlanners, TransformParameters, WaveletSource, wavelet_transform_with};
+use subtune::signals::wavelets;
+use subtune::signals::wavelets::MORLET_HALF_LENGTH;
+use subtune::utils::cache;
+use subtune::utils::cache::CacheKey;
+use subtune::utils::decode_audio;
+use subtune::utils::math::{cast, Float};
+use crate::Cli;
+
+pub mod align;
+pub mod batch;
+pub mod diff;
+pub mod matching;
+pub mod run;
+
+pub fn thread_pool(cli: &Cli) -> ThreadPool {
+    let threads = cli.threads
+        .map(|threads| threads as usize)
+        .unwrap_or_else(|| available_parallelism().map(|cores| cores.get()).unwrap_or(1));
+    ThreadPoolBuilder::new().num_threads(threads).build().unwrap()
+}
+
+pub fn tuning_from(cli: &Cli, tuning: &str) -> Result<Tuning, String> {
+    Tuning::new(cli.reference_pitch.unwrap_or(440.0),
+                TuningSystem::from_str(tuning)?,
+                cli.tonic.as_deref().unwrap_or("C"),
+                cli.cents.as_deref())
+}
+
+/// Frequency grid of the arguments and its frequencies
+pub fn frequencies<T: Float>(cli: &Cli, grid_type: GridType, tuning: &Tuning) -> Result<(FrequencyGrid, Vec<T>), String> {
+    let grid = frequency_grid(cli, grid_type, tuning)?;
+    let frequencies = grid.frequencies::<T>()?;
+    Ok((grid, frequencies))
+}
+
+/// Transform of the file, loaded from the cache when there's one, else computed from the signal (decoded from the
+/// file unless it's given, and resampled first to sample_rate if given) and cached
+pub fn try_transform<T: Float>(cli: &Cli, file: &str, frequencies: &[T], signal: Option<SignalSample<T>>, sample_rate: Option<u32>,
+                           pool: &ThreadPool, planners: &mut FftPlanners<T>) -> Result<Scalogram<Complex<T>>, String> {
+    let key = CacheKey {
+        frequencies,
+        hop_size: cli.hop_size.map(|hop_size| hop_size as usize),
+        pixels_per_second: cli.pixels_per_second.unwrap_or(32),
+        sample_rate,
+        multirate: cli.multirate,
+        analytic: true,
+        spectral: cli.spectral_wavelets,
+    };
+    let cache_file = match &cli.cache_dir {
+        Some(cache_dir) => Some(cache::cache_path(cache_dir, file, &key)?),
+        None => None,
+    };
+
+    if let Some(transform) = cache_file.as_ref().and_then(|cache_file| cache::load(cache_file, &key)) {
+        println!("Loaded cached transform of {}, for {} frequencies", file, frequencies.len());
+        return Ok(transform);
+    }
+    let mut signal = match signal {
+        Some(signal) => signal,
+        None => decode_audio::<T>(file)?,
+    };
+    if let Some(sample_rate) = sample_rate.filter(|sample_rate| *sample_rate != signal.sample_rate) {
+        println!("Resampling {} from {} Hz to {} Hz", file, signal.sample_rate, sample_rate);
+        signal = pool.install(|| signal.resample(sample_rate));
+    }
+    let skipped = validate_frequencies(frequencies, signal.sample_rate, signal.samples.len())?;
+    if skipped > 0 {
+        eprintln!("Leaving the rows up to {:.1} Hz empty, their wavelets are longer than the input, raise the lowest frequency \
+                   with --start-octave or --min-frequency", frequencies[skipped - 1].to_f64().unwrap());
+    }
+    println!("Transforming {} samples of {}, for {} frequencies", signal.samples.len(), file, frequencies.len());
+
+    let hop_size = cli.hop_size.unwrap_or((signal.sample_rate / cli.pixels_per_second.unwrap_or(32) / 8).max(1)) as usize;
+    let sampled_morlet = |frequency, sample_rate| {
+        let wavelet = wavelets::morlet(frequency);
+        SignalSample::from_wavelet(cast::<T>(2.0 * MORLET_HALF_LENGTH) / frequency, sample_rate, &wavelet)
+    };
+    let wavelets = if cli.spectral_wavelets { WaveletSource::Spectral } else { WaveletSource::Sampled(&sampled_morlet) };
+    let mut transform = pool.install(|| wavelet_transform_with(&signal, &wavelets, &frequencies[skipped..], &TransformParameters {
+        multirate: cli.multirate,
+        hop_size,
+        analytic: true,
+    }, planners));
+    // the rows of the lowest frequencies are the last ones
+    transform.coefficients.resize(frequencies.len() * transform.frames, Complex::new(T::zero(), T::zero()));
+
+    if let Some(cache_file) = cache_file {
+        if let Err(e) = cache::store(&cache_file, &transform) {
+            eprintln!("Could not cache the transform: {}", e);
+        }
+    }
+    Ok(transform)
+}
+
+fn frequency_grid(cli: &Cli, grid_type: GridType, tuning: &Tuning) -> Result<FrequencyGrid, String> {
+    let min = cli.min_frequency.unwrap_or(32.7);
+    let max = cli.max_frequency.unwrap_or(8000.0);
+    let bins = cli.bins.unwrap_or(120);
+    Ok(match grid_type {
+        GridType::Notes => FrequencyGrid::Notes {
+            start_octave: cli.start_octave.unwrap_or(1),
+            octaves: cli.num_octaves.unwrap_or(9),
+            frequencies_per_note: cli.frequencies_per_note.unwrap_or(1),
+            tuning: tuning.clone(),
+        },
+        GridType::Linear => FrequencyGrid::Linear { min, max, step: cli.frequency_step.unwrap_or(10.0) },
+        GridType::Mel => FrequencyGrid::Mel { min, max, bins },
+        GridType::Bark => FrequencyGrid::Bark { min, max, bins },
+        GridType::Log => FrequencyGrid::Log { min, max, bins },
+        GridType::File => {
+            let file = cli.frequency_file.as_ref().ok_or("The file grid needs a --frequency-file")?;
+            FrequencyGrid::from_file(file)?
+        }
+    })
+}
@@ -0,0 +1,68 @@
+use std::str::FromStr;
+use image::ImageFormat;
+use subtune::analysis::alignment::{align, alignment_features, AlignmentFeatures, AlignmentParameters};
+use subtune::notes::grid::GridType;
+use subtune::notes::tuning::Tuning;
+use subtune::signals::transform::FftPlanners;
+use subtune::utils::argument_validation::valid_output_extension;
+use subtune::utils::export::{TableFormat, Value, write_table};
+use subtune::utils::math::Float;
+use subtune::utils::visualization::{ColorScheme, output_matrix_image};
+use crate::{Cli, Command};
+use crate::commands::{frequencies, thread_pool, try_transform};
+
+/// Aligns the performance to the reference, both transformed on the same frequencies with the float type T
+pub fn try_align<T: Float>(cli: &Cli, color_scheme: ColorScheme, grid_type: GridType, tuning: Tuning) -> Result<(), String> {
+    let Some(Command::Align { reference, performance, path, cost_image, .. }) = &cli.command else { unreachable!() };
+    let parameters = alignment_parameters(cli)?;
+    let pool = thread_pool(cli);
+    let (_, frequencies) = frequencies::<T>(cli, grid_type, &tuning)?;
+    let mut planners = FftPlanners::new();
+    let mut features = |file| {
+        let transform = try_transform(cli, file, &frequencies, None, None, &pool, &mut planners)?;
+        Ok::<_, String>(pool.install(|| alignment_features(&transform, &frequencies, &tuning, &parameters)))
+    };
+    let (reference_features, performance_features) = (features(reference)?, features(performance)?);
+    let alignment = pool.install(|| align(&reference_features, &performance_features, parameters.block_duration));
+    println!("Aligned {} blocks of {} to {} blocks of {} (average cost {:.3})",
+             performance_features.len(), performance, reference_features.len(), reference, alignment.average_cost);
+
+    if let Some(path_file) = path {
+        let rows: Vec<Vec<Value>> = alignment.path.iter()
+            .map(|(i, j)| vec![Value::Number(*i as f64 * alignment.block_duration), Value::Number(*j as f64 * alignment.block_duration)])
+            .collect();
+        write_table(path_file, &["reference_time", "performance_time"], &rows)?;
+        println!("Exported {} steps of the warping path to {}", rows.len(), path_file);
+    }
+    if let Some(image_file) = cost_image {
+        let pixels_per_cell = ((cli.pixels_per_second.unwrap_or(32) as f64 * alignment.block_duration).round() as usize).max(1);
+        output_matrix_image(&alignment.cost, &alignment.path, pixels_per_cell, color_scheme, image_file, ImageFormat::Png)?;
+        println!("Saved the cost matrix to {}", image_file);
+    }
+    Ok(())
+}
+
+/// Checks the outputs and parameters of the align command before transforming
+pub fn validate_alignment_outputs(cli: &Cli) -> Result<(), String> {
+    let Some(Command::Align { path, cost_image, .. }) = &cli.command else { return Ok(()) };
+    if let Some(path_file) = path {
+        TableFormat::from_file_name(path_file)?;
+    }
+    if let Some(image_file) = cost_image {
+        valid_output_extension(image_file)?;
+    }
+    alignment_parameters(cli).map(|_| ())
+}
+
+fn alignment_parameters(cli: &Cli) -> Result<AlignmentParameters, String> {
+    let Some(Command::Align { features, block_duration, .. }) = &cli.command else { return Ok(AlignmentParameters::default()) };
+    let defaults = AlignmentParameters::default();
+    let block_duration = block_duration.unwrap_or(defaults.block_duration);
+    if !(block_duration > 0.0 && block_duration.is_finite()) {
+        return Err(format!("Invalid block duration {}, should be positive and finite", block_duration));
+    }
+    Ok(AlignmentParameters {
+        features: features.as_deref().map(AlignmentFeatures::from_str).transpose()?.unwrap_or(defaults.features),
+        block_duration,
+    })
+}
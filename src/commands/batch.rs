@@ -0,0 +1,87 @@
+use std::fs;
+use image::ImageFormat;
+use subtune::notes::grid::GridType;
+use subtune::notes::tuning::Tuning;
+use subtune::signals::transform::FftPlanners;
+use subtune::utils::argument_validation::valid_input_extension;
+use subtune::utils::batch::{batch_inputs, mirrored_output, up_to_date};
+use subtune::utils::export::{Value, write_table};
+use subtune::utils::math::{cast, Float};
+use subtune::utils::visualization::{ColorScheme, output_image, ResamplingStrategy, VisualizationParameters};
+use crate::{Cli, Command};
+use crate::commands::{frequencies, thread_pool, try_transform};
+
+/// Saves the image of every file of the batch that isn't up to date, reporting the failures at the end, and fails if
+/// any file did
+pub fn try_batch<T: Float>(cli: &Cli, resampling_strategy: ResamplingStrategy, color_scheme: ColorScheme, grid_type: GridType,
+                           tuning: Tuning) -> Result<(), String> {
+    let Some(Command::Batch { source, output_dir, report, force }) = &cli.command else { unreachable!() };
+    let inputs = batch_inputs(source)?;
+    println!("Found {} files in {}", inputs.len(), source);
+    let pool = thread_pool(cli);
+    let (grid, frequencies) = frequencies::<T>(cli, grid_type, &tuning)?;
+    let image_frequencies: Vec<f64> = frequencies.iter().map(|frequency| cast(*frequency)).collect();
+    let mut planners = FftPlanners::new();
+
+    let mut rows = Vec::new();
+    let mut failures = Vec::new();
+    let (mut done, mut up_to_date_count) = (0, 0);
+    for (index, input) in inputs.iter().enumerate() {
+        let output = mirrored_output(output_dir, input, "png");
+        let file = input.path.display().to_string();
+        let (status, error) = if !force && up_to_date(&input.path, &output) {
+            println!("[{}/{}] {} is up to date", index + 1, inputs.len(), output.display());
+            up_to_date_count += 1;
+            ("up to date", None)
+        } else {
+            println!("[{}/{}] {} -> {}", index + 1, inputs.len(), file, output.display());
+            let result = valid_input_extension(&file)
+                .and_then(|_| output.parent().map_or(Ok(()), fs::create_dir_all).map_err(|e| format!("Could not create the directory of {}: {}", output.display(), e)))
+                .and_then(|_| try_transform(cli, &file, &frequencies, None, None, &pool, &mut planners))
+                .and_then(|transform| pool.install(|| output_image(&transform, &VisualizationParameters {
+                    file_name: output.display().to_string(),
+                    frequencies: image_frequencies.iter().map(|frequency| cast(*frequency)).collect(),
+                    resampling_strategy,
+                    color_scheme,
+                    pixels_per_second: cli.pixels_per_second.unwrap_or(32),
+                    pixels_per_frequency: cli.pixels_per_frequency.unwrap_or(6),
+                    add_piano_roll: cli.piano_roll,
+                    tuning: tuning.clone(),
+                    labels: cli.labels.then(|| grid.labels(&image_frequencies)),
+                    overlays: Vec::new(),
+                    signed: false,
+                    wavelet_delay: true,
+                    strip: None,
+                    image_format: ImageFormat::Png,
+                })));
+            match result {
+                Ok(()) => {
+                    done += 1;
+                    ("done", None)
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failures.push((file.clone(), e.clone()));
+                    ("failed", Some(e))
+                }
+            }
+        };
+        rows.push(vec![Value::Text(file),
+                       Value::Text(output.display().to_string()),
+                       Value::Text(status.to_string()),
+                       error.map(Value::Text).unwrap_or(Value::Missing)]);
+    }
+
+    println!("Processed {} files: {} done, {} up to date, {} failed", inputs.len(), done, up_to_date_count, failures.len());
+    for (file, e) in &failures {
+        println!("Failed {}: {}", file, e);
+    }
+    if let Some(report_file) = report {
+        write_table(report_file, &["file", "output", "status", "error"], &rows)?;
+        println!("Exported the report to {}", report_file);
+    }
+    if !failures.is_empty() {
+        return Err(format!("{} of {} files failed", failures.len(), inputs.len()));
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use image::ImageFormat;
+use subtune::analysis::difference::difference;
+use subtune::notes::grid::GridType;
+use subtune::notes::tuning::Tuning;
+use subtune::signals::SignalSample;
+use subtune::signals::transform::FftPlanners;
+use subtune::utils::decode_audio;
+use subtune::utils::math::{cast, Float};
+use subtune::utils::visualization::{ColorScheme, output_image, ResamplingStrategy, VisualizationParameters};
+use crate::{Cli, Command};
+use crate::commands::{frequencies, thread_pool, try_transform};
+
+/// Saves the image of the difference of the two files, both transformed on the same frequencies with the float type T
+pub fn try_diff<T: Float>(cli: &Cli, output_file: String, resampling_strategy: ResamplingStrategy, grid_type: GridType,
+                          tuning: Tuning) -> Result<(), String> {
+    let Some(Command::Diff { first, second, offset, .. }) = &cli.command else { unreachable!() };
+    let pool = thread_pool(cli);
+    let (grid, frequencies) = frequencies::<T>(cli, grid_type, &tuning)?;
+    let mut planners = FftPlanners::new();
+    // both recordings are compared at the higher of their sample rates
+    let (first_signal, second_signal) = (decode_audio::<T>(first)?, decode_audio::<T>(second)?);
+    let sample_rate = first_signal.sample_rate.max(second_signal.sample_rate);
+    let resampling = |signal: &SignalSample<T>| (signal.sample_rate != sample_rate).then_some(sample_rate);
+    let (first_rate, second_rate) = (resampling(&first_signal), resampling(&second_signal));
+    let first_transform = try_transform(cli, first, &frequencies, Some(first_signal), first_rate, &pool, &mut planners)?;
+    let second_transform = try_transform(cli, second, &frequencies, Some(second_signal), second_rate, &pool, &mut planners)?;
+    let difference = difference(&first_transform, &second_transform, offset.unwrap_or(0.0))?;
+    let largest = difference.coefficients.iter().map(|coefficient| coefficient.re.abs()).fold(T::zero(), T::max);
+    let peak = first_transform.coefficients.iter().map(|coefficient| coefficient.norm()).fold(T::zero(), T::max);
+    println!("Largest difference {:.1}% of the peak magnitude of {}, over {:.2}s",
+             100.0 * cast::<f64>(largest) / cast::<f64>(peak).max(f64::MIN_POSITIVE), first,
+             difference.frames as f64 * difference.frame_duration());
+
+    let frequencies: Vec<f64> = frequencies.iter().map(|frequency| cast(*frequency)).collect();
+    pool.install(|| output_image(&difference, &VisualizationParameters {
+        file_name: output_file.clone(),
+        frequencies: frequencies.iter().map(|frequency| cast(*frequency)).collect(),
+        resampling_strategy,
+        color_scheme: ColorScheme::Diverging,
+        pixels_per_second: cli.pixels_per_second.unwrap_or(32),
+        pixels_per_frequency: cli.pixels_per_frequency.unwrap_or(6),
+        add_piano_roll: cli.piano_roll,
+        tuning,
+        labels: cli.labels.then(|| grid.labels(&frequencies)),
+        overlays: Vec::new(),
+        signed: true,
+        wavelet_delay: true,
+        strip: None,
+        image_format: ImageFormat::Png,
+    }))?;
+    println!("Saved the difference to {}", output_file);
+    Ok(())
+}
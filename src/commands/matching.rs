@@ -0,0 +1,23 @@
+use subtune::analysis::fingerprint::{find_clip, fingerprint};
+use subtune::notes::grid::GridType;
+use subtune::notes::tuning::Tuning;
+use subtune::signals::transform::FftPlanners;
+use subtune::utils::math::Float;
+use crate::Cli;
+use crate::commands::{frequencies, thread_pool, try_transform};
+
+/// Finds the clip in the recording, both transformed on the same frequencies with the float type T
+pub fn try_match<T: Float>(cli: &Cli, clip: &str, recording: &str, grid_type: GridType, tuning: Tuning) -> Result<(), String> {
+    let pool = thread_pool(cli);
+    let (_, frequencies) = frequencies::<T>(cli, grid_type, &tuning)?;
+    let mut planners = FftPlanners::new();
+    let clip_fingerprints = fingerprint(&try_transform(cli, clip, &frequencies, None, None, &pool, &mut planners)?, &frequencies);
+    let recording_fingerprints =
+        fingerprint(&try_transform(cli, recording, &frequencies, None, None, &pool, &mut planners)?, &frequencies);
+    match find_clip(&clip_fingerprints, &recording_fingerprints) {
+        Some(found) => println!("Match at {:.2}s in {} (confidence {:.2}, {} of {} fingerprints)",
+                                found.offset, recording, found.confidence, found.matches, clip_fingerprints.len()),
+        None => println!("No match of {} in {}", clip, recording),
+    }
+    Ok(())
+}
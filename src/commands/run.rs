@@ -0,0 +1,421 @@
+use std::str::FromStr;
+use image::ImageFormat;
+use num_complex::Complex;
+use rayon::ThreadPool;
+use subtune::analysis::chords::{ChordParameters, recognize_chords};
+use subtune::analysis::chroma::{chroma, chroma_scalogram, ChromaNormalization, ChromaParameters};
+use subtune::analysis::fingerprint::fingerprint;
+use subtune::analysis::key::{detect_keys, Key, KeyEstimate, KeyParameters};
+use subtune::analysis::pitch::{PitchParameters, track_pitch};
+use subtune::analysis::separation::{separate, SeparationParameters};
+use subtune::analysis::rhythm::{estimate_tempo, onset_strength, pick_onsets, track_beats};
+use subtune::analysis::transcription::{MIDI_C0, Note, transcribe, TranscriptionParameters};
+use subtune::analysis::tuning::estimate_tuning;
+use subtune::sheets::midi::write_midi;
+use subtune::sheets::{ScoreFormat, write_score};
+use subtune::sheets::score::{quantize, ScoreParameters, TimeSignature};
+use subtune::signals::Scalogram;
+use subtune::signals::synthesis::resynthesize;
+use subtune::notes::{note_number, NOTE_NAMES};
+use subtune::notes::grid::{FrequencyGrid, GridType};
+use subtune::notes::tuning::Tuning;
+use subtune::utils::argument_validation::{valid_audio_output_extension, valid_output_extension};
+use subtune::utils::export::{TableFormat, Value, write_table};
+use subtune::utils::{decode_audio, write_wav};
+use subtune::utils::visualization::{ColorScheme, output_image, Overlay, ResamplingStrategy, VisualizationParameters};
+use subtune::signals::transform::FftPlanners;
+use subtune::utils::math::{cast, Float};
+use crate::Cli;
+use crate::commands::{frequencies, thread_pool, try_transform};
+
+pub fn try_run<T: Float>(cli: &Cli, output_file: String, resampling_strategy: ResamplingStrategy, color_scheme: ColorScheme,
+                         grid_type: GridType, mut tuning: Tuning) -> Result<(), String> {
+    let input_file = cli.input.as_deref().unwrap();
+    let pool = thread_pool(cli);
+
+    let mut signal = None;
+    if cli.estimate_tuning || cli.retune {
+        let decoded = decode_audio::<T>(input_file)?;
+        match pool.install(|| estimate_tuning(&decoded, tuning.reference)) {
+            Some(estimate) => {
+                let estimated_reference = tuning.reference * (estimate.cents / 1200.0).exp2();
+                println!("Estimated tuning: {:+.1} cents (A4 = {:.1} Hz), confidence {:.2} from {} peaks",
+                         estimate.cents, estimated_reference, estimate.confidence, estimate.peaks);
+                if cli.retune {
+                    tuning.reference = estimated_reference;
+                }
+            }
+            None => eprintln!("Can't estimate the tuning at {} Hz, the sample rate must be above twice the reference pitch",
+                             decoded.sample_rate),
+        }
+        signal = Some(decoded);
+    }
+
+    let (grid, frequencies) = frequencies::<T>(cli, grid_type, &tuning)?;
+    println!("Will save result to {}", output_file);
+    let transform = try_transform(cli, input_file, &frequencies, signal, None, &pool, &mut FftPlanners::new())?;
+
+    export_fingerprints(cli, &transform, &frequencies)?;
+    let notes = (cli.midi.is_some() || cli.score.is_some() || cli.note_names)
+        .then(|| transcribe(&transform, &frequencies, &tuning, &TranscriptionParameters::default()));
+    if let Some(notes) = &notes {
+        export_notes(cli, notes, &transform, &frequencies, &tuning)?;
+    }
+    let mut overlays = pitch_overlays(cli, &transform, &frequencies, &tuning)?;
+    if let Some(notes) = notes.filter(|_| cli.note_names) {
+        for note in notes {
+            let pitch = note.pitch as i32 - MIDI_C0;
+            overlays.push(Overlay::Label { time: note.onset, frequency: tuning.frequency(pitch), text: tuning.name(pitch), color: (255, 255, 255) });
+        }
+    }
+    let chords = chord_spans(cli, &transform, &frequencies, &tuning)?;
+    report_keys(cli, &transform, &frequencies, &tuning)?;
+    overlays.extend(rhythm_overlays(cli, &transform, &frequencies)?);
+    let chromagram = chromagram(cli, &transform, &frequencies, &tuning)?;
+
+    // the chromagram is drawn with the notes of the 4th octave, for the piano roll
+    let (image, image_frequencies, labels) = match &chromagram {
+        Some(chromagram) => (chromagram,
+                             (48..60).map(|note| cast(tuning.frequency(note))).collect(),
+                             cli.labels.then(|| NOTE_NAMES.map(String::from).to_vec())),
+        None => (&transform,
+                 frequencies.iter().map(|frequency| cast(*frequency)).collect(),
+                 cli.labels.then(|| grid.labels(&frequencies.iter().map(|frequency| cast(*frequency)).collect::<Vec<f64>>()))),
+    };
+    let parameters = VisualizationParameters {
+        file_name: output_file,
+        frequencies: image_frequencies,
+        resampling_strategy,
+        color_scheme,
+        pixels_per_second: cli.pixels_per_second.unwrap_or(32),
+        pixels_per_frequency: cli.pixels_per_frequency.unwrap_or(if cli.chroma_image { 16 } else { 6 }),
+        add_piano_roll: cli.piano_roll,
+        tuning,
+        labels,
+        overlays,
+        signed: false,
+        wavelet_delay: chromagram.is_none(),
+        strip: cli.chord_strip.then_some(chords),
+        image_format: ImageFormat::Png,
+    };
+    pool.install(|| output_image(image, &parameters))?;
+
+    export_parts(cli, &transform, &frequencies, &grid, &parameters, &pool)
+}
+
+fn export_fingerprints<T: Float>(cli: &Cli, transform: &Scalogram<Complex<T>>, frequencies: &[T]) -> Result<(), String> {
+    let Some(fingerprints_file) = &cli.fingerprints else { return Ok(()) };
+    let fingerprints = fingerprint(transform, frequencies);
+    let rows: Vec<Vec<Value>> = fingerprints.iter()
+        .map(|fingerprint| vec![Value::Number(fingerprint.time), Value::Number(fingerprint.hash as f64)])
+        .collect();
+    write_table(fingerprints_file, &["time", "hash"], &rows)?;
+    println!("Exported {} fingerprints to {}", fingerprints.len(), fingerprints_file);
+    Ok(())
+}
+
+/// Writes the transcribed notes to the MIDI file and the score, if they were requested
+fn export_notes<T: Float>(cli: &Cli, notes: &[Note], transform: &Scalogram<Complex<T>>, frequencies: &[T],
+                          tuning: &Tuning) -> Result<(), String> {
+    if let Some(midi_file) = &cli.midi {
+        write_midi(notes, midi_file)?;
+        println!("Transcribed {} notes to {}", notes.len(), midi_file);
+    }
+    if let Some(score_file) = &cli.score {
+        let mut parameters = score_parameters(cli)?;
+        if cli.score_key.is_none() {
+            if let (Some(estimate), _) = detect_keys(transform, frequencies, tuning, &KeyParameters::default()) {
+                parameters.key = estimate.key;
+            }
+        }
+        println!("Writing the score in {}", parameters.key.name());
+        let score = quantize(notes, parameters);
+        write_score(&score, score_file)?;
+        println!("Wrote {} measures to {}", score.measures.len(), score_file);
+    }
+    Ok(())
+}
+
+/// Harmonics of the given fundamentals or of the tracked pitch, and the pitch contour, exporting the tracked pitch
+fn pitch_overlays<T: Float>(cli: &Cli, transform: &Scalogram<Complex<T>>, frequencies: &[T],
+                            tuning: &Tuning) -> Result<Vec<Overlay>, String> {
+    let mut overlays = Vec::new();
+    let harmonic_color = (255, 0, 255);
+    let partials = 1..=cli.harmonics.unwrap_or(8) as usize;
+    if cli.fundamentals.is_some() {
+        for fundamental in fundamentals(cli, tuning)? {
+            overlays.extend(partials.clone().map(|partial| Overlay::Level { frequency: partial as f64 * fundamental, color: harmonic_color }));
+        }
+    }
+    let tracked_harmonics = cli.harmonics.is_some() && cli.fundamentals.is_none();
+    if !(cli.pitch.is_some() || cli.pitch_overlay || tracked_harmonics) {
+        return Ok(overlays);
+    }
+    let track = track_pitch(transform, frequencies, tuning, &PitchParameters::default());
+    if let Some(pitch_file) = &cli.pitch {
+        let rows: Vec<Vec<Value>> = track.iter()
+            .map(|frame| {
+                let optional = |value: Option<f64>| value.map(Value::Number).unwrap_or(Value::Missing);
+                vec![Value::Number(frame.time),
+                     optional(frame.frequency),
+                     frame.note.map(|(note, _)| Value::Text(tuning.name(note))).unwrap_or(Value::Missing),
+                     optional(frame.note.map(|(_, cents)| cents)),
+                     Value::Number(frame.confidence)]
+            })
+            .collect();
+        write_table(pitch_file, &["time", "frequency", "note", "cents", "confidence"], &rows)?;
+        println!("Tracked pitch in {} of {} frames to {}", track.iter().filter(|frame| frame.frequency.is_some()).count(),
+                 track.len(), pitch_file);
+    }
+    if tracked_harmonics {
+        for partial in partials {
+            let points = track.iter().map(|frame| (frame.time, frame.frequency.map(|frequency| partial as f64 * frequency))).collect();
+            overlays.push(Overlay::Contour { points, color: harmonic_color });
+        }
+    }
+    if cli.pitch_overlay {
+        let points = track.iter().map(|frame| (frame.time, frame.frequency)).collect();
+        overlays.push(Overlay::Contour { points, color: (255, 255, 255) });
+    }
+    Ok(overlays)
+}
+
+/// Exports the recognized chords, and returns them as labeled time spans, none if they weren't requested
+fn chord_spans<T: Float>(cli: &Cli, transform: &Scalogram<Complex<T>>, frequencies: &[T],
+                         tuning: &Tuning) -> Result<Vec<(f64, f64, String)>, String> {
+    if cli.chords.is_none() && !cli.chord_strip {
+        return Ok(Vec::new());
+    }
+    let segments = recognize_chords(transform, frequencies, tuning, &ChordParameters::default());
+    if let Some(chords_file) = &cli.chords {
+        let rows: Vec<Vec<Value>> = segments.iter()
+            .map(|segment| match segment.chord {
+                Some(chord) => vec![Value::Number(segment.start), Value::Number(segment.end), Value::Text(chord.symbol()),
+                                    Value::Text(NOTE_NAMES[chord.root].to_string()), Value::Text(chord.quality.name().to_string()),
+                                    Value::Text(NOTE_NAMES[chord.bass].to_string()), Value::Number(segment.confidence)],
+                None => vec![Value::Number(segment.start), Value::Number(segment.end), Value::Text("N".to_string()),
+                             Value::Missing, Value::Missing, Value::Missing, Value::Number(segment.confidence)],
+            })
+            .collect();
+        write_table(chords_file, &["start", "end", "chord", "root", "quality", "bass", "confidence"], &rows)?;
+        println!("Recognized {} chord segments to {}", segments.len(), chords_file);
+    }
+    Ok(segments.iter()
+        .map(|segment| (segment.start, segment.end, segment.chord.map(|chord| chord.symbol()).unwrap_or("N".to_string())))
+        .collect())
+}
+
+/// Prints and exports the detected keys, if they were requested
+fn report_keys<T: Float>(cli: &Cli, transform: &Scalogram<Complex<T>>, frequencies: &[T], tuning: &Tuning) -> Result<(), String> {
+    if !cli.key && cli.keys.is_none() {
+        return Ok(());
+    }
+    let (global, segments) = detect_keys(transform, frequencies, tuning, &KeyParameters::default());
+    if cli.key {
+        match &global {
+            Some(estimate) => println!("Key: {} (correlation {:.2}, confidence {:.2})",
+                                       estimate.key.name(), estimate.correlation, estimate.confidence),
+            None => println!("Key: none, the input is silent"),
+        }
+        if segments.len() > 1 {
+            for segment in &segments {
+                println!("  {:.1}s - {:.1}s: {} (correlation {:.2})",
+                         segment.start, segment.end, segment.estimate.key.name(), segment.estimate.correlation);
+            }
+        }
+    }
+    if let Some(keys_file) = &cli.keys {
+        let duration = transform.frames as f64 * transform.frame_duration();
+        let row = |scope: &str, start: f64, end: f64, estimate: &KeyEstimate| vec![
+            Value::Text(scope.to_string()), Value::Number(start), Value::Number(end), Value::Text(estimate.key.name()),
+            Value::Text(estimate.key.tonic_name()), Value::Text(estimate.key.mode.name().to_string()),
+            Value::Number(estimate.correlation), Value::Number(estimate.confidence)];
+        let rows: Vec<Vec<Value>> = global.iter().map(|estimate| row("global", 0.0, duration, estimate))
+            .chain(segments.iter().map(|segment| row("segment", segment.start, segment.end, &segment.estimate)))
+            .collect();
+        write_table(keys_file, &["scope", "start", "end", "key", "tonic", "mode", "correlation", "confidence"], &rows)?;
+        println!("Exported the key and {} key segments to {}", segments.len(), keys_file);
+    }
+    Ok(())
+}
+
+/// Exports the onsets and beats, and returns their tick marks if they were requested
+fn rhythm_overlays<T: Float>(cli: &Cli, transform: &Scalogram<Complex<T>>, frequencies: &[T]) -> Result<Vec<Overlay>, String> {
+    if cli.onsets.is_none() && cli.beats.is_none() && !cli.ticks {
+        return Ok(Vec::new());
+    }
+    let frame_duration = transform.frame_duration();
+    let strength = onset_strength(transform, frequencies);
+    let onsets = pick_onsets(&strength, frame_duration);
+    let tempo = estimate_tempo(&strength, frame_duration);
+    let beats = tempo.map(|tempo| track_beats(&strength, frame_duration, tempo)).unwrap_or_default();
+    match tempo {
+        Some(tempo) => println!("Tempo: {:.1} BPM", tempo),
+        None => println!("Tempo: none, no onsets were found"),
+    }
+    if let Some(onsets_file) = &cli.onsets {
+        let rows: Vec<Vec<Value>> = onsets.iter()
+            .map(|time| vec![Value::Number(*time), Value::Number(strength[(time / frame_duration).round() as usize])])
+            .collect();
+        write_table(onsets_file, &["time", "strength"], &rows)?;
+        println!("Detected {} onsets to {}", onsets.len(), onsets_file);
+    }
+    if let Some(beats_file) = &cli.beats {
+        let rows: Vec<Vec<Value>> = beats.iter().enumerate()
+            .map(|(beat, time)| vec![Value::Number(beat as f64 + 1.0), Value::Number(*time)])
+            .collect();
+        write_table(beats_file, &["beat", "time"], &rows)?;
+        println!("Tracked {} beats to {}", beats.len(), beats_file);
+    }
+    if !cli.ticks {
+        return Ok(Vec::new());
+    }
+    Ok(vec![Overlay::Ticks { times: onsets, length: 4, color: (255, 255, 255) },
+            Overlay::Ticks { times: beats, length: 12, color: (255, 255, 255) }])
+}
+
+/// Exports the chroma, and returns it as a scalogram of 12 rows if it replaces the transform in the image
+fn chromagram<T: Float>(cli: &Cli, transform: &Scalogram<Complex<T>>, frequencies: &[T],
+                        tuning: &Tuning) -> Result<Option<Scalogram<Complex<T>>>, String> {
+    if cli.chroma.is_none() && !cli.chroma_image {
+        return Ok(None);
+    }
+    let chroma_frames = chroma(transform, frequencies, tuning, &chroma_parameters(cli)?);
+    if let Some(chroma_file) = &cli.chroma {
+        let frame_duration = transform.frame_duration();
+        let columns: Vec<&str> = std::iter::once("time").chain(NOTE_NAMES).collect();
+        let rows: Vec<Vec<Value>> = chroma_frames.iter().enumerate()
+            .map(|(frame, values)| std::iter::once(frame as f64 * frame_duration).chain(*values).map(Value::Number).collect())
+            .collect();
+        write_table(chroma_file, &columns, &rows)?;
+        println!("Exported the chroma of {} frames to {}", chroma_frames.len(), chroma_file);
+    }
+    Ok(cli.chroma_image.then(|| chroma_scalogram::<T>(&chroma_frames, transform.sample_rate, transform.hop_size)))
+}
+
+/// Separates the harmonic and percussive parts, and saves the images and resynthesized audio that were requested,
+/// drawn like the main image
+fn export_parts<T: Float>(cli: &Cli, transform: &Scalogram<Complex<T>>, frequencies: &[T], grid: &FrequencyGrid,
+                          parameters: &VisualizationParameters, pool: &ThreadPool) -> Result<(), String> {
+    let parts = [("harmonic", &cli.harmonic_image, &cli.harmonic_audio), ("percussive", &cli.percussive_image, &cli.percussive_audio)];
+    if parts.iter().all(|(_, image_file, audio_file)| image_file.is_none() && audio_file.is_none()) {
+        return Ok(());
+    }
+    let separation_parameters = separation_parameters(cli)?;
+    let (harmonic, percussive) = pool.install(|| separate(transform, frequencies, &separation_parameters));
+    let frequencies: Vec<f64> = frequencies.iter().map(|frequency| cast(*frequency)).collect();
+    for ((name, image_file, audio_file), part) in parts.into_iter().zip([harmonic, percussive]) {
+        if let Some(image_file) = image_file {
+            pool.install(|| output_image(&part, &VisualizationParameters {
+                file_name: image_file.clone(),
+                frequencies: frequencies.iter().map(|frequency| cast(*frequency)).collect(),
+                labels: cli.labels.then(|| grid.labels(&frequencies)),
+                overlays: Vec::new(),
+                wavelet_delay: true,
+                strip: None,
+                tuning: parameters.tuning.clone(),
+                ..*parameters
+            }))?;
+            println!("Saved the {} part to {}", name, image_file);
+        }
+        if let Some(audio_file) = audio_file {
+            let stem = pool.install(|| resynthesize(&part, &frequencies, part.frames * part.hop_size));
+            write_wav(audio_file, &stem)?;
+            println!("Resynthesized the {} part to {}", name, audio_file);
+        }
+    }
+    Ok(())
+}
+
+fn chroma_parameters(cli: &Cli) -> Result<ChromaParameters, String> {
+    let parameters = ChromaParameters {
+        harmonics: cli.chroma_harmonics.unwrap_or(1) as usize,
+        harmonic_decay: cli.chroma_harmonic_decay.unwrap_or(0.8),
+        normalization: ChromaNormalization::from_str(cli.chroma_normalization.as_deref().unwrap_or("max"))?,
+    };
+    parameters.validate()?;
+    Ok(parameters)
+}
+
+/// Frequencies of the --fundamentals, given in Hz or as note names of the tuning
+fn fundamentals(cli: &Cli, tuning: &Tuning) -> Result<Vec<f64>, String> {
+    cli.fundamentals.as_deref().unwrap_or("").split(',')
+        .map(|fundamental| {
+            let fundamental = fundamental.trim();
+            f64::from_str(fundamental).ok().filter(|frequency| frequency.is_finite() && *frequency > 0.0)
+                .or_else(|| note_number(fundamental).map(|note| tuning.frequency(note)))
+                .ok_or(format!("Invalid fundamental '{}', should be a frequency in Hz or a note name such as A3", fundamental))
+        })
+        .collect()
+}
+
+fn separation_parameters(cli: &Cli) -> Result<SeparationParameters, String> {
+    let defaults = SeparationParameters::default();
+    let harmonic_window = cli.harmonic_window.unwrap_or(defaults.harmonic_window);
+    let percussive_window = cli.percussive_window.unwrap_or(defaults.percussive_window);
+    let valid = |window: f64| window > 0.0 && window.is_finite();
+    if !valid(harmonic_window) || !valid(percussive_window) {
+        return Err(format!("Invalid separation windows {} s and {} octaves, should be positive and finite", harmonic_window,
+                           percussive_window));
+    }
+    Ok(SeparationParameters { harmonic_window, percussive_window, ..defaults })
+}
+
+fn score_parameters(cli: &Cli) -> Result<ScoreParameters, String> {
+    let tempo = cli.tempo.unwrap_or(120.0);
+    if !(1.0..=1000.0).contains(&tempo) {
+        return Err(format!("Invalid tempo {}, should be between 1 and 1000 BPM", tempo));
+    }
+    let divisions = cli.quantization.unwrap_or(4);
+    if ![1, 2, 4, 8, 16].contains(&divisions) {
+        return Err(format!("Invalid quantization {}, should be one of 1, 2, 4, 8 or 16", divisions));
+    }
+    let time_signature = TimeSignature::from_str(cli.time_signature.as_deref().unwrap_or("4/4"))?;
+    if !(4 * divisions).is_multiple_of(time_signature.beat_type) {
+        return Err(format!("A quantization of {} per quarter note is coarser than the beat of {}/{}, raise it",
+                           divisions, time_signature.beats, time_signature.beat_type));
+    }
+    let key = Key::from_str(cli.score_key.as_deref().unwrap_or("C major"))?;
+    Ok(ScoreParameters { tempo, time_signature, divisions, key })
+}
+
+/// Checks the output files and parameters of the analyses before transforming, so that mistakes don't cost a transform
+pub fn validate_outputs(cli: &Cli, tuning: &Tuning) -> Result<(), String> {
+    if let Some(pitch_file) = &cli.pitch {
+        TableFormat::from_file_name(pitch_file)?;
+    }
+    if let Some(chords_file) = &cli.chords {
+        TableFormat::from_file_name(chords_file)?;
+    }
+    if let Some(chroma_file) = &cli.chroma {
+        TableFormat::from_file_name(chroma_file)?;
+    }
+    if let Some(keys_file) = &cli.keys {
+        TableFormat::from_file_name(keys_file)?;
+    }
+    if let Some(fingerprints_file) = &cli.fingerprints {
+        TableFormat::from_file_name(fingerprints_file)?;
+    }
+    if let Some(onsets_file) = &cli.onsets {
+        TableFormat::from_file_name(onsets_file)?;
+    }
+    if let Some(beats_file) = &cli.beats {
+        TableFormat::from_file_name(beats_file)?;
+    }
+    chroma_parameters(cli)?;
+    for image_file in [&cli.harmonic_image, &cli.percussive_image].into_iter().flatten() {
+        valid_output_extension(image_file)?;
+    }
+    for audio_file in [&cli.harmonic_audio, &cli.percussive_audio].into_iter().flatten() {
+        valid_audio_output_extension(audio_file)?;
+    }
+    separation_parameters(cli)?;
+    if cli.fundamentals.is_some() {
+        fundamentals(cli, tuning)?;
+    }
+    if let Some(score_file) = &cli.score {
+        ScoreFormat::from_file_name(score_file)?;
+    }
+    score_parameters(cli).map(|_| ())
+}
+
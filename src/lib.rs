@@ -0,0 +1,5 @@
+pub mod analysis;
+pub mod notes;
+pub mod sheets;
+pub mod signals;
+pub mod utils;
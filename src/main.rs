@@ -1,127 +1,416 @@
 use std::process::exit;
 use std::str::FromStr;
 use std::time::Instant;
-use clap::Parser;
-use image::ImageFormat;
-use signals::wavelets;
-use crate::notes::C0;
-use crate::utils::argument_validation::validate_arguments;
-use crate::utils::read_audio;
-use crate::utils::visualization::{ColorScheme, output_image, ResamplingStrategy, VisualizationParameters};
-use crate::signals::wavelets::MORLET_HALF_LENGTH;
-use crate::signals::transform::wavelet_transform;
-use crate::utils::math::FloatType;
-
-mod notes;
-mod signals;
-mod utils;
+use clap::{Parser, Subcommand};
+use subtune::notes::grid::GridType;
+use subtune::utils::argument_validation::{valid_input_extension, valid_output_extension, validate_arguments,
+                                          validate_transform_arguments};
+use subtune::utils::export::TableFormat;
+use subtune::utils::math::Precision;
+use subtune::utils::visualization::{ColorScheme, ResamplingStrategy};
+use crate::commands::align::{try_align, validate_alignment_outputs};
+use crate::commands::batch::try_batch;
+use crate::commands::diff::try_diff;
+use crate::commands::matching::try_match;
+use crate::commands::run::{try_run, validate_outputs};
+use crate::commands::tuning_from;
+
+mod commands;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input file path (only .wav, .mp3)
-    #[arg(short, long)]
-    input: String,
+    #[arg(short, long, required = true)]
+    input: Option<String>,
 
     /// Output image file path (only .png)
     #[arg(short, long)]
     output: Option<String>,
 
     /// Number of octaves to analyze, default 9
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     num_octaves: Option<u32>,
 
     /// Index of first octave (default 1 = C1-B1). Negative allowed.
-    #[arg(short, long)]
+    #[arg(short, long, allow_hyphen_values = true, global = true)]
     start_octave: Option<i32>,
 
     /// Resampling strategy [max, avg] (default max)
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     resampling_strategy: Option<String>,
 
-    /// Color scheme [heatmap, grayscale] (default heatmap)
-    #[arg(short, long)]
+    /// Color scheme [heatmap, grayscale, diverging] (default heatmap)
+    #[arg(short, long, global = true)]
     color_scheme: Option<String>,
 
     /// Pixels per second on the horizontal axis of the resulting image (default 32)
-    #[arg(long)]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global = true)]
     pixels_per_second: Option<u32>,
 
-    /// Pixels per frequency on the vertical axis of the resulting image (default 6)
-    #[arg(long)]
+    /// Pixels per frequency on the vertical axis of the resulting image (default 6, 16 for chroma images)
+    #[arg(long, global = true)]
     pixels_per_frequency: Option<u32>,
 
     /// Frequencies per note/pitch, evenly spaced in exponential space (default 1)
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     frequencies_per_note: Option<u32>,
 
-    /// Number of threads to use when calculating the wavelet transform (default 16)
-    #[arg(short, long)]
+    /// Frequency grid [notes, linear, mel, bark, log, file] (default notes)
+    #[arg(long, global = true)]
+    grid: Option<String>,
+
+    /// Frequency of A4 in Hz (default 440)
+    #[arg(long, global = true)]
+    reference_pitch: Option<f64>,
+
+    /// Tuning system of the notes grid, piano roll and labels [equal, just, pythagorean, custom] (default equal)
+    #[arg(long, global = true)]
+    tuning: Option<String>,
+
+    /// Tonic of the just, pythagorean and custom tunings (default C)
+    #[arg(long, global = true)]
+    tonic: Option<String>,
+
+    /// 12 comma separated offsets in cents from the tonic, for the custom tuning
+    #[arg(long, global = true)]
+    cents: Option<String>,
+
+    /// Lowest frequency in Hz of the linear, mel, bark and log grids (default 32.7)
+    #[arg(long, global = true)]
+    min_frequency: Option<f64>,
+
+    /// Highest frequency in Hz of the linear, mel, bark and log grids (default 8000)
+    #[arg(long, global = true)]
+    max_frequency: Option<f64>,
+
+    /// Distance in Hz between two frequencies of the linear grid (default 10)
+    #[arg(long, global = true)]
+    frequency_step: Option<f64>,
+
+    /// Number of frequencies of the mel, bark and log grids (default 120)
+    #[arg(long, global = true)]
+    bins: Option<u32>,
+
+    /// File with one frequency in Hz per line in increasing order, for the file grid
+    #[arg(long, global = true)]
+    frequency_file: Option<String>,
+
+    /// Number of threads to use when calculating the wavelet transform and the image (default: available cores)
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..), global = true)]
     threads: Option<u32>,
 
     /// If this flag is present, adds a simple piano roll in the resulting image
-    #[arg(short, long, default_missing_value = "true")]
+    #[arg(short, long, default_missing_value = "true", global = true)]
     piano_roll: bool,
-}
 
-fn main() {
-    let time = Instant::now();
-    let cli = Cli::parse();
+    /// If this flag is present, rows are labeled with their note or frequency
+    #[arg(long, default_missing_value = "true", global = true)]
+    labels: bool,
 
-    let input_file = cli.input.as_str();
-    let output_file_from_input = default_output_file(input_file);
-    let output_file = cli.output.unwrap_or(output_file_from_input);
+    /// If this flag is present, estimates how many cents the recording is off the reference pitch
+    #[arg(long, default_missing_value = "true")]
+    estimate_tuning: bool,
 
-    let resampling_strategy = cli.resampling_strategy.unwrap_or("max".to_string());
-    let resampling_strategy = resampling_strategy.as_str();
-    let color_scheme = cli.color_scheme.unwrap_or("heatmap".to_string());
-    let color_scheme = color_scheme.as_str();
+    /// If this flag is present, the notes grid is centered on the estimated tuning (implies --estimate-tuning)
+    #[arg(long, default_missing_value = "true")]
+    retune: bool,
 
-    validate(input_file, &output_file, resampling_strategy, color_scheme);
+    /// If this flag is present, low octaves are computed on a decimated signal (faster, slightly less accurate)
+    #[arg(long, default_missing_value = "true", global = true)]
+    multirate: bool,
 
-    let signal = read_audio(input_file);
+    /// If this flag is present, wavelet spectra are computed in closed form instead of with an FFT of each wavelet
+    #[arg(long, default_missing_value = "true", global = true)]
+    spectral_wavelets: bool,
 
-    let first_octave = cli.start_octave.unwrap_or(1);
-    let octaves = cli.num_octaves.unwrap_or(9) as i32;
+    /// Number of samples between two computed wavelet coefficients (default 1/8 of the samples per pixel)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global = true)]
+    hop_size: Option<u32>,
 
-    let frequencies_per_note = cli.frequencies_per_note.unwrap_or(1) as i32;
-    let frequencies: Vec<FloatType> = (12 * frequencies_per_note * first_octave..(12 * frequencies_per_note * (first_octave + octaves) + 12))
-        .map(|i| C0 * (i as FloatType / 12.0 / frequencies_per_note as FloatType).exp2())
-        .collect();
+    /// Float precision of the wavelet transform [f32, f64] (default f32)
+    #[arg(long, global = true)]
+    precision: Option<String>,
 
-    println!("Transforming {} samples, for {} frequencies. Will save result to {}", signal.samples.len(), frequencies.len(), output_file.as_str());
+    /// Transcribes the notes to this Standard MIDI File (.mid)
+    #[arg(long)]
+    midi: Option<String>,
 
-    let transform = wavelet_transform(&signal, &|frequency, sample_rate| {
-        let wavelet = wavelets::morlet(frequency);
-        signals::SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH / frequency, sample_rate, &wavelet)
-    }, &frequencies, cli.threads.unwrap_or(16));
+    /// Tracks the fundamental frequency of a single voice to this file (.csv or .json)
+    #[arg(long)]
+    pitch: Option<String>,
 
-    let parameters = VisualizationParameters {
-        file_name: output_file,
-        frequencies,
-        sample_rate: signal.sample_rate,
-        resampling_strategy: ResamplingStrategy::from_str(resampling_strategy).unwrap(),
-        color_scheme: ColorScheme::from_str(color_scheme).unwrap(),
-        pixels_per_second: cli.pixels_per_second.unwrap_or(32),
-        pixels_per_frequency: cli.pixels_per_frequency.unwrap_or(6),
-        add_piano_roll: cli.piano_roll,
-        image_format: ImageFormat::Png,
-    };
-    output_image(&transform, &parameters);
+    /// If this flag is present, draws the tracked fundamental frequency over the image
+    #[arg(long, default_missing_value = "true")]
+    pitch_overlay: bool,
 
-    println!("Done in {:?}", time.elapsed());
+    /// Draws markers at the first N partials of the tracked fundamental, or of --fundamentals, over the image (default 8)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    harmonics: Option<u32>,
+
+    /// Comma separated fundamentals in Hz or note names (e.g. 110,E3) whose partials are marked instead of the tracked ones
+    #[arg(long)]
+    fundamentals: Option<String>,
+
+    /// If this flag is present, the transcribed notes are labeled with their names over the image
+    #[arg(long, default_missing_value = "true")]
+    note_names: bool,
+
+    /// Recognizes the chords to this timeline (.csv or .json)
+    #[arg(long)]
+    chords: Option<String>,
+
+    /// If this flag is present, draws the recognized chords in a strip under the image
+    #[arg(long, default_missing_value = "true")]
+    chord_strip: bool,
+
+    /// Exports the chroma, the magnitudes of the 12 pitch classes per frame, to this file (.csv or .json)
+    #[arg(long)]
+    chroma: Option<String>,
+
+    /// If this flag is present, the image is a 12 row chromagram instead of the scalogram
+    #[arg(long, default_missing_value = "true", conflicts_with_all = ["pitch_overlay", "harmonics", "fundamentals", "note_names"])]
+    chroma_image: bool,
+
+    /// Number of partials of each note added to its chroma, 1 to only fold the octaves (default 1)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    chroma_harmonics: Option<u32>,
+
+    /// Weight of each partial relative to the previous one in the chroma, at least 0 (default 0.8)
+    #[arg(long)]
+    chroma_harmonic_decay: Option<f64>,
+
+    /// Per frame normalization of the chroma [none, max, sum, l2] (default max)
+    #[arg(long)]
+    chroma_normalization: Option<String>,
+
+    /// If this flag is present, prints the estimated key and key changes
+    #[arg(long, default_missing_value = "true")]
+    key: bool,
+
+    /// Exports the global key and the segments in a single key to this file (.csv or .json)
+    #[arg(long)]
+    keys: Option<String>,
+
+    /// Exports the detected onsets and their strength to this file (.csv or .json)
+    #[arg(long)]
+    onsets: Option<String>,
+
+    /// Exports the tracked beats to this file (.csv or .json)
+    #[arg(long)]
+    beats: Option<String>,
+
+    /// If this flag is present, draws the onsets (short) and beats (long) as tick marks at the top of the image
+    #[arg(long, default_missing_value = "true")]
+    ticks: bool,
+
+    /// Saves the image of the harmonic part of the transform, separated from the percussive one, to this file (.png)
+    #[arg(long)]
+    harmonic_image: Option<String>,
+
+    /// Saves the image of the percussive part of the transform to this file (.png)
+    #[arg(long)]
+    percussive_image: Option<String>,
+
+    /// Resynthesizes the harmonic part of the transform to this file (.wav)
+    #[arg(long)]
+    harmonic_audio: Option<String>,
+
+    /// Resynthesizes the percussive part of the transform to this file (.wav)
+    #[arg(long)]
+    percussive_audio: Option<String>,
+
+    /// Duration in seconds of the median filter along the rows for the harmonic part (default 0.4)
+    #[arg(long)]
+    harmonic_window: Option<f64>,
+
+    /// Width in octaves of the median filter across the rows for the percussive part (default 1)
+    #[arg(long)]
+    percussive_window: Option<f64>,
+
+    /// Exports the fingerprints of the input, hashes of pairs of peaks, to this file (.csv or .json)
+    #[arg(long)]
+    fingerprints: Option<String>,
+
+    /// Transcribes the notes to this score, in MusicXML (.musicxml, .xml), LilyPond (.ly) or ABC (.abc)
+    #[arg(long)]
+    score: Option<String>,
+
+    /// Tempo of the score in quarter notes per minute, from 1 to 1000 (default 120)
+    #[arg(long)]
+    tempo: Option<f64>,
+
+    /// Time signature of the score (default 4/4)
+    #[arg(long)]
+    time_signature: Option<String>,
+
+    /// Rhythms of the score are rounded to this fraction of a quarter note [1, 2, 4, 8, 16] (default 4)
+    #[arg(long)]
+    quantization: Option<u32>,
+
+    /// Key signature of the score, e.g. "Eb major" or "F# minor" (default: the detected key)
+    #[arg(long)]
+    score_key: Option<String>,
+
+    /// Directory where computed transforms are cached, to re-render with other image settings without recomputing (default: no cache)
+    #[arg(long, global = true)]
+    cache_dir: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Finds where a short clip occurs in a longer recording, from the fingerprints of their transforms
+    Match {
+        /// Clip file path (only .wav, .mp3)
+        clip: String,
+        /// Recording file path (only .wav, .mp3)
+        recording: String,
+    },
+    /// Aligns a performance to a reference recording by dynamic time warping of the features of their transforms
+    Align {
+        /// Reference file path (only .wav, .mp3)
+        reference: String,
+        /// Performance file path (only .wav, .mp3)
+        performance: String,
+        /// Exports the warping path, the matching times of the reference and the performance, to this file (.csv or .json)
+        #[arg(long)]
+        path: Option<String>,
+        /// Saves the cost matrix, reference upwards and performance rightwards, with the path over it to this file (.png)
+        #[arg(long)]
+        cost_image: Option<String>,
+        /// Features compared between the recordings [chroma, scalogram] (default chroma)
+        #[arg(long)]
+        features: Option<String>,
+        /// Duration in seconds of the blocks of frames aligned together (default 0.1)
+        #[arg(long)]
+        block_duration: Option<f64>,
+    },
+    /// Renders the difference of the magnitudes of the transforms of two files, red where the second is louder and
+    /// blue where it is quieter
+    Diff {
+        /// First file path (only .wav, .mp3)
+        first: String,
+        /// Second file path (only .wav, .mp3)
+        second: String,
+        /// Output image file path (only .png, default <first>-diff.png)
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Seconds the second file lags behind the first one, negative if it is ahead (default 0)
+        #[arg(long, allow_hyphen_values = true)]
+        offset: Option<f64>,
+    },
+    /// Saves the images of many files with the same options, sharing the threads and the FFT plans between them
+    Batch {
+        /// Directory, glob pattern (quoted, e.g. 'music/**/*.wav') or manifest file with one path per line
+        source: String,
+        /// Directory where the images are saved, in the same tree as the inputs, named after them with .png appended
+        #[arg(long)]
+        output_dir: String,
+        /// Exports the status of every file (done, up to date or failed, with the error) to this file (.csv or .json)
+        #[arg(long)]
+        report: Option<String>,
+        /// If this flag is present, files are processed even if their image is newer than them (needed after changing the
+        /// options, which aren't compared)
+        #[arg(long, default_missing_value = "true")]
+        force: bool,
+    },
 }
 
-fn validate(input_file: &str, output_file: &str, resampling_strategy: &str, color_scheme: &str) {
-    let validation_result =
-        validate_arguments(input_file,
-                           output_file,
-                           resampling_strategy,
-                           color_scheme);
-    if validation_result.is_err() {
-        eprintln!("{}", validation_result.err().unwrap());
+fn main() {
+    let time = Instant::now();
+    let cli = Cli::parse();
+    if let Err(e) = try_main(&cli) {
+        eprintln!("{}", e);
         exit(1);
     }
+    println!("Done in {:?}", time.elapsed());
+}
+
+/// Validates the arguments of the command, then runs it with the float type of the precision
+fn try_main(cli: &Cli) -> Result<(), String> {
+    let precision = cli.precision.clone().unwrap_or("f32".to_string());
+    let precision = precision.as_str();
+    let grid = cli.grid.clone().unwrap_or("notes".to_string());
+    let grid = grid.as_str();
+    let tuning = cli.tuning.clone().unwrap_or("equal".to_string());
+    let tuning = tuning.as_str();
+    let resampling_strategy = cli.resampling_strategy.clone().unwrap_or("max".to_string());
+    let resampling_strategy = resampling_strategy.as_str();
+    let color_scheme = cli.color_scheme.clone().unwrap_or("heatmap".to_string());
+    let color_scheme = color_scheme.as_str();
+
+    match &cli.command {
+        Some(Command::Match { clip, recording }) => {
+            valid_input_extension(clip)?;
+            valid_input_extension(recording)?;
+            validate_transform_arguments(precision, grid, tuning)?;
+            let (grid_type, tuning) = (GridType::from_str(grid)?, tuning_from(cli, tuning)?);
+            match Precision::from_str(precision)? {
+                Precision::Single => try_match::<f32>(cli, clip, recording, grid_type, tuning),
+                Precision::Double => try_match::<f64>(cli, clip, recording, grid_type, tuning),
+            }
+        }
+        Some(Command::Align { reference, performance, .. }) => {
+            valid_input_extension(reference)?;
+            valid_input_extension(performance)?;
+            validate_transform_arguments(precision, grid, tuning)?;
+            let color_scheme = ColorScheme::from_str(color_scheme)?;
+            validate_alignment_outputs(cli)?;
+            let (grid_type, tuning) = (GridType::from_str(grid)?, tuning_from(cli, tuning)?);
+            match Precision::from_str(precision)? {
+                Precision::Single => try_align::<f32>(cli, color_scheme, grid_type, tuning),
+                Precision::Double => try_align::<f64>(cli, color_scheme, grid_type, tuning),
+            }
+        }
+        Some(Command::Diff { first, second, output, offset }) => {
+            let output_file = output.clone()
+                .unwrap_or_else(|| default_output_file(first).trim_end_matches(".png").to_string() + "-diff.png");
+            valid_input_extension(first)?;
+            valid_input_extension(second)?;
+            valid_output_extension(&output_file)?;
+            let resampling_strategy = ResamplingStrategy::from_str(resampling_strategy)?;
+            validate_transform_arguments(precision, grid, tuning)?;
+            if let Some(offset) = offset.filter(|offset| !offset.is_finite()) {
+                return Err(format!("Invalid offset {}, should be finite", offset));
+            }
+            let (grid_type, tuning) = (GridType::from_str(grid)?, tuning_from(cli, tuning)?);
+            match Precision::from_str(precision)? {
+                Precision::Single => try_diff::<f32>(cli, output_file, resampling_strategy, grid_type, tuning),
+                Precision::Double => try_diff::<f64>(cli, output_file, resampling_strategy, grid_type, tuning),
+            }
+        }
+        Some(Command::Batch { report, .. }) => {
+            let resampling_strategy = ResamplingStrategy::from_str(resampling_strategy)?;
+            let color_scheme = ColorScheme::from_str(color_scheme)?;
+            validate_transform_arguments(precision, grid, tuning)?;
+            if let Some(report_file) = report {
+                TableFormat::from_file_name(report_file)?;
+            }
+            let (grid_type, tuning) = (GridType::from_str(grid)?, tuning_from(cli, tuning)?);
+            match Precision::from_str(precision)? {
+                Precision::Single => try_batch::<f32>(cli, resampling_strategy, color_scheme, grid_type, tuning),
+                Precision::Double => try_batch::<f64>(cli, resampling_strategy, color_scheme, grid_type, tuning),
+            }
+        }
+        None => {
+            let input_file = cli.input.as_deref().unwrap();
+            let output_file = cli.output.clone().unwrap_or_else(|| default_output_file(input_file));
+            validate_arguments(input_file, &output_file, resampling_strategy, color_scheme, precision, grid, tuning)?;
+
+            let resampling_strategy = ResamplingStrategy::from_str(resampling_strategy)?;
+            let color_scheme = ColorScheme::from_str(color_scheme)?;
+            let tuning = tuning_from(cli, tuning)?;
+            let grid_type = GridType::from_str(grid)?;
+            validate_outputs(cli, &tuning)?;
+            match Precision::from_str(precision)? {
+                Precision::Single => try_run::<f32>(cli, output_file, resampling_strategy, color_scheme, grid_type, tuning),
+                Precision::Double => try_run::<f64>(cli, output_file, resampling_strategy, color_scheme, grid_type, tuning),
+            }
+        }
+    }
 }
 
 fn default_output_file(input_file: &str) -> String {
@@ -1,3 +1,127 @@
-use crate::utils::math::FloatType;
+pub mod grid;
+pub mod tuning;
 
-pub(crate) const C0: FloatType = 16.35;
\ No newline at end of file
+use std::str::FromStr;
+
+pub const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Index of A4 in semitones from C0, the reference of the tunings
+pub const A4: i32 = 57;
+
+/// Pitch class (0 for C to 11 for B) of a note name, with an optional sharp or flat
+pub fn pitch_class(name: &str) -> Option<i32> {
+    let mut characters = name.chars();
+    let natural: i32 = match characters.next()? {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let accidental = match characters.as_str() {
+        "" => 0,
+        "#" => 1,
+        "b" => -1,
+        _ => return None,
+    };
+    Some((natural + accidental).rem_euclid(12))
+}
+
+/// Number of semitones above C0 of a note name with its octave, e.g. 57 for A4 or 47 for Cb4
+pub fn note_number(name: &str) -> Option<i32> {
+    let octave_start = name.char_indices().skip(1).find(|(_, character)| character.is_ascii_digit() || *character == '-')?.0;
+    let (pitch, octave) = name.split_at(octave_start);
+    let natural = pitch_class(pitch.get(..1)?)?;
+    let accidental = (pitch_class(pitch)? - natural + 1).rem_euclid(12) - 1;
+    Some(12 * i32::from_str(octave).ok()? + natural + accidental)
+}
+
+/// Step letter, alteration in semitones and octave of the note the given number of semitones above C0, e.g. ('C', 1, 4)
+/// for C#4, in the key signature with the given number of sharps (flats if negative): notes of the scale are spelled
+/// with the alterations of the signature, the others as naturals when they can, else as raised degrees in sharp keys and
+/// C major, lowered ones in flat keys
+pub fn spell(note: i32, fifths: i32) -> (char, i32, i32) {
+    let pitch_class = note.rem_euclid(12);
+    let direction = if fifths < 0 { -1 } else { 1 };
+    let candidates = || STEPS.iter().flat_map(|step| (-1..=1).map(move |alter| (*step, alter)))
+        .filter(|(step, alter)| (pitch_class_of_step(*step) + alter).rem_euclid(12) == pitch_class);
+    let (step, alter) = candidates().find(|(step, alter)| *alter == key_alter(*step, fifths))
+        .or_else(|| candidates().find(|(_, alter)| *alter == 0))
+        .or_else(|| candidates().find(|(step, alter)| *alter == key_alter(*step, fifths) + direction))
+        .unwrap();
+    (step, alter, (note - alter).div_euclid(12))
+}
+
+const STEPS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+fn pitch_class_of_step(step: char) -> i32 {
+    pitch_class(&step.to_string()).unwrap()
+}
+
+/// Alteration of the step in the key signature with the given number of sharps (flats if negative)
+pub fn key_alter(step: char, fifths: i32) -> i32 {
+    // sharps are added from F in fifths, flats from B in fourths
+    let position = |order: &str| order.find(step).unwrap() as i32;
+    if fifths > 0 && position("FCGDAEB") < fifths {
+        1
+    } else if fifths < 0 && position("BEADGCF") < -fifths {
+        -1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notes::{key_alter, note_number, pitch_class, spell};
+
+    #[test]
+    fn pitch_classes() {
+        assert_eq!(pitch_class("C"), Some(0));
+        assert_eq!(pitch_class("F#"), Some(6));
+        assert_eq!(pitch_class("Bb"), Some(10));
+        assert_eq!(pitch_class("Cb"), Some(11));
+        assert_eq!(pitch_class("H"), None);
+        assert_eq!(pitch_class("C##"), None);
+    }
+
+    #[test]
+    fn note_numbers() {
+        assert_eq!(note_number("A4"), Some(57));
+        assert_eq!(note_number("C#3"), Some(37));
+        assert_eq!(note_number("Cb4"), Some(47));
+        assert_eq!(note_number("Bb-1"), Some(-2));
+        assert_eq!(note_number("A"), None);
+        assert_eq!(note_number("H4"), None);
+        assert_eq!(note_number("A4.5"), None);
+    }
+
+    #[test]
+    fn spelling() {
+        assert_eq!(spell(48, 0), ('C', 0, 4));
+        assert_eq!(spell(58, 0), ('A', 1, 4));
+        assert_eq!(spell(-1, 0), ('B', 0, -1));
+        // B flat in F major, E flat as a lowered degree, G sharp as a raised one, E natural in B flat major
+        assert_eq!(spell(58, -1), ('B', -1, 4));
+        assert_eq!(spell(51, -1), ('E', -1, 4));
+        assert_eq!(spell(54, 2), ('F', 1, 4));
+        assert_eq!(spell(56, 2), ('G', 1, 4));
+        assert_eq!(spell(52, -2), ('E', 0, 4));
+        // E sharp in F# major, C flat in Gb major, on the octave of their letter
+        assert_eq!(spell(53, 6), ('E', 1, 4));
+        assert_eq!(spell(47, -6), ('C', -1, 4));
+        assert_eq!(spell(59, -6), ('C', -1, 5));
+    }
+
+    #[test]
+    fn key_signatures() {
+        assert_eq!(key_alter('F', 1), 1);
+        assert_eq!(key_alter('C', 1), 0);
+        assert_eq!(key_alter('E', -3), -1);
+        assert_eq!(key_alter('D', -3), 0);
+        assert_eq!(key_alter('B', 0), 0);
+    }
+}
@@ -0,0 +1,332 @@
+use std::fs;
+use std::str::FromStr;
+use crate::notes::tuning::Tuning;
+use crate::signals::wavelets::MORLET_HALF_LENGTH;
+use crate::utils::math::{cast, Float};
+
+/// Most frequencies of the linear grid, far more rows than an image can show
+const MAX_LINEAR_FREQUENCIES: f64 = 10_000.0;
+
+pub enum GridType {
+    Notes,
+    Linear,
+    Mel,
+    Bark,
+    Log,
+    File,
+}
+
+impl FromStr for GridType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "notes" => Ok(GridType::Notes),
+            "linear" => Ok(GridType::Linear),
+            "mel" => Ok(GridType::Mel),
+            "bark" => Ok(GridType::Bark),
+            "log" => Ok(GridType::Log),
+            "file" => Ok(GridType::File),
+            _ => Err(format!("Invalid frequency grid '{}'", s).to_string()),
+        }
+    }
+}
+
+/// Frequencies the wavelet transform is computed for, one row of the scalogram each
+pub enum FrequencyGrid {
+    /// Notes of the tuning from C of start_octave, with frequencies_per_note frequencies per note evenly spaced in
+    /// exponential space up to the next one
+    Notes { start_octave: i32, octaves: u32, frequencies_per_note: u32, tuning: Tuning },
+    /// Every step Hz from min to max
+    Linear { min: f64, max: f64, step: f64 },
+    /// bins frequencies from min to max, evenly spaced on the mel scale
+    Mel { min: f64, max: f64, bins: u32 },
+    /// bins frequencies from min to max, evenly spaced on the bark scale
+    Bark { min: f64, max: f64, bins: u32 },
+    /// bins frequencies from min to max, evenly spaced in exponential space
+    Log { min: f64, max: f64, bins: u32 },
+    List(Vec<f64>),
+}
+
+impl FrequencyGrid {
+    /// Reads one frequency in Hz per line in increasing order, ignoring empty lines and lines starting with #
+    pub fn from_file(file_path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(file_path).map_err(|e| format!("Could not read {}: {}", file_path, e))?;
+        Self::parse_list(&content).map_err(|e| format!("{} in {}", e, file_path))
+    }
+
+    fn parse_list(content: &str) -> Result<Self, String> {
+        let mut frequencies = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let frequency = f64::from_str(line).ok().filter(|frequency| frequency.is_finite())
+                .ok_or(format!("Invalid frequency '{}' at line {}", line, index + 1))?;
+            if let Some(previous) = frequencies.last().filter(|previous| **previous >= frequency) {
+                return Err(format!("Frequencies must be strictly increasing, but {} follows {} at line {}", frequency, previous, index + 1));
+            }
+            frequencies.push(frequency);
+        }
+        Ok(FrequencyGrid::List(frequencies))
+    }
+
+    /// Frequencies in Hz in ascending order
+    pub fn frequencies<T: Float>(&self) -> Result<Vec<T>, String> {
+        let frequencies = match self {
+            FrequencyGrid::Notes { start_octave, octaves, frequencies_per_note, tuning } => {
+                if *octaves == 0 || *frequencies_per_note == 0 {
+                    return Err(format!("The notes grid needs at least one octave and one frequency per note, but got {} and {}",
+                                       octaves, frequencies_per_note));
+                }
+                let per_note = *frequencies_per_note as i32;
+                let per_octave = 12 * per_note;
+                (per_octave * start_octave..per_octave * (start_octave + *octaves as i32))
+                    .map(|i| {
+                        let note = tuning.frequency(i.div_euclid(per_note));
+                        let next_note = tuning.frequency(i.div_euclid(per_note) + 1);
+                        note * (next_note / note).powf(i.rem_euclid(per_note) as f64 / per_note as f64)
+                    })
+                    .collect()
+            }
+            FrequencyGrid::Linear { min, max, step } => {
+                validate_range(*min, *max)?;
+                if !(*step > 0.0 && step.is_finite()) {
+                    return Err(format!("Frequency step must be positive, but is {}", step));
+                }
+                let steps = ((max - min) / step + 1e-9).floor();
+                if steps >= MAX_LINEAR_FREQUENCIES {
+                    return Err(format!("A step of {} Hz gives more than {} frequencies from {} Hz to {} Hz, raise it",
+                                       step, MAX_LINEAR_FREQUENCIES, min, max));
+                }
+                let steps = steps as usize;
+                (0..=steps).map(|i| min + i as f64 * step).collect()
+            }
+            FrequencyGrid::Mel { min, max, bins } => spaced(*min, *max, *bins, hz_to_mel, mel_to_hz)?,
+            FrequencyGrid::Bark { min, max, bins } => spaced(*min, *max, *bins, hz_to_bark, bark_to_hz)?,
+            FrequencyGrid::Log { min, max, bins } => spaced(*min, *max, *bins, f64::ln, f64::exp)?,
+            FrequencyGrid::List(frequencies) => {
+                if frequencies.is_empty() {
+                    return Err("The frequency list is empty".to_string());
+                }
+                if frequencies[0] <= 0.0 {
+                    return Err(format!("Frequencies must be positive, but the list contains {}", frequencies[0]));
+                }
+                frequencies.clone()
+            }
+        };
+        Ok(frequencies.into_iter().map(cast).collect())
+    }
+
+    /// Row labels for the frequencies, in the same order: note names on the notes grid (only for the rows
+    /// exactly on a note), frequencies in Hz otherwise
+    pub fn labels(&self, frequencies: &[f64]) -> Vec<String> {
+        match self {
+            FrequencyGrid::Notes { start_octave, frequencies_per_note, tuning, .. } => {
+                let per_note = *frequencies_per_note as usize;
+                (0..frequencies.len())
+                    .map(|i| if i % per_note == 0 { tuning.name(12 * start_octave + (i / per_note) as i32) } else { String::new() })
+                    .collect()
+            }
+            _ => frequencies.iter().map(|frequency| hz_label(*frequency)).collect(),
+        }
+    }
+}
+
+/// Checks that the frequencies (in ascending order) are finite and positive and that the highest one is below the
+/// Nyquist frequency of the signal, and returns how many of the lowest ones need a wavelet longer than the signal,
+/// which can't be analyzed in it
+pub fn validate_frequencies<T: Float>(frequencies: &[T], sample_rate: u32, samples: usize) -> Result<usize, String> {
+    if frequencies.is_empty() {
+        return Err("No frequencies to analyze".to_string());
+    }
+    let invalid = frequencies.iter().map(|frequency| frequency.to_f64().unwrap())
+        .find(|frequency| !(*frequency > 0.0 && frequency.is_finite()));
+    if let Some(frequency) = invalid {
+        return Err(format!("Frequencies must be positive and finite, but got {}", frequency));
+    }
+    let nyquist = sample_rate as f64 / 2.0;
+    let highest = frequencies[frequencies.len() - 1].to_f64().unwrap();
+    if highest >= nyquist {
+        return Err(format!("The highest frequency {:.1} Hz is above the Nyquist frequency {} Hz of the input, lower it \
+                            with --num-octaves, --start-octave or --max-frequency", highest, nyquist));
+    }
+    let duration = samples as f64 / sample_rate as f64;
+    Ok(frequencies.iter().take_while(|frequency| 2.0 * MORLET_HALF_LENGTH / frequency.to_f64().unwrap() > duration).count())
+}
+
+fn validate_range(min: f64, max: f64) -> Result<(), String> {
+    if !(min > 0.0 && max > min && max.is_finite()) {
+        return Err(format!("Invalid frequency range {} Hz - {} Hz, expected 0 < min < max", min, max));
+    }
+    Ok(())
+}
+
+/// bins frequencies from min to max, evenly spaced on the scale given by to_scale and its inverse
+fn spaced(min: f64, max: f64, bins: u32, to_scale: fn(f64) -> f64, from_scale: fn(f64) -> f64) -> Result<Vec<f64>, String> {
+    validate_range(min, max)?;
+    if bins < 2 {
+        return Err(format!("At least 2 frequency bins are needed, but got {}", bins));
+    }
+    let (first, last) = (to_scale(min), to_scale(max));
+    Ok((0..bins)
+        .map(|i| from_scale(first + (last - first) * i as f64 / (bins - 1) as f64))
+        .collect())
+}
+
+fn hz_to_mel(frequency: f64) -> f64 {
+    2595.0 * (1.0 + frequency / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f64) -> f64 {
+    700.0 * (10f64.powf(mel / 2595.0) - 1.0)
+}
+
+/// Traunmüller's approximation
+fn hz_to_bark(frequency: f64) -> f64 {
+    26.81 * frequency / (1960.0 + frequency) - 0.53
+}
+
+fn bark_to_hz(bark: f64) -> f64 {
+    1960.0 * (bark + 0.53) / (26.28 - bark)
+}
+
+/// Short frequency label, e.g. 440 or 1.2k
+fn hz_label(frequency: f64) -> String {
+    if frequency >= 1000.0 {
+        let label = format!("{:.1}", frequency / 1000.0);
+        format!("{}k", label.trim_end_matches(".0"))
+    } else {
+        format!("{:.0}", frequency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notes::grid::{bark_to_hz, FrequencyGrid, hz_to_bark, hz_to_mel, mel_to_hz, validate_frequencies};
+    use crate::notes::tuning::{Tuning, TuningSystem};
+
+    fn assert_frequencies(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-2, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn linear_grid() {
+        let grid = FrequencyGrid::Linear { min: 100.0, max: 150.0, step: 10.0 };
+        assert_frequencies(&grid.frequencies::<f64>().unwrap(), &[100.0, 110.0, 120.0, 130.0, 140.0, 150.0]);
+        assert_eq!(grid.labels(&[100.0, 1000.0, 1250.0]), vec!["100", "1k", "1.2k"]);
+    }
+
+    #[test]
+    fn log_grid() {
+        let grid = FrequencyGrid::Log { min: 100.0, max: 800.0, bins: 4 };
+        assert_frequencies(&grid.frequencies::<f64>().unwrap(), &[100.0, 200.0, 400.0, 800.0]);
+    }
+
+    #[test]
+    fn mel_and_bark_grids() {
+        assert!((hz_to_mel(1000.0) - 1000.0).abs() < 0.1);
+        for frequency in [20.0, 440.0, 8000.0] {
+            assert!((mel_to_hz(hz_to_mel(frequency)) - frequency).abs() < 1e-6);
+            assert!((bark_to_hz(hz_to_bark(frequency)) - frequency).abs() < 1e-6);
+        }
+
+        let mel = FrequencyGrid::Mel { min: 0.1, max: 8000.0, bins: 10 }.frequencies::<f64>().unwrap();
+        let bark = FrequencyGrid::Bark { min: 100.0, max: 8000.0, bins: 10 }.frequencies::<f64>().unwrap();
+        for grid in [mel, bark] {
+            assert_eq!(grid.len(), 10);
+            // perceptual scales are closer to linear at low frequencies than at high ones
+            assert!(grid[1] - grid[0] < grid[9] - grid[8]);
+            assert!(grid[1] / grid[0] > grid[9] / grid[8]);
+        }
+    }
+
+    #[test]
+    fn notes_grid() {
+        let grid = FrequencyGrid::Notes { start_octave: 4, octaves: 1, frequencies_per_note: 2, tuning: Tuning::default() };
+        let frequencies = grid.frequencies::<f64>().unwrap();
+        assert!((frequencies[0] - 261.6).abs() < 0.1);
+        assert!((frequencies[18] - 440.0).abs() < 0.1);
+        assert_eq!(frequencies.len(), 24);
+        assert!((frequencies[23] - 508.3).abs() < 0.1);
+        let labels = grid.labels(&frequencies);
+        assert_eq!(labels[0], "C4");
+        assert_eq!(labels[1], "");
+        assert_eq!(labels[18], "A4");
+    }
+
+    #[test]
+    fn notes_grid_follows_tuning() {
+        let tuning = Tuning::new(415.0, TuningSystem::Just, "C", None).unwrap();
+        let grid = FrequencyGrid::Notes { start_octave: 4, octaves: 1, frequencies_per_note: 2, tuning: tuning.clone() };
+        let frequencies = grid.frequencies::<f64>().unwrap();
+        assert!((frequencies[8] - tuning.frequency(52)).abs() < 1e-9);
+        assert!((frequencies[9] - (tuning.frequency(52) * tuning.frequency(53)).sqrt()).abs() < 1e-9);
+        assert!((frequencies[8] / frequencies[0] - 1.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn frequency_list() {
+        let grid = FrequencyGrid::parse_list("# open strings\n196\n293.66\n\n440.0\n659.3\n").unwrap();
+        assert_frequencies(&grid.frequencies::<f64>().unwrap(), &[196.0, 293.66, 440.0, 659.3]);
+
+        assert_eq!(FrequencyGrid::parse_list("440\nA4\n").err(), Some("Invalid frequency 'A4' at line 2".to_string()));
+        assert_eq!(FrequencyGrid::parse_list("440\ninf\n").err(), Some("Invalid frequency 'inf' at line 2".to_string()));
+        assert_eq!(FrequencyGrid::parse_list("440\nNaN\n").err(), Some("Invalid frequency 'NaN' at line 2".to_string()));
+        assert_eq!(FrequencyGrid::parse_list("196\n440\n# fifth\n293.66\n").err(),
+                   Some("Frequencies must be strictly increasing, but 293.66 follows 440 at line 4".to_string()));
+        assert!(FrequencyGrid::parse_list("196\n196\n").is_err());
+        assert_eq!(FrequencyGrid::parse_list("# nothing\n").unwrap().frequencies::<f32>().err(),
+                   Some("The frequency list is empty".to_string()));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert!(FrequencyGrid::Log { min: 800.0, max: 100.0, bins: 4 }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Mel { min: 100.0, max: 800.0, bins: 1 }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Linear { min: 100.0, max: 800.0, step: 0.0 }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Log { min: f64::NAN, max: 800.0, bins: 4 }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Mel { min: 100.0, max: f64::NAN, bins: 4 }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Bark { min: 100.0, max: f64::INFINITY, bins: 4 }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Linear { min: 100.0, max: 800.0, step: f64::NAN }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Linear { min: 100.0, max: 800.0, step: f64::INFINITY }.frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Linear { min: 100.0, max: 800.0, step: 1e-6 }.frequencies::<f32>().is_err());
+    }
+
+    #[test]
+    fn notes_grid_has_requested_octaves() {
+        for (octaves, frequencies_per_note) in [(1, 1), (9, 1), (3, 4)] {
+            let grid = FrequencyGrid::Notes { start_octave: 1, octaves, frequencies_per_note, tuning: Tuning::default() };
+            let frequencies = grid.frequencies::<f64>().unwrap();
+            assert_eq!(frequencies.len(), (12 * octaves * frequencies_per_note) as usize);
+            assert!((frequencies[0] - 32.70).abs() < 0.01);
+            // the last frequency is just below C of the octave after the last one
+            let next_c = 32.70 * (octaves as f64).exp2();
+            assert!(frequencies[frequencies.len() - 1] < next_c);
+            assert!(frequencies[frequencies.len() - 1] > next_c * (-1.0 / 12.0f64).exp2() * 0.999);
+        }
+        assert!(FrequencyGrid::Notes { start_octave: 1, octaves: 0, frequencies_per_note: 1, tuning: Tuning::default() }
+            .frequencies::<f32>().is_err());
+        assert!(FrequencyGrid::Notes { start_octave: 1, octaves: 2, frequencies_per_note: 0, tuning: Tuning::default() }
+            .frequencies::<f32>().is_err());
+    }
+
+    #[test]
+    fn frequencies_are_validated_against_the_signal() {
+        assert_eq!(validate_frequencies(&[100.0, 3000.0], 8000, 8000), Ok(0));
+        assert_eq!(validate_frequencies(&[100.0, 4000.0], 8000, 8000),
+                   Err("The highest frequency 4000.0 Hz is above the Nyquist frequency 4000 Hz of the input, lower it \
+                        with --num-octaves, --start-octave or --max-frequency".to_string()));
+        // the wavelets of 32 Hz and 48 Hz last 2 s and 1.33 s
+        assert_eq!(validate_frequencies(&[32.0, 48.0, 64.0, 3000.0], 8000, 8000), Ok(2));
+        assert_eq!(validate_frequencies(&[32.0, 48.0], 8000, 8000), Ok(2));
+        assert!(validate_frequencies(&[f64::NAN, 100.0], 8000, 8000).is_err());
+        assert!(validate_frequencies(&[100.0, f64::INFINITY], 8000, 8000).is_err());
+        assert!(validate_frequencies(&[0.0, 100.0], 8000, 8000).is_err());
+        assert!(validate_frequencies::<f64>(&[], 8000, 8000).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::str::FromStr;
+use crate::notes::{A4, NOTE_NAMES, pitch_class};
+
+pub enum TuningSystem {
+    Equal,
+    Just,
+    Pythagorean,
+    Custom,
+}
+
+impl FromStr for TuningSystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "equal" => Ok(TuningSystem::Equal),
+            "just" => Ok(TuningSystem::Just),
+            "pythagorean" => Ok(TuningSystem::Pythagorean),
+            "custom" => Ok(TuningSystem::Custom),
+            _ => Err(format!("Invalid tuning system '{}'", s).to_string()),
+        }
+    }
+}
+
+/// 5-limit just intonation ratios of the 12 degrees from the tonic
+const JUST_RATIOS: [f64; 12] = [1.0, 16.0 / 15.0, 9.0 / 8.0, 6.0 / 5.0, 5.0 / 4.0, 4.0 / 3.0, 45.0 / 32.0, 3.0 / 2.0,
+    8.0 / 5.0, 5.0 / 3.0, 9.0 / 5.0, 15.0 / 8.0];
+/// Ratios of the 12 degrees from the tonic built from pure fifths
+const PYTHAGOREAN_RATIOS: [f64; 12] = [1.0, 256.0 / 243.0, 9.0 / 8.0, 32.0 / 27.0, 81.0 / 64.0, 4.0 / 3.0, 729.0 / 512.0,
+    3.0 / 2.0, 128.0 / 81.0, 27.0 / 16.0, 16.0 / 9.0, 243.0 / 128.0];
+
+/// Frequencies of the notes: the degrees of each octave are placed from the tonic by the cents of the tuning system,
+/// and the whole scale is transposed so that A4 is at the reference pitch
+#[derive(Clone)]
+pub struct Tuning {
+    pub reference: f64,
+    pub tonic: i32,
+    pub cents: [f64; 12],
+}
+
+impl Default for Tuning {
+    /// Equal temperament with A4 = 440 Hz
+    fn default() -> Self {
+        Tuning::equal(440.0)
+    }
+}
+
+impl Tuning {
+    pub fn equal(reference: f64) -> Self {
+        Tuning { reference, tonic: 0, cents: std::array::from_fn(|degree| 100.0 * degree as f64) }
+    }
+
+    /// Tuning from the command line arguments: custom_cents are 12 comma separated offsets from the tonic
+    pub fn new(reference: f64, system: TuningSystem, tonic: &str, custom_cents: Option<&str>) -> Result<Self, String> {
+        if !(reference > 0.0 && reference.is_finite()) {
+            return Err(format!("The reference pitch must be positive, but is {}", reference));
+        }
+        let tonic = pitch_class(tonic).ok_or(format!("Invalid tonic '{}'", tonic))?;
+        let cents = match system {
+            TuningSystem::Equal => return Ok(Tuning::equal(reference)),
+            TuningSystem::Just => JUST_RATIOS.map(ratio_to_cents),
+            TuningSystem::Pythagorean => PYTHAGOREAN_RATIOS.map(ratio_to_cents),
+            TuningSystem::Custom => parse_cents(custom_cents.ok_or("The custom tuning needs --cents")?)?,
+        };
+        Ok(Tuning { reference, tonic, cents })
+    }
+
+    /// Frequency of the note the given number of semitones above C0
+    pub fn frequency(&self, note: i32) -> f64 {
+        self.reference * ((self.cents_above_c0(note) - self.cents_above_c0(A4)) / 1200.0).exp2()
+    }
+
+    /// Position of the note in cents above C0, with the tonic in equal temperament
+    fn cents_above_c0(&self, note: i32) -> f64 {
+        let degree = (note - self.tonic).rem_euclid(12);
+        100.0 * (note - degree) as f64 + self.cents[degree as usize]
+    }
+
+    /// Note closest to the frequency in cents, in semitones above C0
+    pub fn nearest_note(&self, frequency: f64) -> i32 {
+        let equal_tempered = A4 + (12.0 * (frequency / self.reference).log2()).round() as i32;
+        (equal_tempered - 1..=equal_tempered + 1)
+            .min_by(|a, b| {
+                let distance = |note: i32| (frequency / self.frequency(note)).log2().abs();
+                distance(*a).total_cmp(&distance(*b))
+            })
+            .unwrap()
+    }
+
+    /// Name and octave of the note, e.g. A4
+    pub fn name(&self, note: i32) -> String {
+        format!("{}{}", NOTE_NAMES[note.rem_euclid(12) as usize], note.div_euclid(12))
+    }
+}
+
+fn ratio_to_cents(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
+}
+
+fn parse_cents(cents: &str) -> Result<[f64; 12], String> {
+    let values = cents.split(',')
+        .map(|value| f64::from_str(value.trim()).ok().filter(|value| value.is_finite())
+            .ok_or(format!("Invalid cents value '{}'", value.trim())))
+        .collect::<Result<Vec<f64>, String>>()?;
+    values.try_into().map_err(|values: Vec<f64>| format!("Expected 12 cents values, one per degree, but got {}", values.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::notes::tuning::{Tuning, TuningSystem};
+
+    fn assert_frequency(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-2, "Expected {} but got {}", expected, actual);
+    }
+
+    #[test]
+    fn equal_temperament() {
+        let tuning = Tuning::default();
+        assert_frequency(tuning.frequency(57), 440.0);
+        assert_frequency(tuning.frequency(48), 261.63);
+        assert_frequency(tuning.frequency(0), 16.35);
+        assert_frequency(tuning.frequency(-1), 15.43);
+
+        let baroque = Tuning::new(415.0, TuningSystem::Equal, "C", None).unwrap();
+        assert_frequency(baroque.frequency(57), 415.0);
+        assert_frequency(baroque.frequency(58), 439.68);
+    }
+
+    #[test]
+    fn just_and_pythagorean() {
+        let just = Tuning::new(440.0, TuningSystem::Just, "C", None).unwrap();
+        let c4 = just.frequency(48);
+        assert_frequency(c4, 264.0);
+        assert_frequency(just.frequency(52), c4 * 5.0 / 4.0);
+        assert_frequency(just.frequency(57), c4 * 5.0 / 3.0);
+        assert_frequency(just.frequency(60), c4 * 2.0);
+
+        let pythagorean = Tuning::new(440.0, TuningSystem::Pythagorean, "D", None).unwrap();
+        let d4 = pythagorean.frequency(50);
+        assert_frequency(pythagorean.frequency(57), d4 * 3.0 / 2.0);
+        assert_frequency(pythagorean.frequency(49), d4 * 243.0 / 256.0);
+    }
+
+    #[test]
+    fn custom_cents() {
+        let quarter_tones = "0, 100, 200, 300, 400, 500, 600, 700, 800, 950, 1000, 1100";
+        let tuning = Tuning::new(440.0, TuningSystem::Custom, "C", Some(quarter_tones)).unwrap();
+        assert_frequency(tuning.frequency(57), 440.0);
+        assert_frequency(tuning.frequency(48), 440.0 * (-950.0f64 / 1200.0).exp2());
+        assert_frequency(tuning.frequency(58), 440.0 * (50.0f64 / 1200.0).exp2());
+
+        assert_eq!(Tuning::new(440.0, TuningSystem::Custom, "C", Some("0, 100")).err(),
+                   Some("Expected 12 cents values, one per degree, but got 2".to_string()));
+        assert_eq!(Tuning::new(440.0, TuningSystem::Custom, "C", None).err(), Some("The custom tuning needs --cents".to_string()));
+        assert_eq!(Tuning::new(440.0, TuningSystem::Just, "X", None).err(), Some("Invalid tonic 'X'".to_string()));
+        let infinite = "0, 100, 200, 300, 400, 500, 600, 700, 800, 900, 1000, inf";
+        assert_eq!(Tuning::new(440.0, TuningSystem::Custom, "C", Some(infinite)).err(), Some("Invalid cents value 'inf'".to_string()));
+        assert!(Tuning::new(440.0, TuningSystem::Custom, "C", Some(&infinite.replace("inf", "NaN"))).is_err());
+    }
+
+    #[test]
+    fn a4_is_the_reference_in_every_system() {
+        let stretched = "0, 110, 190, 310, 390, 510, 590, 710, 790, 910, 990, 1110";
+        for system in ["equal", "just", "pythagorean", "custom"] {
+            for tonic in ["C", "D", "F#", "A", "Bb"] {
+                let tuning = Tuning::new(415.0, TuningSystem::from_str(system).unwrap(), tonic, Some(stretched)).unwrap();
+                assert_frequency(tuning.frequency(57), 415.0);
+                assert_frequency(tuning.frequency(45), 207.5);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_reference() {
+        for reference in [0.0, -440.0, f64::NAN, f64::INFINITY] {
+            assert!(Tuning::new(reference, TuningSystem::Equal, "C", None).is_err(), "{} was accepted", reference);
+        }
+    }
+
+    #[test]
+    fn note_names() {
+        let tuning = Tuning::default();
+        assert_eq!(tuning.name(tuning.nearest_note(440.0)), "A4");
+        assert_eq!(tuning.name(tuning.nearest_note(277.0)), "C#4");
+        assert_eq!(tuning.name(tuning.nearest_note(16.35)), "C0");
+        assert_eq!(tuning.name(tuning.nearest_note(15.43)), "B-1");
+
+        let baroque = Tuning::equal(415.0);
+        assert_eq!(baroque.name(baroque.nearest_note(415.0)), "A4");
+        assert_eq!(baroque.name(baroque.nearest_note(440.0)), "A#4");
+    }
+}
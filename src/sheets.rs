@@ -0,0 +1,38 @@
+pub mod abc;
+pub mod lilypond;
+pub mod midi;
+pub mod musicxml;
+pub mod score;
+
+use std::fs;
+use crate::sheets::score::Score;
+use crate::utils::file_extension;
+
+#[derive(Clone, Copy)]
+pub enum ScoreFormat {
+    MusicXml,
+    LilyPond,
+    Abc,
+}
+
+impl ScoreFormat {
+    /// Format of a score file from its extension (.musicxml or .xml, .ly, .abc)
+    pub fn from_file_name(file_name: &str) -> Result<Self, String> {
+        match file_extension(file_name) {
+            Some("musicxml") | Some("xml") => Ok(ScoreFormat::MusicXml),
+            Some("ly") => Ok(ScoreFormat::LilyPond),
+            Some("abc") => Ok(ScoreFormat::Abc),
+            _ => Err(format!("Only .musicxml, .xml, .ly and .abc formats are supported for scores, but score file is {}!", file_name)),
+        }
+    }
+}
+
+/// Writes the score in the format given by the extension of the file
+pub fn write_score(score: &Score, file_name: &str) -> Result<(), String> {
+    let text = match ScoreFormat::from_file_name(file_name)? {
+        ScoreFormat::MusicXml => musicxml::musicxml(score),
+        ScoreFormat::LilyPond => lilypond::lilypond(score),
+        ScoreFormat::Abc => abc::abc(score),
+    };
+    fs::write(file_name, text).map_err(|e| format!("Could not write {}: {}", file_name, e))
+}
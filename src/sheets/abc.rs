@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::analysis::key::{Key, Mode};
+use crate::notes::{key_alter, spell};
+use crate::sheets::score::{BASS_CLEF_BELOW, Chord, Score, ScorePitch};
+
+/// ABC tune whose unit note length is one division, one line per measure
+pub fn abc(score: &Score) -> String {
+    let parameters = &score.parameters;
+    let clef = if score.average_pitch() < BASS_CLEF_BELOW { " clef=bass" } else { "" };
+    let mut tune = String::new();
+    writeln!(tune, "X:1\nT:Transcription\nM:{}/{}\nL:1/{}\nQ:1/4={}\nK:{}{}",
+             parameters.time_signature.beats, parameters.time_signature.beat_type, 4 * parameters.divisions,
+             parameters.tempo.round(), key_symbol(&parameters.key), clef).unwrap();
+    let fifths = parameters.key.fifths();
+    for measure in &score.measures {
+        // accidentals last until the end of the measure, so the alteration of the signature is written back after
+        // another one of the same note
+        let mut accidentals = HashMap::new();
+        let chords: Vec<String> = measure.iter().map(|chord| chord_symbol(chord, fifths, &mut accidentals)).collect();
+        writeln!(tune, "{} |", chords.join(" ")).unwrap();
+    }
+    tune
+}
+
+/// Tonic and mode of the K: field, e.g. Bb or F#m
+fn key_symbol(key: &Key) -> String {
+    format!("{}{}", key.tonic_name(), if key.mode == Mode::Minor { "m" } else { "" })
+}
+
+fn chord_symbol(chord: &Chord, fifths: i32, accidentals: &mut HashMap<(char, i32), i32>) -> String {
+    let duration = if chord.duration == 1 { String::new() } else { chord.duration.to_string() };
+    let pitches: Vec<String> = chord.pitches.iter()
+        .map(|pitch| {
+            let (step, alter, octave) = spell(pitch.pitch as i32 - 12, fifths);
+            let previous = accidentals.insert((step, octave), alter).unwrap_or(key_alter(step, fifths));
+            let accidental = match alter {
+                _ if alter == previous => "",
+                1 => "^",
+                -1 => "_",
+                _ => "=",
+            };
+            format!("{}{}", accidental, pitch_symbol(step, octave))
+        })
+        .collect();
+    let tie = |pitch: &ScorePitch| if pitch.tie_start { "-" } else { "" };
+    match pitches.len() {
+        0 => format!("z{}", duration),
+        1 => format!("{}{}{}", pitches[0], duration, tie(&chord.pitches[0])),
+        _ => {
+            let pitches: Vec<String> = chord.pitches.iter().zip(pitches).map(|(pitch, symbol)| symbol + tie(pitch)).collect();
+            format!("[{}]{}", pitches.join(""), duration)
+        }
+    }
+}
+
+/// ABC note letter with octave marks, C being middle C and c the octave above
+fn pitch_symbol(step: char, octave: i32) -> String {
+    if octave >= 5 {
+        format!("{}{}", step.to_ascii_lowercase(), "'".repeat((octave - 5) as usize))
+    } else {
+        format!("{}{}", step, ",".repeat((4 - octave) as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::analysis::key::Key;
+    use crate::analysis::transcription::Note;
+    use crate::sheets::abc::{abc, pitch_symbol};
+    use crate::sheets::score::{quantize, ScoreParameters, TimeSignature};
+
+    #[test]
+    fn pitches() {
+        assert_eq!(pitch_symbol('C', 4), "C");
+        assert_eq!(pitch_symbol('C', 5), "c");
+        assert_eq!(pitch_symbol('A', 6), "a'");
+        assert_eq!(pitch_symbol('B', 2), "B,,");
+    }
+
+    #[test]
+    fn abc_tune() {
+        let notes = [Note { pitch: 61, onset: 0.0, duration: 0.75, velocity: 100 },
+                     Note { pitch: 64, onset: 0.0, duration: 0.75, velocity: 100 },
+                     Note { pitch: 60, onset: 1.0, duration: 0.25, velocity: 100 },
+                     Note { pitch: 69, onset: 1.25, duration: 1.0, velocity: 100 }];
+        let parameters = |key| ScoreParameters { tempo: 120.0, time_signature: TimeSignature::from_str("3/4").unwrap(),
+                                                 divisions: 4, key: Key::from_str(key).unwrap() };
+        let tune = abc(&quantize(&notes, parameters("C major")));
+
+        assert!(tune.starts_with("X:1\nT:Transcription\nM:3/4\nL:1/16\nQ:1/4=120\nK:C\n"), "{}", tune);
+        // the C after the C sharp of the same measure needs a natural
+        assert!(tune.ends_with("[^CE]6 z2 =C2 A2- |\nA6 z2 z4 |\n"), "{}", tune);
+
+        // in D major the C sharp is in the signature, the C natural isn't
+        let tune = abc(&quantize(&notes, parameters("D major")));
+        assert!(tune.contains("K:D\n[CE]6 z2 =C2 A2- |\n"), "{}", tune);
+        // in F minor the C sharp is the D flat of the signature, and the E and A need naturals in every measure
+        let tune = abc(&quantize(&notes, parameters("F minor")));
+        assert!(tune.contains("K:Fm\n[D=E]6 z2 C2 =A2- |\n=A6 z2 z4 |\n"), "{}", tune);
+    }
+}
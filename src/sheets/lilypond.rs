@@ -0,0 +1,98 @@
+use std::fmt::Write;
+use crate::analysis::key::Key;
+use crate::notes::spell;
+use crate::sheets::score::{BASS_CLEF_BELOW, Chord, note_value, Score};
+
+/// LilyPond score with absolute pitches, one line per measure
+pub fn lilypond(score: &Score) -> String {
+    let parameters = &score.parameters;
+    let clef = if score.average_pitch() < BASS_CLEF_BELOW { "bass" } else { "treble" };
+    let mut ly = String::new();
+    ly.push_str("\\version \"2.24.0\"\n\\score {\n  \\new Staff {\n");
+    let fifths = parameters.key.fifths();
+    writeln!(ly, "    \\clef {}\n    \\key {}\n    \\time {}/{}\n    \\tempo 4 = {}",
+             clef, key_symbol(&parameters.key), parameters.time_signature.beats, parameters.time_signature.beat_type,
+             parameters.tempo.round()).unwrap();
+    for measure in &score.measures {
+        let chords: Vec<String> = measure.iter().map(|chord| chord_symbol(chord, parameters.divisions, fifths)).collect();
+        writeln!(ly, "    {} |", chords.join(" ")).unwrap();
+    }
+    ly.push_str("  }\n  \\layout { }\n}\n");
+    ly
+}
+
+/// Tonic and mode of the \\key command, e.g. bes \\major
+fn key_symbol(key: &Key) -> String {
+    let (step, alter, _) = spell(key.tonic as i32, key.fifths());
+    format!("{} \\{}", step_name(step, alter), key.mode.name())
+}
+
+fn chord_symbol(chord: &Chord, divisions: u32, fifths: i32) -> String {
+    let (value, dotted) = note_value(chord.duration, divisions);
+    let duration = format!("{}{}", value, if dotted { "." } else { "" });
+    let pitches: Vec<String> = chord.pitches.iter().map(|pitch| pitch_symbol(pitch.pitch, fifths)).collect();
+    let all_tied = chord.pitches.iter().all(|pitch| pitch.tie_start);
+    match chord.pitches.len() {
+        0 => format!("r{}", duration),
+        1 => format!("{}{}{}", pitches[0], duration, if all_tied { "~" } else { "" }),
+        _ => {
+            let pitches: Vec<String> = chord.pitches.iter().zip(pitches)
+                .map(|(pitch, symbol)| if pitch.tie_start { symbol + "~" } else { symbol })
+                .collect();
+            format!("<{}>{}", pitches.join(" "), duration)
+        }
+    }
+}
+
+/// Absolute LilyPond pitch of a MIDI note spelled in the key signature, c' being middle C
+fn pitch_symbol(pitch: u8, fifths: i32) -> String {
+    let (step, alter, octave) = spell(pitch as i32 - 12, fifths);
+    let marks = if octave >= 3 { "'".repeat((octave - 3) as usize) } else { ",".repeat((3 - octave) as usize) };
+    format!("{}{}", step_name(step, alter), marks)
+}
+
+/// Dutch note name, e.g. fis or bes, with the short es and as for E and A flat
+fn step_name(step: char, alter: i32) -> String {
+    match (step, alter) {
+        ('E', -1) => "es".to_string(),
+        ('A', -1) => "as".to_string(),
+        (_, 1) => format!("{}is", step.to_ascii_lowercase()),
+        (_, -1) => format!("{}es", step.to_ascii_lowercase()),
+        _ => step.to_ascii_lowercase().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::analysis::key::Key;
+    use crate::analysis::transcription::Note;
+    use crate::sheets::lilypond::{lilypond, pitch_symbol};
+    use crate::sheets::score::{quantize, ScoreParameters, TimeSignature};
+
+    #[test]
+    fn pitches() {
+        assert_eq!(pitch_symbol(60, 0), "c'");
+        assert_eq!(pitch_symbol(61, 0), "cis'");
+        assert_eq!(pitch_symbol(48, 0), "c");
+        assert_eq!(pitch_symbol(35, 0), "b,,");
+        assert_eq!(pitch_symbol(81, 0), "a''");
+        assert_eq!(pitch_symbol(70, -2), "bes'");
+        assert_eq!(pitch_symbol(63, -2), "es'");
+        assert_eq!(pitch_symbol(68, -4), "as'");
+        assert_eq!(pitch_symbol(59, -6), "ces'");
+    }
+
+    #[test]
+    fn lilypond_score() {
+        let notes = [Note { pitch: 61, onset: 0.0, duration: 0.75, velocity: 100 },
+                     Note { pitch: 64, onset: 0.0, duration: 0.75, velocity: 100 },
+                     Note { pitch: 69, onset: 1.25, duration: 1.0, velocity: 100 }];
+        let parameters = ScoreParameters { tempo: 120.0, time_signature: TimeSignature::from_str("3/4").unwrap(), divisions: 4,
+                                           key: Key::from_str("Bb major").unwrap() };
+        let ly = lilypond(&quantize(&notes, parameters));
+
+        assert!(ly.contains("\\clef treble\n    \\key bes \\major\n    \\time 3/4\n    \\tempo 4 = 120\n"), "{}", ly);
+        assert!(ly.contains("    <des' e'>4. r8 r8 a'8~ |\n    a'4. r8 r4 |\n"), "{}", ly);
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use crate::analysis::transcription::Note;
+
+/// Ticks per quarter note
+const DIVISION: u16 = 480;
+/// Tempo of the written file: note times are in seconds, so any tempo works as long as ticks are computed from it
+const TEMPO_BPM: f64 = 120.0;
+
+/// Writes the notes as a single track Standard MIDI File (format 0)
+pub fn write_midi(notes: &[Note], file_name: &str) -> Result<(), String> {
+    fs::write(file_name, midi_bytes(notes)).map_err(|e| format!("Could not write {}: {}", file_name, e))
+}
+
+fn midi_bytes(notes: &[Note]) -> Vec<u8> {
+    let ticks_per_second = DIVISION as f64 * TEMPO_BPM / 60.0;
+    let ticks = |seconds: f64| (seconds * ticks_per_second).round() as u32;
+
+    // (tick, is note on, pitch, velocity), note offs first when simultaneous so that repeated notes aren't cut
+    let mut events: Vec<(u32, bool, u8, u8)> = notes.iter()
+        .flat_map(|note| [(ticks(note.onset), true, note.pitch, note.velocity),
+                          (ticks(note.onset + note.duration).max(ticks(note.onset) + 1), false, note.pitch, 0)])
+        .collect();
+    events.sort_by_key(|(tick, is_on, pitch, _)| (*tick, *is_on, *pitch));
+
+    let mut track = Vec::new();
+    let microseconds_per_quarter = (60_000_000.0 / TEMPO_BPM) as u32;
+    track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03]);
+    track.extend_from_slice(&microseconds_per_quarter.to_be_bytes()[1..]);
+    let mut previous_tick = 0;
+    for (tick, is_on, pitch, velocity) in events {
+        write_variable_length(&mut track, tick - previous_tick);
+        track.extend_from_slice(&[if is_on { 0x90 } else { 0x80 }, pitch, velocity]);
+        previous_tick = tick;
+    }
+    track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    let mut bytes = Vec::with_capacity(22 + track.len());
+    bytes.extend_from_slice(b"MThd");
+    bytes.extend_from_slice(&6u32.to_be_bytes());
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&DIVISION.to_be_bytes());
+    bytes.extend_from_slice(b"MTrk");
+    bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&track);
+    bytes
+}
+
+/// MIDI variable length quantity: 7 bits per byte, most significant first, with the high bit set on all but the last
+fn write_variable_length(bytes: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::transcription::Note;
+    use crate::sheets::midi::{midi_bytes, write_variable_length};
+
+    #[test]
+    fn variable_length_quantities() {
+        for (value, expected) in [(0, vec![0x00]), (0x7F, vec![0x7F]), (0x80, vec![0x81, 0x00]),
+                                  (0x2000, vec![0xC0, 0x00]), (0x0FFFFFFF, vec![0xFF, 0xFF, 0xFF, 0x7F])] {
+            let mut bytes = Vec::new();
+            write_variable_length(&mut bytes, value);
+            assert_eq!(bytes, expected);
+        }
+    }
+
+    #[test]
+    fn midi_file_layout() {
+        let notes = [Note { pitch: 60, onset: 0.0, duration: 0.5, velocity: 100 },
+                     Note { pitch: 64, onset: 0.5, duration: 0.25, velocity: 90 }];
+        let bytes = midi_bytes(&notes);
+
+        assert_eq!(&bytes[..14], &[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xE0]);
+        assert_eq!(&bytes[14..18], b"MTrk");
+        let track = &bytes[22..];
+        assert_eq!(u32::from_be_bytes(bytes[18..22].try_into().unwrap()) as usize, track.len());
+        assert_eq!(track, &[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+                            0x00, 0x90, 60, 100,
+                            // 0.5 s at 120 bpm is a quarter note, 480 ticks; the note off comes first
+                            0x83, 0x60, 0x80, 60, 0,
+                            0x00, 0x90, 64, 90,
+                            0x81, 0x70, 0x80, 64, 0,
+                            0x00, 0xFF, 0x2F, 0x00]);
+    }
+}
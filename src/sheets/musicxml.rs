@@ -0,0 +1,107 @@
+use std::fmt::Write;
+use crate::notes::spell;
+use crate::sheets::score::{BASS_CLEF_BELOW, Chord, note_value, Score};
+
+/// Single part MusicXML document in partwise layout
+pub fn musicxml(score: &Score) -> String {
+    let parameters = &score.parameters;
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+    xml.push_str("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">\n");
+    xml.push_str("<score-partwise version=\"4.0\">\n");
+    xml.push_str("  <part-list>\n    <score-part id=\"P1\">\n      <part-name>Transcription</part-name>\n    </score-part>\n  </part-list>\n");
+    xml.push_str("  <part id=\"P1\">\n");
+    for (index, measure) in score.measures.iter().enumerate() {
+        writeln!(xml, "    <measure number=\"{}\">", index + 1).unwrap();
+        if index == 0 {
+            let (sign, line) = if score.average_pitch() < BASS_CLEF_BELOW { ("F", 4) } else { ("G", 2) };
+            writeln!(xml, "      <attributes>\n        <divisions>{}</divisions>\n        \
+                           <key><fifths>{}</fifths><mode>{}</mode></key>\n        \
+                           <time><beats>{}</beats><beat-type>{}</beat-type></time>\n        \
+                           <clef><sign>{}</sign><line>{}</line></clef>\n      </attributes>",
+                     parameters.divisions, parameters.key.fifths(), parameters.key.mode.name(),
+                     parameters.time_signature.beats, parameters.time_signature.beat_type, sign, line).unwrap();
+            writeln!(xml, "      <direction placement=\"above\">\n        <direction-type>\n          \
+                           <metronome><beat-unit>quarter</beat-unit><per-minute>{}</per-minute></metronome>\n        \
+                           </direction-type>\n        <sound tempo=\"{}\"/>\n      </direction>",
+                     parameters.tempo.round(), parameters.tempo.round()).unwrap();
+        }
+        for chord in measure {
+            write_chord(&mut xml, chord, parameters.divisions, parameters.key.fifths());
+        }
+        xml.push_str("    </measure>\n");
+    }
+    xml.push_str("  </part>\n</score-partwise>\n");
+    xml
+}
+
+fn write_chord(xml: &mut String, chord: &Chord, divisions: u32, fifths: i32) {
+    let (value, dotted) = note_value(chord.duration, divisions);
+    let note_type = match value {
+        1 => "whole",
+        2 => "half",
+        4 => "quarter",
+        8 => "eighth",
+        16 => "16th",
+        32 => "32nd",
+        _ => "64th",
+    };
+    let dot = if dotted { "<dot/>" } else { "" };
+    if chord.pitches.is_empty() {
+        writeln!(xml, "      <note><rest/><duration>{}</duration><voice>1</voice><type>{}</type>{}</note>",
+                 chord.duration, note_type, dot).unwrap();
+    }
+    for (index, pitch) in chord.pitches.iter().enumerate() {
+        let (step, alter, octave) = spell(pitch.pitch as i32 - 12, fifths);
+        let alter = if alter != 0 { format!("<alter>{}</alter>", alter) } else { String::new() };
+        let chord_tag = if index > 0 { "<chord/>" } else { "" };
+        let mut ties = String::new();
+        let mut tied = String::new();
+        if pitch.tie_stop {
+            ties.push_str("<tie type=\"stop\"/>");
+            tied.push_str("<tied type=\"stop\"/>");
+        }
+        if pitch.tie_start {
+            ties.push_str("<tie type=\"start\"/>");
+            tied.push_str("<tied type=\"start\"/>");
+        }
+        let notations = if tied.is_empty() { String::new() } else { format!("<notations>{}</notations>", tied) };
+        writeln!(xml, "      <note>{}<pitch><step>{}</step>{}<octave>{}</octave></pitch><duration>{}</duration>{}\
+                       <voice>1</voice><type>{}</type>{}{}</note>",
+                 chord_tag, step, alter, octave, chord.duration, ties, note_type, dot, notations).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::analysis::key::Key;
+    use crate::analysis::transcription::Note;
+    use crate::sheets::musicxml::musicxml;
+    use crate::sheets::score::{quantize, ScoreParameters, TimeSignature};
+
+    #[test]
+    fn musicxml_notes() {
+        let notes = [Note { pitch: 61, onset: 0.0, duration: 0.75, velocity: 100 },
+                     Note { pitch: 64, onset: 0.0, duration: 0.75, velocity: 100 },
+                     Note { pitch: 69, onset: 1.25, duration: 1.0, velocity: 100 }];
+        let parameters = ScoreParameters { tempo: 120.0, time_signature: TimeSignature::from_str("3/4").unwrap(), divisions: 4,
+                                           key: Key::from_str("A major").unwrap() };
+        let xml = musicxml(&quantize(&notes, parameters));
+
+        assert!(xml.contains("<key><fifths>3</fifths><mode>major</mode></key>"));
+        assert!(xml.contains("<time><beats>3</beats><beat-type>4</beat-type></time>"));
+        assert!(xml.contains("<clef><sign>G</sign><line>2</line></clef>"));
+        assert!(xml.contains("<per-minute>120</per-minute>"));
+        assert!(xml.contains("<note><pitch><step>C</step><alter>1</alter><octave>4</octave></pitch><duration>6</duration>\
+                              <voice>1</voice><type>quarter</type><dot/></note>"));
+        assert!(xml.contains("<note><chord/><pitch><step>E</step><octave>4</octave></pitch><duration>6</duration>"));
+        // the rest starts off the beat, so it is cut at the next one
+        assert!(xml.contains("<note><rest/><duration>2</duration><voice>1</voice><type>eighth</type></note>\n      \
+                              <note><rest/><duration>2</duration>"));
+        // the A crosses the bar line
+        assert!(xml.contains("<step>A</step><octave>4</octave></pitch><duration>2</duration><tie type=\"start\"/>"));
+        assert!(xml.contains("<duration>6</duration><tie type=\"stop\"/><voice>1</voice>"));
+        assert_eq!(xml.matches("<measure ").count(), 2);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use crate::analysis::key::Key;
+use crate::analysis::transcription::Note;
+
+/// MIDI pitch under which the average pitch of the score is written in bass clef
+pub const BASS_CLEF_BELOW: f64 = 60.0;
+
+pub struct TimeSignature {
+    pub beats: u32,
+    pub beat_type: u32,
+}
+
+impl FromStr for TimeSignature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid time signature '{}'", s);
+        let (beats, beat_type) = s.split_once('/').ok_or_else(invalid)?;
+        let beats = u32::from_str(beats).map_err(|_| invalid())?;
+        let beat_type = u32::from_str(beat_type).map_err(|_| invalid())?;
+        if beats == 0 || ![1, 2, 4, 8, 16].contains(&beat_type) {
+            return Err(invalid());
+        }
+        Ok(TimeSignature { beats, beat_type })
+    }
+}
+
+pub struct ScoreParameters {
+    /// Quarter notes per minute
+    pub tempo: f64,
+    pub time_signature: TimeSignature,
+    /// Quantization grid in subdivisions of a quarter note, a power of 2 from 1 to 16
+    pub divisions: u32,
+    /// Key of the signature and of the spelling of the pitches
+    pub key: Key,
+}
+
+/// Pitch of a chord, tied to the same pitch in the previous and/or next chord
+#[derive(Debug, PartialEq)]
+pub struct ScorePitch {
+    /// MIDI note number
+    pub pitch: u8,
+    pub tie_start: bool,
+    pub tie_stop: bool,
+}
+
+/// Notes starting together, or a rest if there are none, lasting a duration with a single symbol (possibly dotted)
+#[derive(Debug, PartialEq)]
+pub struct Chord {
+    pub pitches: Vec<ScorePitch>,
+    /// In divisions of a quarter note
+    pub duration: u32,
+}
+
+pub struct Score {
+    pub parameters: ScoreParameters,
+    pub measures: Vec<Vec<Chord>>,
+}
+
+impl Score {
+    /// Average MIDI pitch of all the notes, middle C if there are none
+    pub fn average_pitch(&self) -> f64 {
+        let pitches: Vec<f64> = self.measures.iter().flatten()
+            .flat_map(|chord| chord.pitches.iter().map(|pitch| pitch.pitch as f64))
+            .collect();
+        if pitches.is_empty() { 60.0 } else { pitches.iter().sum::<f64>() / pitches.len() as f64 }
+    }
+}
+
+impl ScoreParameters {
+    pub fn measure_length(&self) -> u32 {
+        self.time_signature.beats * self.beat_length()
+    }
+
+    /// Length of a beat in divisions, which must be a whole number of them
+    pub fn beat_length(&self) -> u32 {
+        self.divisions * 4 / self.time_signature.beat_type
+    }
+}
+
+/// Quantizes the notes to the divisions of the tempo, then cuts the timeline wherever a note starts or stops and at
+/// every bar line. Notes sounding across a cut are tied.
+pub fn quantize(notes: &[Note], parameters: ScoreParameters) -> Score {
+    let divisions_per_second = parameters.tempo / 60.0 * parameters.divisions as f64;
+    let quantized: Vec<(u32, u32, u8)> = notes.iter()
+        .map(|note| {
+            let start = (note.onset * divisions_per_second).round() as u32;
+            let end = ((note.onset + note.duration) * divisions_per_second).round() as u32;
+            (start, end.max(start + 1), note.pitch)
+        })
+        .collect();
+
+    let measure_length = parameters.measure_length();
+    let last = quantized.iter().map(|(_, end, _)| *end).max().unwrap_or(0);
+    let measures_num = last.div_ceil(measure_length).max(1);
+    let mut cuts: BTreeSet<u32> = (0..=measures_num).map(|measure| measure * measure_length).collect();
+    cuts.extend(quantized.iter().flat_map(|(start, end, _)| [*start, *end]));
+
+    let mut measures: Vec<Vec<Chord>> = (0..measures_num).map(|_| Vec::new()).collect();
+    let cuts: Vec<u32> = cuts.into_iter().collect();
+    for segment in cuts.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        let mut sounding: Vec<(u8, bool, bool)> = quantized.iter()
+            .filter(|(note_start, note_end, _)| *note_start <= start && start < *note_end)
+            .map(|(note_start, note_end, pitch)| (*pitch, *note_end > end, *note_start < start))
+            .collect();
+        sounding.sort_by_key(|(pitch, ..)| *pitch);
+        sounding.dedup_by_key(|(pitch, ..)| *pitch);
+
+        let pieces = split_duration(start, end - start, parameters.beat_length(), parameters.divisions);
+        for (index, duration) in pieces.iter().enumerate() {
+            let pitches = sounding.iter()
+                .map(|(pitch, tie_start, tie_stop)| ScorePitch {
+                    pitch: *pitch,
+                    tie_start: *tie_start || index + 1 < pieces.len(),
+                    tie_stop: *tie_stop || index > 0,
+                })
+                .collect();
+            measures[(start / measure_length) as usize].push(Chord { pitches, duration: *duration });
+        }
+    }
+    Score { parameters, measures }
+}
+
+/// Splits a duration from start into ones that can be written with a single (possibly dotted) note, longest first, after
+/// cutting it at the first beat boundary when it starts off the beat
+pub fn split_duration(start: u32, duration: u32, beat: u32, divisions: u32) -> Vec<u32> {
+    let to_beat = ((beat - start % beat) % beat).min(duration);
+    let mut pieces = single_notes(to_beat, divisions);
+    pieces.extend(single_notes(duration - to_beat, divisions));
+    pieces
+}
+
+/// Splits a duration into ones that can be written with a single (possibly dotted) note, longest first
+fn single_notes(duration: u32, divisions: u32) -> Vec<u32> {
+    let mut lengths: Vec<u32> = (0..7)
+        .map(|power| (4 * divisions) >> power)
+        .filter(|length| *length > 0)
+        .flat_map(|length| [length, length * 3 / 2].into_iter().filter(move |dotted| length == *dotted || length.is_multiple_of(2)))
+        .collect();
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    let mut pieces = Vec::new();
+    let mut rest = duration;
+    while rest > 0 {
+        let length = *lengths.iter().find(|length| **length <= rest).unwrap();
+        pieces.push(length);
+        rest -= length;
+    }
+    pieces
+}
+
+/// Note value of a duration returned by split_duration, as the denominator of a whole note (4 for a quarter note)
+/// and whether it is dotted
+pub fn note_value(duration: u32, divisions: u32) -> (u32, bool) {
+    let whole = 4 * divisions;
+    if whole.is_multiple_of(duration) {
+        (whole / duration, false)
+    } else {
+        (whole * 3 / (2 * duration), true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::analysis::key::Key;
+    use crate::analysis::transcription::Note;
+    use crate::sheets::score::{Chord, note_value, quantize, ScoreParameters, ScorePitch, split_duration, TimeSignature};
+
+    fn parameters(time_signature: &str) -> ScoreParameters {
+        ScoreParameters { tempo: 120.0, time_signature: TimeSignature::from_str(time_signature).unwrap(), divisions: 4,
+                          key: Key::from_str("C major").unwrap() }
+    }
+
+    fn note(pitch: u8, onset: f64, duration: f64) -> Note {
+        Note { pitch, onset, duration, velocity: 100 }
+    }
+
+    fn pitch(pitch: u8, tie_start: bool, tie_stop: bool) -> ScorePitch {
+        ScorePitch { pitch, tie_start, tie_stop }
+    }
+
+    #[test]
+    fn time_signatures() {
+        assert_eq!(parameters("4/4").measure_length(), 16);
+        assert_eq!(parameters("3/4").measure_length(), 12);
+        assert_eq!(parameters("6/8").measure_length(), 12);
+        assert!(TimeSignature::from_str("4/3").is_err());
+        assert!(TimeSignature::from_str("four").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(split_duration(0, 16, 4, 4), vec![16]);
+        assert_eq!(split_duration(0, 12, 4, 4), vec![12]);
+        assert_eq!(split_duration(0, 11, 4, 4), vec![8, 3]);
+        assert_eq!(split_duration(4, 5, 4, 4), vec![4, 1]);
+        assert_eq!(split_duration(0, 3, 1, 1), vec![3]);
+        // off the beat, the piece up to the next beat comes first
+        assert_eq!(split_duration(2, 8, 4, 4), vec![2, 6]);
+        assert_eq!(split_duration(3, 14, 4, 4), vec![1, 12, 1]);
+        assert_eq!(split_duration(1, 2, 4, 4), vec![2]);
+        assert_eq!(split_duration(6, 6, 2, 4), vec![6]);
+        assert_eq!(note_value(4, 4), (4, false));
+        assert_eq!(note_value(6, 4), (4, true));
+        assert_eq!(note_value(1, 4), (16, false));
+        assert_eq!(note_value(24, 4), (1, true));
+    }
+
+    #[test]
+    fn quantizes_notes_with_ties_and_rests() {
+        // at 120 bpm a quarter note lasts 0.5 s; slightly off times are rounded to the 16th
+        let notes = [note(60, 0.01, 0.49), note(64, 0.5, 0.26), note(67, 0.5, 1.5), note(72, 1.76, 0.74)];
+        let score = quantize(&notes, parameters("4/4"));
+
+        assert_eq!(score.measures.len(), 2);
+        assert_eq!(score.measures[0], vec![
+            Chord { pitches: vec![pitch(60, false, false)], duration: 4 },
+            Chord { pitches: vec![pitch(64, false, false), pitch(67, true, false)], duration: 2 },
+            Chord { pitches: vec![pitch(67, true, true)], duration: 2 },
+            Chord { pitches: vec![pitch(67, true, true)], duration: 6 },
+            Chord { pitches: vec![pitch(67, false, true), pitch(72, true, false)], duration: 2 },
+        ]);
+        assert_eq!(score.measures[1], vec![
+            Chord { pitches: vec![pitch(72, false, true)], duration: 4 },
+            Chord { pitches: vec![], duration: 12 },
+        ]);
+    }
+}
@@ -1,6 +1,10 @@
+use std::f64::consts::PI;
+use std::slice::Chunks;
 use num_complex::Complex;
-use crate::utils::math::FloatType;
+use rayon::prelude::*;
+use crate::utils::math::{cast, Float};
 
+pub mod synthesis;
 pub mod transform;
 pub mod wavelets;
 
@@ -10,14 +14,40 @@ pub struct SignalSample<T> {
     pub samples: Vec<T>,
 }
 
-impl SignalSample<Complex<FloatType>> {
-    pub fn from_wavelet(length_t: FloatType, sample_rate: u32, signal_fn: &impl Fn(FloatType) -> Complex<FloatType>) -> Self {
-        let sample_rate_FloatType = sample_rate as FloatType;
-        let samples = (length_t * sample_rate_FloatType) as usize;
+/// Wavelet transform coefficients, one row per frequency (highest first) and one column every hop_size input samples,
+/// stored row by row in a single buffer
+pub struct Scalogram<T> {
+    pub sample_rate: u32,
+    pub hop_size: usize,
+    pub frames: usize,
+    /// Whether the rows were convolved at the decimated rates of decimation_level rather than at the full sample rate
+    pub multirate: bool,
+    pub coefficients: Vec<T>,
+}
+
+impl<T> Scalogram<T> {
+    pub fn row(&self, index: usize) -> &[T] {
+        &self.coefficients[index * self.frames..(index + 1) * self.frames]
+    }
+
+    pub fn rows(&self) -> Chunks<'_, T> {
+        self.coefficients.chunks(self.frames)
+    }
+
+    /// Seconds between two frames
+    pub fn frame_duration(&self) -> f64 {
+        self.hop_size as f64 / self.sample_rate as f64
+    }
+}
+
+impl<T: Float> SignalSample<Complex<T>> {
+    pub fn from_wavelet(length_t: T, sample_rate: u32, signal_fn: &impl Fn(T) -> Complex<T>) -> Self {
+        let sample_rate_float: T = cast(sample_rate);
+        let samples = (length_t * sample_rate_float).to_usize().unwrap();
         let mut result = Vec::with_capacity(samples);
 
         for i in 0..samples {
-            result.push(signal_fn(i as FloatType / sample_rate_FloatType));
+            result.push(signal_fn(cast::<T>(i) / sample_rate_float));
         }
         Self {
             sample_rate,
@@ -25,3 +55,73 @@ impl SignalSample<Complex<FloatType>> {
         }
     }
 }
+
+/// Zero crossings of the sinc kernel on each side of a resampled sample
+const RESAMPLING_HALF_WIDTH: f64 = 32.0;
+
+impl<T: Float> SignalSample<T> {
+    /// The signal at another sample rate, interpolated with a Hann-windowed sinc that also low-passes it below the new
+    /// Nyquist frequency when the rate goes down
+    pub fn resample(&self, sample_rate: u32) -> SignalSample<T> {
+        if sample_rate == self.sample_rate {
+            return SignalSample { sample_rate, samples: self.samples.clone() };
+        }
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        let cutoff = ratio.min(1.0);
+        let half_width = RESAMPLING_HALF_WIDTH / cutoff;
+        let length = (self.samples.len() as f64 * ratio).round() as usize;
+        let samples = (0..length).into_par_iter()
+            .map(|n| {
+                let center = n as f64 / ratio;
+                let first = (center - half_width).ceil().max(0.0) as usize;
+                let last = ((center + half_width).floor() as usize).min(self.samples.len().saturating_sub(1));
+                let mut sum = 0.0;
+                for k in first..=last {
+                    let distance = center - k as f64;
+                    let sinc = if distance == 0.0 { 1.0 } else { (PI * cutoff * distance).sin() / (PI * cutoff * distance) };
+                    let window = 0.5 + 0.5 * (PI * distance / half_width).cos();
+                    sum += self.samples[k].to_f64().unwrap() * cutoff * sinc * window;
+                }
+                cast(sum)
+            })
+            .collect();
+        SignalSample { sample_rate, samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use crate::signals::SignalSample;
+
+    fn sines(sample_rate: u32, frequencies: &[f64]) -> SignalSample<f64> {
+        SignalSample {
+            sample_rate,
+            samples: (0..sample_rate)
+                .map(|n| frequencies.iter().map(|frequency| (TAU * frequency * n as f64 / sample_rate as f64).sin()).sum())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn resamples_between_rates() {
+        // away from the edges, where the kernel runs out of samples
+        let middle = |signal: &SignalSample<f64>| {
+            let margin = signal.sample_rate as usize / 10;
+            signal.samples[margin..signal.samples.len() - margin].to_vec()
+        };
+        let max_error = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+
+        let upsampled = sines(8000, &[440.0, 1234.5]).resample(11025);
+        assert_eq!(upsampled.sample_rate, 11025);
+        assert_eq!(upsampled.samples.len(), 11025);
+        let error = max_error(&middle(&upsampled), &middle(&sines(11025, &[440.0, 1234.5])));
+        assert!(error < 0.01, "Error {} when upsampling", error);
+
+        // the 6 kHz sine is above the new Nyquist frequency and must not fold back to 2 kHz
+        let downsampled = sines(16000, &[440.0, 6000.0]).resample(8000);
+        assert_eq!(downsampled.samples.len(), 8000);
+        let error = max_error(&middle(&downsampled), &middle(&sines(8000, &[440.0])));
+        assert!(error < 0.01, "Error {} when downsampling", error);
+    }
+}
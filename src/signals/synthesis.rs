@@ -0,0 +1,107 @@
+use std::f64::consts::{PI, TAU};
+use num_complex::Complex;
+use rayon::prelude::*;
+use crate::signals::{Scalogram, SignalSample};
+use crate::signals::transform::decimation_level;
+use crate::signals::wavelets::{morlet_spectrum, MORLET_HALF_LENGTH};
+use crate::utils::math::{cast, Float};
+
+/// Output samples computed by a thread at a time
+const CHUNK_SIZE: usize = 4096;
+
+/// Signal of the given length whose transform is (approximately) the scalogram of the given frequencies (in ascending
+/// order), such as a masked transform: every row is an oscillator at its frequency, its magnitude and phase
+/// interpolated between the frames, and the rows are weighted so that a tone at the frequency of any row comes back at
+/// its amplitude. Frequencies between the rows of a sparse grid come back weaker, as the wavelets barely overlap.
+/// A scalogram without frames gives silence.
+pub fn resynthesize<T: Float>(scalogram: &Scalogram<Complex<T>>, frequencies: &[f64], samples: usize) -> SignalSample<T> {
+    if scalogram.frames == 0 {
+        return SignalSample { sample_rate: scalogram.sample_rate, samples: vec![T::zero(); samples] };
+    }
+    let sample_rate = scalogram.sample_rate as f64;
+    let hop_size = scalogram.hop_size as f64;
+    // the coefficient of a cosine at the frequency of row i is response(i, frequency) / 2 times its phase
+    let response = |row: usize, frequency: f64| {
+        morlet_spectrum(frequencies[row])(frequency).norm() * frequencies[row] / (2.0 * MORLET_HALF_LENGTH)
+    };
+    let gains: Vec<f64> = frequencies.iter()
+        .map(|frequency| 2.0 / (0..frequencies.len()).map(|row| response(row, *frequency)).sum::<f64>())
+        .collect();
+    // the phase of a coefficient is the one of the signal at the center of its wavelet, this many samples after its
+    // frame, the wavelet of a decimated row ending decimation - 1 samples later
+    let offsets: Vec<f64> = frequencies.iter()
+        .map(|frequency| {
+            let decimation = if scalogram.multirate { (1 << decimation_level(*frequency, scalogram.sample_rate)) as f64 } else { 1.0 };
+            let wavelet_len = (2.0 * MORLET_HALF_LENGTH / frequency * sample_rate / decimation).floor();
+            decimation - 1.0 + (wavelet_len - 1.0) * decimation - MORLET_HALF_LENGTH / frequency * sample_rate
+        })
+        .collect();
+    let rows: Vec<&[Complex<T>]> = scalogram.rows().rev().collect();
+
+    let mut output = vec![T::zero(); samples];
+    output.par_chunks_mut(CHUNK_SIZE).enumerate().for_each(|(chunk, values)| {
+        for (row, coefficients) in rows.iter().enumerate() {
+            let phase_step = TAU * frequencies[row] / sample_rate * hop_size;
+            for (index, value) in values.iter_mut().enumerate() {
+                let position = ((chunk * CHUNK_SIZE + index) as f64 - offsets[row]) / hop_size;
+                let frame = (position.floor().max(0.0) as usize).min(scalogram.frames - 1);
+                let next = (frame + 1).min(scalogram.frames - 1);
+                let fraction = position - frame as f64;
+                let (current, following) = (coefficients[frame], coefficients[next]);
+                let (mut magnitude, mut phase) = (current.norm().to_f64().unwrap(), current.arg().to_f64().unwrap());
+                if next == frame || !(0.0..=1.0).contains(&fraction) {
+                    // before the first frame or after the last one, the oscillator goes on at the row frequency
+                    phase += fraction * phase_step;
+                } else {
+                    // the phase advances by the step of the row frequency, corrected by the deviation of the signal
+                    let deviation = following.arg().to_f64().unwrap() - phase - phase_step;
+                    phase += fraction * (phase_step + (deviation + PI).rem_euclid(TAU) - PI);
+                    magnitude += fraction * (following.norm().to_f64().unwrap() - magnitude);
+                }
+                *value = *value + cast(gains[row] * magnitude * phase.cos());
+            }
+        }
+    });
+    SignalSample { sample_rate: scalogram.sample_rate, samples: output }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+    use num_complex::Complex;
+    use crate::analysis::{test_scalogram, TEST_SAMPLE_RATE};
+    use crate::signals::Scalogram;
+    use crate::signals::synthesis::resynthesize;
+
+    #[test]
+    fn resynthesizes_tones_on_the_grid() {
+        // A3 then C5 over A3
+        let sample_rate = TEST_SAMPLE_RATE;
+        let samples: Vec<f32> = (0..2 * sample_rate)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64;
+                let high = if t >= 1.0 { 0.5 * (TAU * 523.25 * t).sin() } else { 0.0 };
+                ((TAU * 220.0 * t).sin() + high) as f32
+            })
+            .collect();
+        let (scalogram, frequencies) = test_scalogram(samples.clone(), 2, 4, 20);
+
+        let frequencies: Vec<f64> = frequencies.iter().map(|frequency| *frequency as f64).collect();
+        let resynthesized = resynthesize(&scalogram, &frequencies, samples.len());
+
+        assert_eq!(resynthesized.samples.len(), samples.len());
+        // away from the edges and the start of the C5, where the wavelets see the change
+        for range in [2000..6000, 10000..14000] {
+            let error: f64 = range.clone().map(|n| (resynthesized.samples[n] - samples[n]).powi(2) as f64).sum();
+            let energy: f64 = range.clone().map(|n| samples[n].powi(2) as f64).sum();
+            assert!((error / energy).sqrt() < 0.05, "Relative error {} in {:?}", (error / energy).sqrt(), range);
+        }
+    }
+
+    #[test]
+    fn resynthesizes_silence_without_frames() {
+        let scalogram = Scalogram::<Complex<f32>> { sample_rate: 8000, hop_size: 20, frames: 0, multirate: true, coefficients: vec![] };
+        let resynthesized = resynthesize(&scalogram, &[220.0, 440.0], 100);
+        assert_eq!(resynthesized.samples, vec![0.0; 100]);
+    }
+}
@@ -1,49 +1,278 @@
 use std::sync::Arc;
 use rayon::prelude::*;
+use realfft::RealFftPlanner;
 use rustfft::{Fft, FftPlanner};
 use rustfft::num_complex::Complex;
-use crate::signals::SignalSample;
-use crate::signals::wavelets::MORLET_HALF_LENGTH;
-use crate::utils::math::{FloatType, re};
-
-/// wavelet_factory: from (frequency, sample rate) to a SignalSample lasting 1/frequency
-pub(crate) fn wavelet_transform(signal: &SignalSample<FloatType>,
-                                wavelet_factory: &(impl Fn(FloatType, u32) -> SignalSample<Complex<FloatType>> + Sync),
-                                frequencies: &[FloatType],
-                                n_threads: u32) -> Vec<Vec<Complex<FloatType>>> {
+use num_traits::Zero;
+use crate::signals::{Scalogram, SignalSample};
+use crate::signals::wavelets::{morlet_bandwidth, morlet_spectrum, MORLET_HALF_LENGTH};
+use crate::utils::math::{cast, Float};
+
+/// Minimum number of samples per period of a frequency when convolving it at a decimated sample rate
+const MIN_SAMPLES_PER_PERIOD: f64 = 4.0;
+/// Length of the anti-aliasing filter applied before each decimation by 2
+const LOWPASS_TAPS: usize = 33;
+
+pub struct TransformParameters {
+    /// If true, each octave is convolved at the lowest sample rate that can represent it
+    pub multirate: bool,
+    /// Number of input samples between two output frames
+    pub hop_size: usize,
+    /// If true, the wavelets are assumed to have no negative frequencies, so only the positive half of the spectrum is
+    /// convolved, and only the samples of the convolution kept by the hop size are transformed back
+    pub analytic: bool,
+}
+
+/// Where the kernels convolved with the signal come from
+pub enum WaveletSource<'a, T: Float> {
+    /// From (frequency, sample rate) to a SignalSample lasting 1/frequency, transformed to the fft length.
+    /// When multirate is enabled, decimated octaves request the wavelet at (frequency * 2^level, sample rate) to get it
+    /// sampled at sample rate / 2^level, so the wavelet must be scale invariant as the morlet is.
+    Sampled(&'a (dyn Fn(T, u32) -> SignalSample<Complex<T>> + Sync)),
+    /// Morlet spectra computed in closed form at the fft length
+    Spectral,
+}
+
+/// FFT planners, which cache the plans of the lengths they were asked for, so that transforms of many signals of
+/// similar lengths don't plan them again
+pub struct FftPlanners<T: Float> {
+    complex: FftPlanner<T>,
+    real: RealFftPlanner<T>,
+}
+
+impl<T: Float> FftPlanners<T> {
+    pub fn new() -> Self {
+        FftPlanners { complex: FftPlanner::new(), real: RealFftPlanner::new() }
+    }
+}
+
+impl<T: Float> Default for FftPlanners<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scalogram of the signal at the given frequencies, convolving it with the wavelets of the source
+pub fn wavelet_transform<T: Float>(signal: &SignalSample<T>,
+                                   wavelets: &WaveletSource<T>,
+                                   frequencies: &[T],
+                                   parameters: &TransformParameters) -> Scalogram<Complex<T>> {
+    wavelet_transform_with(signal, wavelets, frequencies, parameters, &mut FftPlanners::new())
+}
+
+/// Same as wavelet_transform, planning the FFTs with the given planners
+pub fn wavelet_transform_with<T: Float>(signal: &SignalSample<T>,
+                                        wavelets: &WaveletSource<T>,
+                                        frequencies: &[T],
+                                        parameters: &TransformParameters,
+                                        planners: &mut FftPlanners<T>) -> Scalogram<Complex<T>> {
     let sample_rate = signal.sample_rate;
-    let signal = &signal.samples;
+    let hop_size = parameters.hop_size;
+    let frames = signal.samples.len().div_ceil(hop_size);
     let frequencies_num = frequencies.len();
 
-    let max_wavelet_samples = (2.0 * MORLET_HALF_LENGTH * (sample_rate as FloatType) / frequencies[0]).ceil() as usize;
-    let max_convolution_len = signal.len() + max_wavelet_samples - 1;
+    let levels: Vec<usize> = frequencies.iter()
+        .map(|frequency| if parameters.multirate { decimation_level(*frequency, sample_rate) } else { 0 })
+        .collect();
+    let pyramid = decimation_pyramid(&signal.samples, *levels.iter().max().unwrap_or(&0));
+
+    let octaves: Vec<Option<Octave<T>>> = pyramid.iter().enumerate()
+        .map(|(level, samples)| {
+            let lowest_frequency = frequencies.iter().zip(levels.iter())
+                .filter(|(_, frequency_level)| **frequency_level == level)
+                .map(|(frequency, _)| *frequency)
+                .fold(T::max_value(), T::min);
+            if lowest_frequency == T::max_value() {
+                return None;
+            }
+            let max_wavelet_samples = (cast::<T>(2.0 * MORLET_HALF_LENGTH) * cast(sample_rate) / (lowest_frequency * cast(1 << level))).ceil();
+            let max_convolution_len = samples.len() + max_wavelet_samples.to_usize().unwrap() - 1;
+
+            // frames of decimated octaves are interpolated from consecutive samples, so only full rate ones are folded,
+            // by the largest power of 2 dividing the hop size
+            let stride = if parameters.analytic && level == 0 { hop_size & hop_size.wrapping_neg() } else { 1 };
+            Some(Octave::new(samples, max_convolution_len, stride, planners))
+        })
+        .collect();
+
+    let mut coefficients = vec![Complex::zero(); frequencies_num * frames];
+    // runs on the current rayon pool, one workspace per split of the rows
+    coefficients
+        .par_chunks_mut(frames)
+        .enumerate()
+        .for_each_init(Workspace::default, |workspace, (row_index, row)| {
+            let frequency_index = frequencies_num - row_index - 1;
+            let frequency_hz = frequencies[frequency_index];
+            let level = levels[frequency_index];
+            let octave = octaves[level].as_ref().unwrap();
+            let decimation = 1 << level;
+
+            let (convolution, wavelet_len) = match wavelets {
+                WaveletSource::Spectral => {
+                    let octave_sample_rate = cast::<T>(sample_rate) / cast(decimation);
+                    let wavelet_len = (cast::<T>(2.0 * MORLET_HALF_LENGTH) / frequency_hz * octave_sample_rate).to_usize().unwrap();
+                    let convolution = spectral_convolution(octave, frequency_hz, octave_sample_rate, wavelet_len,
+                                                           parameters.analytic, workspace);
+                    (convolution, wavelet_len)
+                }
+                WaveletSource::Sampled(wavelet_factory) => {
+                    let wavelet = wavelet_factory(frequency_hz * cast(decimation), sample_rate);
+                    let convolution = fourier_convolution(octave, &wavelet.samples, parameters.analytic, workspace);
+                    (convolution, wavelet.samples.len())
+                }
+            };
+            let normalization = T::one() / cast(wavelet_len);
+            if decimation == 1 {
+                // the first frame is the sample wavelet_len - 1 of the convolution, of which only every stride-th
+                // sample was transformed back when it was folded
+                let stride = octave.fold.as_ref().map_or(1, |fold| fold.stride);
+                let frames = convolution[(wavelet_len - 1) / stride..].iter().step_by(hop_size / stride);
+                for (coefficient, value) in row.iter_mut().zip(frames) {
+                    *coefficient = value * normalization;
+                }
+            } else {
+                let convolution = &convolution[(wavelet_len - 1)..(octave.signal_len + wavelet_len - 1)];
+                let phase_step = T::TAU() * frequency_hz * cast(decimation) / cast(sample_rate);
+                resample(convolution, decimation, hop_size, phase_step, normalization, row);
+            }
+        });
+    Scalogram {
+        sample_rate,
+        hop_size,
+        frames,
+        multirate: parameters.multirate,
+        coefficients,
+    }
+}
+
+/// Buffers reused by a thread across all the frequencies it convolves, resized when the fft length changes
+#[derive(Default)]
+struct Workspace<T> {
+    spectrum: Vec<Complex<T>>,
+    scratch: Vec<Complex<T>>,
+}
+
+impl<T: Float> Workspace<T> {
+    fn prepare(&mut self, octave: &Octave<T>) {
+        self.spectrum.resize(octave.forward_fft.len(), Complex::zero());
+        let scratch_len = octave.forward_fft.get_inplace_scratch_len()
+            .max(octave.inverse_fft.get_inplace_scratch_len())
+            .max(octave.fold.as_ref().map_or(0, |fold| fold.inverse_fft.get_inplace_scratch_len()));
+        self.scratch.resize(scratch_len, Complex::zero());
+    }
+}
+
+/// Decimated signal spectrum (positive frequencies only) and the plans to convolve it, for all frequencies sharing the same decimation level
+struct Octave<T> {
+    signal_len: usize,
+    signal_fourier: Vec<Complex<T>>,
+    forward_fft: Arc<dyn Fft<T>>,
+    inverse_fft: Arc<dyn Fft<T>>,
+    fold: Option<Fold<T>>,
+}
+
+impl<T: Float> Octave<T> {
+    /// Spectrum of the samples zero padded to at least min_len, and the plans to convolve it, folding analytic
+    /// convolutions by stride (at most half the fft length) if it's above 1
+    fn new(samples: &[T], min_len: usize, stride: usize, planners: &mut FftPlanners<T>) -> Self {
+        let signal_fourier = in_place_fourier(samples, min_len, &mut planners.real);
+        let convolution_len = 2 * (signal_fourier.len() - 1);
+        let stride = stride.min(convolution_len / 2);
+        let fold = (stride > 1).then(|| Fold {
+            stride,
+            inverse_fft: planners.complex.plan_fft_inverse(convolution_len / stride),
+            twiddles: (0..convolution_len)
+                .map(|k| Complex::from_polar(T::one(), T::TAU() * cast(k) / cast(convolution_len)))
+                .collect(),
+        });
+        Octave {
+            signal_len: samples.len(),
+            signal_fourier,
+            forward_fft: planners.complex.plan_fft_forward(convolution_len),
+            inverse_fft: planners.complex.plan_fft_inverse(convolution_len),
+            fold,
+        }
+    }
+}
+
+/// Inverse transform of every stride-th sample of an analytic convolution only. With N the fft length and M = N /
+/// stride, the samples r + stride * m are the inverse transform of length M of the spectrum folded as
+/// Z'(j) = sum over q of Z(j + q M) e^(2 pi i (j + q M) r / N), which only has to sum the positive half of Z.
+struct Fold<T> {
+    stride: usize,
+    inverse_fft: Arc<dyn Fft<T>>,
+    /// e^(2 pi i k / N) for k from 0 to N - 1
+    twiddles: Vec<Complex<T>>,
+}
+
+/// Number of times the signal can be halved while keeping at least MIN_SAMPLES_PER_PERIOD samples per period
+pub(crate) fn decimation_level<T: Float>(frequency: T, sample_rate: u32) -> usize {
+    let ratio = cast::<T>(sample_rate) / (cast::<T>(MIN_SAMPLES_PER_PERIOD) * frequency);
+    if ratio < cast(2.0) {
+        return 0;
+    }
+    ratio.log2().floor().to_usize().unwrap()
+}
+
+/// Signal low-passed and decimated by 2 once per level, the first element being the signal itself
+fn decimation_pyramid<T: Float>(signal: &[T], levels: usize) -> Vec<Vec<T>> {
+    let kernel = lowpass_kernel(LOWPASS_TAPS);
+    let mut pyramid = vec![signal.to_vec()];
+    for level in 0..levels {
+        let decimated = decimate(&pyramid[level], &kernel);
+        pyramid.push(decimated);
+    }
+    pyramid
+}
 
-    let signal_fourier = in_place_fourier(signal, max_convolution_len);
-    let results: Vec<(usize, FloatType)> = (0..frequencies.len())
-        .map(|index| (index, frequencies[frequencies.len() - index - 1]))
+/// Blackman-windowed sinc with cutoff at a quarter of the sample rate (the Nyquist frequency after decimation)
+fn lowpass_kernel<T: Float>(taps: usize) -> Vec<T> {
+    let center = cast::<T>(taps - 1) / cast(2.0);
+    let kernel: Vec<T> = (0..taps)
+        .map(|n| {
+            let x = cast::<T>(n) - center;
+            let sinc = if x == T::zero() { cast(0.5) } else { (T::FRAC_PI_2() * x).sin() / (T::PI() * x) };
+            let phase = T::TAU() * cast(n) / cast(taps - 1);
+            sinc * (cast::<T>(0.42) - cast::<T>(0.5) * phase.cos() + cast::<T>(0.08) * (phase + phase).cos())
+        })
         .collect();
+    let sum: T = kernel.iter().copied().sum();
+    kernel.iter().map(|k| *k / sum).collect()
+}
 
-    let mut planner = FftPlanner::<FloatType>::new();
-    let forward_fft = planner.plan_fft_forward(signal_fourier.len());
-    let inverse_fft = planner.plan_fft_inverse(signal_fourier.len());
-    let results: Vec<(usize, Vec<Complex<FloatType>>)> = results
-        .par_rchunks((frequencies_num as FloatType / n_threads as FloatType).ceil() as usize)
-        .flat_map(|elements| {
-            elements.iter().map(|(index, frequency_hz)| {
-                let wavelet = wavelet_factory(*frequency_hz, sample_rate);
-                let convolution =
-                    fourier_convolution(&signal_fourier, &wavelet.samples, &forward_fft, &inverse_fft);
-                //todo: merge these two
-                let convolution: Vec<Complex<FloatType>> = convolution.iter().map(|c| *c / (wavelet.samples.len() as FloatType)).collect();
-                (*index, convolution[(wavelet.samples.len() - 1)..(signal.len() + wavelet.samples.len() - 1)].to_vec())
-            }).collect::<Vec<(usize, Vec<Complex<FloatType>>)>>()
+/// Filters the signal with a symmetric kernel (without delay) and keeps every other sample
+fn decimate<T: Float>(signal: &[T], kernel: &[T]) -> Vec<T> {
+    let half = kernel.len() / 2;
+    (0..signal.len().div_ceil(2))
+        .map(|m| {
+            let center = 2 * m + half;
+            kernel.iter().enumerate()
+                .filter(|(k, _)| center >= *k && center - k < signal.len())
+                .map(|(k, tap)| signal[center - k] * *tap)
+                .sum()
         })
+        .collect()
+}
+
+/// Interpolates a decimated wavelet coefficient row at every hop_size samples of the full sample rate, scaled by
+/// normalization, into output. The row is demodulated (its phase advances by phase_step every sample) before the
+/// linear interpolation, so that magnitude does not drop between samples.
+fn resample<T: Float>(row: &[Complex<T>], decimation: usize, hop_size: usize, phase_step: T, normalization: T,
+                      output: &mut [Complex<T>]) {
+    let demodulation = Complex::from_polar(T::one(), -phase_step);
+    let remodulations: Vec<Complex<T>> = (0..decimation)
+        .map(|offset| Complex::from_polar(normalization, phase_step * cast(offset) / cast(decimation)))
         .collect();
-    let mut transform = vec![Vec::new(); frequencies_num];
-    for result in results {
-        transform[result.0] = result.1;
+    // the decimated convolution at position p is evaluated at the same time as the full rate one at
+    // p * decimation - (decimation - 1), as the wavelet ends decimation - 1 samples later
+    for (frame, coefficient) in output.iter_mut().enumerate() {
+        let j = frame * hop_size;
+        let previous = j.div_ceil(decimation).min(row.len() - 1);
+        let next = (previous + 1).min(row.len() - 1);
+        let offset = (j + decimation - 1) % decimation;
+        let fraction: T = cast::<T>(offset) / cast(decimation);
+        *coefficient = (row[previous] * (T::one() - fraction) + row[next] * demodulation * fraction) * remodulations[offset];
     }
-    transform
 }
 
 fn round_to_power_2(n: i64) -> i64 {
@@ -55,39 +284,101 @@ fn round_to_power_2(n: i64) -> i64 {
     smaller * 2
 }
 
-fn fourier_convolution(signal_fourier: &[Complex<FloatType>],
-                       kernel: &[Complex<FloatType>],
-                       forward_fft: &Arc<dyn Fft<FloatType>>,
-                       inverse_fft: &Arc<dyn Fft<FloatType>>) -> Vec<Complex<FloatType>> {
-    let convolution_len = signal_fourier.len();
+/// Convolution of a real signal, given the positive half of its spectrum, with a complex kernel, computed in the
+/// workspace buffers. The negative frequencies of the signal are the conjugate of the positive ones; if the kernel is
+/// analytic they're skipped altogether, as its spectrum is zero there, and the convolution is folded if the octave
+/// allows it, starting at its sample (kernel length - 1) % stride.
+fn fourier_convolution<'a, T: Float>(octave: &Octave<T>,
+                                     kernel: &[Complex<T>],
+                                     analytic: bool,
+                                     workspace: &'a mut Workspace<T>) -> &'a [Complex<T>] {
+    workspace.prepare(octave);
+    let spectrum = &mut workspace.spectrum;
+    spectrum[..kernel.len()].copy_from_slice(kernel);
+    spectrum[kernel.len()..].fill(Complex::zero());
+    octave.forward_fft.process_with_scratch(spectrum, &mut workspace.scratch);
+
+    multiply_and_invert(octave, analytic, kernel.len(), workspace)
+}
 
-    let mut kernel_transform = pad(kernel, convolution_len, re(0.0));
+/// Same as fourier_convolution with a morlet wavelet of frequency_hz sampled at sample_rate, but with the kernel
+/// spectrum computed in closed form, only within the band where it isn't negligible
+fn spectral_convolution<'a, T: Float>(octave: &Octave<T>,
+                                      frequency_hz: T,
+                                      sample_rate: T,
+                                      wavelet_len: usize,
+                                      analytic: bool,
+                                      workspace: &'a mut Workspace<T>) -> &'a [Complex<T>] {
+    workspace.prepare(octave);
+    let signal_fourier = &octave.signal_fourier;
+    let bin_width = sample_rate / cast(octave.inverse_fft.len());
+    let bandwidth = morlet_bandwidth(frequency_hz);
+    let first_bin = ((frequency_hz - bandwidth) / bin_width).ceil().max(T::zero()).to_usize().unwrap();
+    let last_bin = ((frequency_hz + bandwidth) / bin_width).floor().to_usize().unwrap().min(signal_fourier.len() - 1);
+
+    let spectrum = &mut workspace.spectrum;
+    spectrum.fill(Complex::zero());
+    // the dft of a sampled signal is its continuous transform times the sample rate
+    let wavelet_spectrum = morlet_spectrum(frequency_hz);
+    for (bin, value) in spectrum.iter_mut().enumerate().take(last_bin + 1).skip(first_bin) {
+        *value = wavelet_spectrum(cast::<T>(bin) * bin_width) * sample_rate;
+    }
 
-    forward_fft.process(&mut kernel_transform);
+    multiply_and_invert(octave, analytic, wavelet_len, workspace)
+}
 
-    let mut signal_transform = vec![re(0.0); convolution_len];
-    let signal_transform_slice = &mut signal_transform;
-    for i in 0..convolution_len {
-        signal_transform_slice[i] = signal_fourier[i] * kernel_transform[i] / (convolution_len as FloatType);
+/// Multiplies the kernel spectrum in the workspace by the signal spectrum and transforms it back in place, folded
+/// for an analytic kernel if the octave allows it
+fn multiply_and_invert<'a, T: Float>(octave: &Octave<T>,
+                                     analytic: bool,
+                                     kernel_len: usize,
+                                     workspace: &'a mut Workspace<T>) -> &'a [Complex<T>] {
+    let signal_fourier = &octave.signal_fourier;
+    let convolution_len = octave.inverse_fft.len();
+    let half_len = signal_fourier.len();
+    let spectrum = &mut workspace.spectrum;
+
+    let scale = T::one() / cast(convolution_len);
+    for i in 0..half_len {
+        spectrum[i] = spectrum[i] * signal_fourier[i] * scale;
+    }
+    if !analytic {
+        for i in half_len..convolution_len {
+            spectrum[i] = spectrum[i] * signal_fourier[convolution_len - i].conj() * scale;
+        }
+        octave.inverse_fft.process_with_scratch(spectrum, &mut workspace.scratch);
+        return spectrum;
     }
 
-    inverse_fft.process(signal_transform_slice);
-
-    signal_transform
+    let Some(fold) = &octave.fold else {
+        spectrum[half_len..].fill(Complex::zero());
+        octave.inverse_fft.process_with_scratch(spectrum, &mut workspace.scratch);
+        return spectrum;
+    };
+    let folded_len = convolution_len / fold.stride;
+    let offset = (kernel_len - 1) % fold.stride;
+    for j in 0..folded_len {
+        // in place, as the bin j is only read by the sum of the bin j itself
+        let mut sum = Complex::zero();
+        for k in (j..half_len).step_by(folded_len) {
+            sum = sum + spectrum[k] * fold.twiddles[k * offset % convolution_len];
+        }
+        spectrum[j] = sum;
+    }
+    let folded = &mut spectrum[..folded_len];
+    fold.inverse_fft.process_with_scratch(folded, &mut workspace.scratch);
+    folded
 }
 
-fn in_place_fourier(signal: &[FloatType], length: usize) -> Vec<Complex<FloatType>> {
-    let signal_len = signal.len();
+/// Positive half of the spectrum of the real signal, zero padded to the next power of 2 of length
+fn in_place_fourier<T: Float>(signal: &[T], length: usize, planner: &mut RealFftPlanner<T>) -> Vec<Complex<T>> {
     let convolution_len: usize = round_to_power_2(length as i64) as usize;
 
-    let mut signal_transform = vec![re(0.0); convolution_len];
-    for i in 0..signal_len {
-        signal_transform[i] = re(signal[i]);
-    }
-
-    let mut planner = FftPlanner::<FloatType>::new();
     let fft = planner.plan_fft_forward(convolution_len);
-    fft.process(&mut signal_transform);
+
+    let mut padded_signal = pad(signal, convolution_len, T::zero());
+    let mut signal_transform = fft.make_output_vec();
+    fft.process(&mut padded_signal, &mut signal_transform).unwrap();
 
     signal_transform
 }
@@ -101,10 +392,13 @@ fn pad<T: Copy>(vector: &[T], new_length: usize, default: T) -> Vec<T> {
 #[cfg(test)]
 mod tests {
     use num_complex::Complex;
+    use realfft::RealFftPlanner;
     use rustfft::FftPlanner;
     use crate::utils::math::{assert_complex_vec, FloatType, i, re};
     use crate::signals::SignalSample;
-    use crate::signals::transform::{fourier_convolution, in_place_fourier, pad, round_to_power_2, wavelet_transform};
+    use crate::signals::transform::{Octave, Workspace, decimate, decimation_level, fourier_convolution, in_place_fourier, lowpass_kernel, pad, round_to_power_2, wavelet_transform, wavelet_transform_with, FftPlanners, TransformParameters, WaveletSource, LOWPASS_TAPS};
+    use crate::signals::wavelets::{morlet, MORLET_HALF_LENGTH};
+    use std::f32::consts::TAU as PI2;
 
     #[test]
     fn test_convolution_real_part() {
@@ -112,7 +406,7 @@ mod tests {
         let wavelet = vec![re(1.0), -re(2.0), re(0.5)];
 
         let convolution = complex_convolution(&signal, &wavelet);
-        assert_complex_vec(&convolution, &vec![re(0.3), -re(0.1), -re(1.85), re(2.95), -re(1.9), re(0.35)]);
+        assert_complex_vec(&convolution, &[re(0.3), -re(0.1), -re(1.85), re(2.95), -re(1.9), re(0.35)]);
     }
 
     #[test]
@@ -121,7 +415,7 @@ mod tests {
         let wavelet = vec![i(1.0), -i(2.0), i(0.5)];
 
         let convolution = complex_convolution(&signal, &wavelet);
-        assert_complex_vec(&convolution, &vec![i(0.3), -i(0.1), -i(1.85), i(2.95), -i(1.9), i(0.35)]);
+        assert_complex_vec(&convolution, &[i(0.3), -i(0.1), -i(1.85), i(2.95), -i(1.9), i(0.35)]);
     }
 
     #[test]
@@ -130,7 +424,7 @@ mod tests {
         let wavelet = vec![0.4 + i(1.0), 0.6 + i(-2.0), -0.2 + i(0.5)];
 
         let convolution = complex_convolution(&signal, &wavelet);
-        assert_complex_vec(&convolution, &vec![0.12 + i(0.3), 0.38 - i(0.1), -0.16 - i(1.85),
+        assert_complex_vec(&convolution, &[0.12 + i(0.3), 0.38 - i(0.1), -0.16 - i(1.85),
                                                -0.42 + i(2.95), 0.62 - i(1.9), -0.14 + i(0.35)]);
     }
 
@@ -144,13 +438,13 @@ mod tests {
         };
         let frequencies = vec![1.0];
         let transform = wavelet_transform(&signal_sample,
-                                          &|_, _| SignalSample {
+                                          &WaveletSource::Sampled(&|_, _| SignalSample {
                                               sample_rate: 3,
                                               samples: vec![0.4 + i(1.0), 0.6 - i(2.0), -0.2 + i(0.5)],
-                                          },
-                                          &frequencies, 1);
+                                          }),
+                                          &frequencies, &TransformParameters { multirate: false, hop_size: 1, analytic: false });
 
-        assert_complex_vec(&transform[0].to_vec(), &vec![(-0.16 - i(1.85)) / 3.0, (-0.42 + i(2.95)) / 3.0,
+        assert_complex_vec(transform.row(0), &[(-0.16 - i(1.85)) / 3.0, (-0.42 + i(2.95)) / 3.0,
                                                          (0.62 - i(1.9)) / 3.0, (-0.14 + i(0.35)) / 3.0]);
     }
 
@@ -174,11 +468,8 @@ mod tests {
         let signal = vec![0.3, 0.5, -1.0, 0.0];
         let wavelet = vec![re(0.2), re(-0.7), re(0.4)];
 
-        let signal_fourier = in_place_fourier(&signal, 8);
-
-        let mut planner = FftPlanner::<FloatType>::new();
-        let fourier_convolution = fourier_convolution(&signal_fourier, &wavelet,
-                                                      &planner.plan_fft_forward(8), &planner.plan_fft_inverse(8));
+        let octave = Octave::new(&signal, 8, 1, &mut FftPlanners::new());
+        let fourier_convolution = fourier_convolution(&octave, &wavelet, false, &mut Workspace::default()).to_vec();
 
         let convolution = complex_convolution(&signal, &wavelet);
         let convolution = pad(&convolution, 8, re(0.0));
@@ -186,6 +477,232 @@ mod tests {
         assert_complex_vec(&convolution, &fourier_convolution);
     }
 
+    #[test]
+    fn test_decimation_level() {
+        assert_eq!(decimation_level(30.0, 44100), 8);
+        assert_eq!(decimation_level(440.0, 44100), 4);
+        assert_eq!(decimation_level(5000.0, 44100), 1);
+        assert_eq!(decimation_level(8000.0, 44100), 0);
+        assert_eq!(decimation_level(20000.0, 44100), 0);
+    }
+
+    #[test]
+    fn test_decimation_filters_high_frequencies() {
+        let kernel = lowpass_kernel(LOWPASS_TAPS);
+        let sine = |cycles_per_sample: FloatType| (0..1000)
+            .map(|n| (PI2 * cycles_per_sample * n as FloatType).sin())
+            .collect::<Vec<FloatType>>();
+
+        let rms = |signal: &[FloatType]| (signal.iter().map(|x| x * x).sum::<FloatType>() / signal.len() as FloatType).sqrt();
+
+        let low = decimate(&sine(0.05), &kernel);
+        assert_eq!(low.len(), 500);
+        let low_rms = rms(&low[100..400]);
+        assert!((low_rms - 0.5_f32.sqrt()).abs() < 0.01, "Low frequency rms is {}", low_rms);
+
+        let high = decimate(&sine(0.4), &kernel);
+        let high_rms = rms(&high[100..400]);
+        assert!(high_rms < 1e-3, "High frequency rms is {}", high_rms);
+    }
+
+    #[test]
+    fn test_multirate_transform_matches_full_rate() {
+        let sample_rate = 8000;
+        let signal_sample = SignalSample {
+            sample_rate,
+            samples: (0..3 * sample_rate)
+                .map(|n| {
+                    let t = n as FloatType / sample_rate as FloatType;
+                    (PI2 * 110.0 * t).sin() + 0.5 * (PI2 * 440.0 * t).sin()
+                })
+                .collect(),
+        };
+        let frequencies = vec![110.0, 220.0, 440.0];
+        let wavelet_factory = |frequency: FloatType, sample_rate: u32|
+            SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / frequency, sample_rate, &morlet(frequency));
+
+        let full_rate = wavelet_transform(&signal_sample, &WaveletSource::Sampled(&wavelet_factory), &frequencies,
+                                          &TransformParameters { multirate: false, hop_size: 1, analytic: false });
+        let multirate = wavelet_transform(&signal_sample, &WaveletSource::Sampled(&wavelet_factory), &frequencies,
+                                          &TransformParameters { multirate: true, hop_size: 1, analytic: false });
+
+        for (full_rate_row, multirate_row) in full_rate.rows().zip(multirate.rows()) {
+            assert_eq!(full_rate_row.len(), multirate_row.len());
+            for j in 1000..(full_rate_row.len() - 5000) {
+                let difference = (full_rate_row[j].norm() - multirate_row[j].norm()).abs();
+                assert!(difference < 0.002, "Difference {} at sample {}", difference, j);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_hop_size() {
+        let sample_rate = 4000;
+        let signal_sample = SignalSample {
+            sample_rate,
+            samples: (0..sample_rate)
+                .map(|n| (PI2 * 200.0 * n as FloatType / sample_rate as FloatType).sin())
+                .collect(),
+        };
+        let frequencies = vec![100.0, 200.0];
+        let wavelet_factory = |frequency: FloatType, sample_rate: u32|
+            SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / frequency, sample_rate, &morlet(frequency));
+
+        // an odd hop size, and an even one for which analytic convolutions are folded
+        for (multirate, analytic, hop_size) in [(false, false, 7), (true, false, 7), (false, true, 12), (true, true, 12)] {
+            let per_sample = wavelet_transform(&signal_sample, &WaveletSource::Sampled(&wavelet_factory), &frequencies,
+                                               &TransformParameters { multirate, hop_size: 1, analytic });
+            let hopped = wavelet_transform(&signal_sample, &WaveletSource::Sampled(&wavelet_factory), &frequencies,
+                                           &TransformParameters { multirate, hop_size, analytic });
+            assert_eq!(hopped.hop_size, hop_size);
+            for (per_sample_row, hopped_row) in per_sample.rows().zip(hopped.rows()) {
+                assert_eq!(hopped_row.len(), (sample_rate as usize).div_ceil(hop_size));
+                let expected: Vec<Complex<FloatType>> = per_sample_row.iter().step_by(hop_size).copied().collect();
+                assert_complex_vec(hopped_row, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_with_shared_planners() {
+        let sample_rate = 4000;
+        let signal = |frequency: FloatType, samples: u32| SignalSample {
+            sample_rate,
+            samples: (0..samples).map(|n| (PI2 * frequency * n as FloatType / sample_rate as FloatType).sin()).collect(),
+        };
+        let frequencies = vec![100.0, 200.0, 400.0];
+        let parameters = TransformParameters { multirate: true, hop_size: 4, analytic: true };
+        let mut planners = FftPlanners::new();
+
+        // the second signal has the same fft lengths as the first one, the third one longer ones
+        for signal in [signal(200.0, 4000), signal(300.0, 3900), signal(100.0, 9000)] {
+            let shared = wavelet_transform_with(&signal, &WaveletSource::Spectral, &frequencies, &parameters, &mut planners);
+            let own = wavelet_transform(&signal, &WaveletSource::Spectral, &frequencies, &parameters);
+            assert_complex_vec(&shared.coefficients, &own.coefficients);
+        }
+    }
+
+    #[test]
+    fn test_real_fourier_matches_complex_fourier() {
+        let signal = vec![0.3, 0.5, -1.0, 0.7, 0.1];
+
+        let signal_fourier = in_place_fourier(&signal, 6, &mut RealFftPlanner::new());
+        let complex_fourier = complex_fourier(&signal, 8);
+
+        assert_complex_vec(&signal_fourier, &complex_fourier[..5]);
+    }
+
+    #[test]
+    fn test_analytic_convolution_matches_full_spectrum() {
+        let sample_rate = 1000;
+        let signal: Vec<FloatType> = (0..2000)
+            .map(|n| {
+                let t = n as FloatType / sample_rate as FloatType;
+                (PI2 * 50.0 * t).sin() + 0.3 * (PI2 * 120.0 * t).cos()
+            })
+            .collect();
+        let wavelet = SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / 50.0, sample_rate, &morlet(50.0));
+
+        let mut planner = FftPlanner::<FloatType>::new();
+        let forward_fft = planner.plan_fft_forward(4096);
+        let inverse_fft = planner.plan_fft_inverse(4096);
+        let octave = Octave::new(&signal, 4096, 1, &mut FftPlanners::new());
+        let mut workspace = Workspace::default();
+        let full_spectrum = fourier_convolution(&octave, &wavelet.samples, false, &mut workspace).to_vec();
+        let analytic = fourier_convolution(&octave, &wavelet.samples, true, &mut workspace).to_vec();
+
+        let complex_fourier = complex_fourier(&signal, 4096);
+        let mut kernel_fourier = pad(&wavelet.samples, 4096, re(0.0));
+        forward_fft.process(&mut kernel_fourier);
+        let mut reference: Vec<Complex<FloatType>> = complex_fourier.iter().zip(kernel_fourier.iter())
+            .map(|(s, k)| s * k / 4096.0)
+            .collect();
+        inverse_fft.process(&mut reference);
+
+        let scale = wavelet.samples.len() as FloatType;
+        for j in 0..4096 {
+            assert!((full_spectrum[j] - reference[j]).norm() / scale < 1e-5, "Full spectrum differs at {}", j);
+            assert!((analytic[j] - reference[j]).norm() / scale < 1e-3, "Analytic differs at {}", j);
+        }
+    }
+
+    #[test]
+    fn test_folded_convolution_matches_unfolded() {
+        let sample_rate = 1000;
+        let signal: Vec<FloatType> = (0..2000)
+            .map(|n| (PI2 * 70.0 * n as FloatType / sample_rate as FloatType).sin())
+            .collect();
+        let mut planners = FftPlanners::new();
+        let unfolded = Octave::new(&signal, 4096, 1, &mut planners);
+        let folded = Octave::new(&signal, 4096, 4, &mut planners);
+        let mut workspace = Workspace::default();
+
+        // kernel lengths giving every offset of the first kept sample within the stride
+        for frequency in [60.0, 61.0, 62.0, 63.0] {
+            let wavelet = SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / frequency, sample_rate, &morlet(frequency));
+            let offset = (wavelet.samples.len() - 1) % 4;
+            let expected: Vec<Complex<FloatType>> = fourier_convolution(&unfolded, &wavelet.samples, true, &mut workspace)
+                .iter().skip(offset).step_by(4).copied().collect();
+            let actual = fourier_convolution(&folded, &wavelet.samples, true, &mut workspace).to_vec();
+            assert_eq!(actual.len(), 1024);
+            let scale = wavelet.samples.len() as FloatType;
+            for (j, (a, e)) in actual.iter().zip(&expected).enumerate() {
+                assert!((a - e).norm() / scale < 1e-5, "Folded convolution differs at {} for {} Hz", j, frequency);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_double_precision() {
+        let sample_rate = 4000;
+        let samples: Vec<f64> = (0..sample_rate)
+            .map(|n| (std::f64::consts::TAU * 300.0 * n as f64 / sample_rate as f64).sin())
+            .collect();
+        let single = wavelet_transform(&SignalSample { sample_rate, samples: samples.iter().map(|s| *s as f32).collect() },
+                                       &WaveletSource::Sampled(&|frequency: f32, sample_rate| SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH as f32 / frequency, sample_rate, &morlet(frequency))),
+                                       &[150.0, 300.0], &TransformParameters { multirate: true, hop_size: 10, analytic: true });
+        let double = wavelet_transform(&SignalSample { sample_rate, samples },
+                                       &WaveletSource::Sampled(&|frequency: f64, sample_rate| SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH / frequency, sample_rate, &morlet(frequency))),
+                                       &[150.0, 300.0], &TransformParameters { multirate: true, hop_size: 10, analytic: true });
+
+        for (single_row, double_row) in single.rows().zip(double.rows()) {
+            for (single_value, double_value) in single_row.iter().zip(double_row.iter()) {
+                assert!((single_value.re as f64 - double_value.re).abs() < 1e-4);
+                assert!((single_value.im as f64 - double_value.im).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_spectral_transform_matches_sampled_wavelets() {
+        let sample_rate = 4000;
+        let signal_sample = SignalSample {
+            sample_rate,
+            samples: (0..2 * sample_rate)
+                .map(|n| {
+                    let t = n as FloatType / sample_rate as FloatType;
+                    (PI2 * 130.0 * t).sin() + 0.5 * (PI2 * 520.0 * t).sin()
+                })
+                .collect(),
+        };
+        let frequencies = vec![130.0, 260.0, 520.0];
+        let wavelet_factory = |frequency: FloatType, sample_rate: u32|
+            SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / frequency, sample_rate, &morlet(frequency));
+
+        for multirate in [false, true] {
+            let sampled = wavelet_transform(&signal_sample, &WaveletSource::Sampled(&wavelet_factory), &frequencies,
+                                            &TransformParameters { multirate, hop_size: 5, analytic: true });
+            let spectral = wavelet_transform(&signal_sample, &WaveletSource::Spectral, &frequencies,
+                                             &TransformParameters { multirate, hop_size: 5, analytic: true });
+            for (sampled_row, spectral_row) in sampled.rows().zip(spectral.rows()) {
+                for j in 200..(sampled_row.len() - 400) {
+                    let difference = (sampled_row[j] - spectral_row[j]).norm();
+                    assert!(difference < 0.01, "Difference {} at frame {}", difference, j);
+                }
+            }
+        }
+    }
+
     fn complex_convolution(signal: &[FloatType], kernel: &[Complex<FloatType>]) -> Vec<Complex<FloatType>> {
         let signal_len = signal.len() as i64;
         let kernel_len = kernel.len() as i64;
@@ -204,4 +721,11 @@ mod tests {
         }
         convolution_result
     }
-}
\ No newline at end of file
+
+    /// Full spectrum of the real signal computed with a complex fft, as the transform did before using a real one
+    fn complex_fourier(signal: &[FloatType], length: usize) -> Vec<Complex<FloatType>> {
+        let mut signal_transform: Vec<Complex<FloatType>> = pad(signal, length, 0.0).iter().map(|s| re(*s)).collect();
+        FftPlanner::<FloatType>::new().plan_fft_forward(length).process(&mut signal_transform);
+        signal_transform
+    }
+}
@@ -1,38 +1,86 @@
 use num_complex::Complex;
-use crate::utils::math::{FloatType, i};
+use crate::utils::math::{cast, Float};
 
-const PI2: FloatType = 2.0 * std::f64::consts::PI as FloatType;
+const WAVE_NUMBER: f64 = 16.0;
+pub const MORLET_HALF_LENGTH: f64 = WAVE_NUMBER * 2.0;
 
-const WAVE_NUMBER: FloatType = 16.0;
-pub(crate) const MORLET_HALF_LENGTH: FloatType = WAVE_NUMBER * 2.0;
-
-pub(crate) fn morlet(frequency_hz: FloatType) -> impl Fn(FloatType) -> Complex<FloatType> {
+pub fn morlet<T: Float>(frequency_hz: T) -> impl Fn(T) -> Complex<T> {
     move |t| {
-        let d = frequency_hz / WAVE_NUMBER;
-        let x = d * t - 2.0;
+        let d = frequency_hz / cast(WAVE_NUMBER);
+        let x = d * t - cast(2.0);
         let exp = (-x * x).exp();
-        let x2pi = PI2 * t * frequency_hz;
-        x2pi.cos() * exp + i(x2pi.sin() * exp)
+        let x2pi = T::TAU() * t * frequency_hz;
+        Complex::new(x2pi.cos() * exp, x2pi.sin() * exp)
+    }
+}
+
+/// Time in seconds from the start of the morlet wavelet to the center of its envelope: the coefficient of a
+/// scalogram frame describes the signal around the frame time plus this delay
+pub fn morlet_center<T: Float>(frequency_hz: T) -> T {
+    cast::<T>(MORLET_HALF_LENGTH) / frequency_hz
+}
+
+/// Distance in Hz from frequency_hz beyond which the morlet spectrum is below e^-36, i.e. negligible even in f64
+pub fn morlet_bandwidth<T: Float>(frequency_hz: T) -> T {
+    cast::<T>(6.0) * frequency_hz / cast(WAVE_NUMBER) / T::PI()
+}
+
+/// Continuous Fourier transform of the morlet wavelet, as a function of the frequency in Hz. The gaussian envelope is
+/// not truncated, unlike when sampling the wavelet over 2 * MORLET_HALF_LENGTH periods.
+pub fn morlet_spectrum<T: Float>(frequency_hz: T) -> impl Fn(T) -> Complex<T> {
+    let d = frequency_hz / cast(WAVE_NUMBER);
+    let amplitude = T::PI().sqrt() / d;
+    // the envelope is centered 2 / d seconds after the start of the wavelet
+    let delay = cast::<T>(2.0) / d;
+    move |frequency| {
+        let offset = frequency - frequency_hz;
+        let x = T::PI() * offset / d;
+        Complex::from_polar(amplitude * (-x * x).exp(), -T::TAU() * offset * delay)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::utils::math::{assert_epsilon, FloatType};
+    use rustfft::FftPlanner;
+    use crate::signals::SignalSample;
     use crate::signals::wavelets;
-    use crate::signals::wavelets::MORLET_HALF_LENGTH;
+    use crate::signals::wavelets::{morlet_bandwidth, MORLET_HALF_LENGTH};
+
+    /// The gaussian envelope exp(-x^2) is truncated at x = ±2, where it equals e^-4
+    const EDGE_ENVELOPE: FloatType = 0.018_315_64;
 
     #[test]
     fn morlet_wavelet() {
         for frequency in 1..100 {
-            let frequency_FloatType = frequency as FloatType;
-            let wavelet = wavelets::morlet(frequency_FloatType);
-            assert_epsilon(wavelet(0.0).re, 0.0);
+            let frequency_float = frequency as FloatType;
+            let wavelet = wavelets::morlet(frequency_float);
+            assert_epsilon(wavelet(0.0).re, EDGE_ENVELOPE);
             assert_epsilon(wavelet(0.0).im, 0.0);
-            assert_epsilon(wavelet(MORLET_HALF_LENGTH / frequency_FloatType).re, 1.0);
-            assert_epsilon(wavelet(MORLET_HALF_LENGTH / frequency_FloatType).im, 0.0);
-            assert_epsilon(wavelet(2.0 * MORLET_HALF_LENGTH / frequency_FloatType).re, 0.0);
-            assert_epsilon(wavelet(2.0 * MORLET_HALF_LENGTH / frequency_FloatType).im, 0.0);
+            assert_epsilon(wavelet(MORLET_HALF_LENGTH as FloatType / frequency_float).re, 1.0);
+            assert_epsilon(wavelet(MORLET_HALF_LENGTH as FloatType / frequency_float).norm(), 1.0);
+            assert_epsilon(wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / frequency_float).re, EDGE_ENVELOPE);
+            assert_epsilon(wavelet(2.0 * MORLET_HALF_LENGTH as FloatType / frequency_float).im, 0.0);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn morlet_spectrum_matches_sampled_wavelet() {
+        let sample_rate = 1000;
+        let frequency = 50.0;
+        let wavelet = SignalSample::from_wavelet(2.0 * MORLET_HALF_LENGTH / frequency, sample_rate, &wavelets::morlet(frequency));
+        let fft_len = 4096;
+        let mut kernel = wavelet.samples.clone();
+        kernel.resize(fft_len, Default::default());
+        FftPlanner::<f64>::new().plan_fft_forward(fft_len).process(&mut kernel);
+
+        let spectrum = wavelets::morlet_spectrum(frequency);
+        let peak = spectrum(frequency).norm() * sample_rate as f64;
+        for (bin, sampled) in kernel.iter().enumerate().take(fft_len / 2) {
+            let analytic = spectrum(bin as f64 * sample_rate as f64 / fft_len as f64) * sample_rate as f64;
+            // the sampled wavelet is truncated, which spreads a small error over all frequencies
+            assert!((analytic - sampled).norm() / peak < 0.02, "Spectra differ at bin {}", bin);
+        }
+        assert!(spectrum(frequency + morlet_bandwidth(frequency)).norm() * sample_rate as f64 / peak < 1e-15);
+    }
+}
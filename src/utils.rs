@@ -1,9 +1,12 @@
 use std::fs::File;
 use minimp3::{Decoder, Error, Frame};
 use crate::signals::SignalSample;
-use crate::utils::math::FloatType;
+use crate::utils::math::{cast, Float};
 
 pub mod argument_validation;
+pub mod batch;
+pub mod cache;
+pub mod export;
 pub mod math;
 pub mod visualization;
 
@@ -15,27 +18,33 @@ pub(crate) fn file_extension(file_path: &str) -> Option<&str> {
     Some(split[split.len() - 1])
 }
 
-pub(crate) fn read_audio(file_path: &str) -> SignalSample<FloatType> {
+pub fn read_audio<T: Float>(file_path: &str) -> SignalSample<T> {
+    decode_audio(file_path).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Same as read_audio, returning an error rather than panicking when the file can't be decoded
+pub fn decode_audio<T: Float>(file_path: &str) -> Result<SignalSample<T>, String> {
     let extension = file_extension(file_path);
     match extension {
         Some("wav") => read_wav(file_path),
         Some("mp3") => read_mp3(file_path),
-        _ => panic!("Unrecognised file format for {}", file_path),
+        _ => Err(format!("Unrecognised file format for {}", file_path)),
     }
 }
 
-fn read_wav(file_path: &str) -> SignalSample<FloatType> {
-    let mut reader = hound::WavReader::open(file_path).unwrap();
-    SignalSample {
+fn read_wav<T: Float>(file_path: &str) -> Result<SignalSample<T>, String> {
+    let error = |e: hound::Error| format!("Error reading {}: {}", file_path, e);
+    let mut reader = hound::WavReader::open(file_path).map_err(error)?;
+    Ok(SignalSample {
         sample_rate: reader.spec().sample_rate,
         samples: reader.samples::<i16>()
-            .map(|sample| to_float_sample(sample.unwrap()))
-            .collect(),
-    }
+            .map(|sample| sample.map(to_float_sample).map_err(error))
+            .collect::<Result<_, _>>()?,
+    })
 }
 
-fn read_mp3(file_path: &str) -> SignalSample<FloatType> {
-    let mut decoder = Decoder::new(File::open(file_path).unwrap());
+fn read_mp3<T: Float>(file_path: &str) -> Result<SignalSample<T>, String> {
+    let mut decoder = Decoder::new(File::open(file_path).map_err(|e| format!("Error reading {}: {}", file_path, e))?);
 
     let mut samples = Vec::new();
     let mut eof_reached = false;
@@ -45,23 +54,35 @@ fn read_mp3(file_path: &str) -> SignalSample<FloatType> {
             Ok(Frame { data, sample_rate, channels, .. }) => {
                 s_rate = sample_rate;
                 for i in 0..(data.len() / channels) {
-                    let mut sample = 0.0;
+                    let mut sample = T::zero();
                     for c in 0..channels {
-                        sample += to_float_sample(data[i * channels + c]);
+                        sample = sample + to_float_sample::<T>(data[i * channels + c]);
                     }
-                    samples.push(sample / (channels as FloatType));
+                    samples.push(sample / cast(channels));
                 }
             }
             Err(Error::Eof) => eof_reached = true,
-            Err(e) => panic!("Error reading {}: {:?}", file_path, e),
+            Err(e) => return Err(format!("Error reading {}: {:?}", file_path, e)),
         }
     }
-    SignalSample {
+    Ok(SignalSample {
         sample_rate: s_rate as u32,
         samples,
+    })
+}
+
+/// Writes the signal as a mono 16 bit .wav file, clipping it to [-1, 1]
+pub fn write_wav<T: Float>(file_path: &str, signal: &SignalSample<T>) -> Result<(), String> {
+    let spec = hound::WavSpec { channels: 1, sample_rate: signal.sample_rate, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let error = |e: hound::Error| format!("Could not write {}: {}", file_path, e);
+    let mut writer = hound::WavWriter::create(file_path, spec).map_err(error)?;
+    for sample in &signal.samples {
+        let value = sample.to_f64().unwrap().clamp(-1.0, 1.0) * i16::MAX as f64;
+        writer.write_sample(value.round() as i16).map_err(error)?;
     }
+    writer.finalize().map_err(error)
 }
 
-fn to_float_sample(sample: i16) -> FloatType {
-    (sample as FloatType) / (i16::MAX as FloatType)
+fn to_float_sample<T: Float>(sample: i16) -> T {
+    cast::<T>(sample) / cast(i16::MAX)
 }
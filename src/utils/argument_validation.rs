@@ -1,11 +1,17 @@
 use std::str::FromStr;
+use crate::notes::grid::GridType;
+use crate::notes::tuning::TuningSystem;
 use crate::utils::file_extension;
+use crate::utils::math::Precision;
 use crate::utils::visualization::{ColorScheme, ResamplingStrategy};
 
-pub(crate) fn validate_arguments(input_file: &str,
-                                 output_file: &str,
-                                 resampling_strategy: &str,
-                                 color_scheme: &str) -> Result<(), String> {
+pub fn validate_arguments(input_file: &str,
+                          output_file: &str,
+                          resampling_strategy: &str,
+                          color_scheme: &str,
+                          precision: &str,
+                          grid: &str,
+                          tuning: &str) -> Result<(), String> {
     valid_input_extension(input_file)?;
     valid_output_extension(output_file)?;
 
@@ -19,10 +25,30 @@ pub(crate) fn validate_arguments(input_file: &str,
         return Err(is_color_scheme_valid.err().unwrap());
     }
 
+    validate_transform_arguments(precision, grid, tuning)
+}
+
+/// Checks the arguments shared by all the commands that transform audio
+pub fn validate_transform_arguments(precision: &str, grid: &str, tuning: &str) -> Result<(), String> {
+    let is_precision_valid = Precision::from_str(precision);
+    if is_precision_valid.is_err() {
+        return Err(is_precision_valid.err().unwrap());
+    }
+
+    let is_grid_valid = GridType::from_str(grid);
+    if is_grid_valid.is_err() {
+        return Err(is_grid_valid.err().unwrap());
+    }
+
+    let is_tuning_valid = TuningSystem::from_str(tuning);
+    if is_tuning_valid.is_err() {
+        return Err(is_tuning_valid.err().unwrap());
+    }
+
     Ok(())
 }
 
-fn valid_input_extension(input_file: &str) -> Result<(), String> {
+pub fn valid_input_extension(input_file: &str) -> Result<(), String> {
     let extension = file_extension(input_file);
     match extension {
         None => Err("Only .wav format is supported, but input file has no extension!".to_string()),
@@ -32,7 +58,7 @@ fn valid_input_extension(input_file: &str) -> Result<(), String> {
     }
 }
 
-fn valid_output_extension(output_file: &str) -> Result<(), String> {
+pub fn valid_output_extension(output_file: &str) -> Result<(), String> {
     let extension = file_extension(output_file);
     match extension {
         None => Err("Only .png format is supported for output, but output file has no extension!".to_string()),
@@ -41,30 +67,55 @@ fn valid_output_extension(output_file: &str) -> Result<(), String> {
     }
 }
 
+pub fn valid_audio_output_extension(output_file: &str) -> Result<(), String> {
+    match file_extension(output_file) {
+        Some("wav") => Ok(()),
+        _ => Err(format!("Only .wav format is supported for audio outputs, but output file is {}!", output_file)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::argument_validation::validate_arguments;
 
     #[test]
     fn both_input_and_output_are_valid() {
-        assert_eq!(validate_arguments("input.wav", "output.png", "max", "heatmap"), Ok(()));
+        assert_eq!(validate_arguments("input.wav", "output.png", "max", "heatmap", "f32", "notes", "equal"), Ok(()));
     }
 
     #[test]
     fn input_has_wrong_extension() {
-        assert_eq!(validate_arguments("input.wav2", "output.png", "max", "heatmap"),
-                   Err("Only .wav format is supported, but input format is .wav2!".to_string()));
+        assert_eq!(validate_arguments("input.wav2", "output.png", "max", "heatmap", "f32", "notes", "equal"),
+                   Err("Only .wav and .mp3 formats are supported, but input format is .wav2!".to_string()));
     }
 
     #[test]
     fn input_has_no_extension() {
-        assert_eq!(validate_arguments("input", "output.png", "max", "heatmap"),
+        assert_eq!(validate_arguments("input", "output.png", "max", "heatmap", "f32", "notes", "equal"),
                    Err("Only .wav format is supported, but input file has no extension!".to_string()));
     }
 
     #[test]
     fn output_has_wrong_extension() {
-        assert_eq!(validate_arguments("input.wav", "output", "max", "heatmap"),
+        assert_eq!(validate_arguments("input.wav", "output", "max", "heatmap", "f32", "notes", "equal"),
                    Err("Only .png format is supported for output, but output file has no extension!".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn precision_is_invalid() {
+        assert_eq!(validate_arguments("input.wav", "output.png", "max", "heatmap", "f16", "notes", "equal"),
+                   Err("Invalid precision 'f16'".to_string()));
+    }
+
+    #[test]
+    fn grid_is_invalid() {
+        assert_eq!(validate_arguments("input.wav", "output.png", "max", "heatmap", "f32", "cents", "equal"),
+                   Err("Invalid frequency grid 'cents'".to_string()));
+    }
+
+    #[test]
+    fn tuning_is_invalid() {
+        assert_eq!(validate_arguments("input.wav", "output.png", "max", "heatmap", "f32", "notes", "meantone"),
+                   Err("Invalid tuning system 'meantone'".to_string()));
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::utils::argument_validation::valid_input_extension;
+
+/// Audio file of a batch
+#[derive(Debug, PartialEq)]
+pub struct BatchInput {
+    pub path: PathBuf,
+    /// Path relative to the root of the batch, mirrored in the output directory
+    pub relative: PathBuf,
+}
+
+/// Audio files of a batch, sorted by path. The source is either:
+/// - a directory, whose .wav and .mp3 files are all listed, recursively
+/// - a glob pattern, where `*` and `?` match within a path component and `**` any number of components, relative to
+///   its leading components without wildcards
+/// - a manifest, a text file with one path per line relative to its directory, skipping blank lines and lines
+///   starting with `#`. Its paths are listed even if they aren't audio files, so that they are reported as failures.
+/// - a single audio file
+pub fn batch_inputs(source: &str) -> Result<Vec<BatchInput>, String> {
+    let mut inputs = if source.contains(['*', '?']) {
+        glob_inputs(source)?
+    } else if Path::new(source).is_dir() {
+        let mut files = Vec::new();
+        list_files(Path::new(source), &mut files)?;
+        files.into_iter()
+            .filter(|path| is_audio(path))
+            .map(|path| BatchInput { relative: path.strip_prefix(source).unwrap().to_path_buf(), path })
+            .collect()
+    } else if is_audio(Path::new(source)) {
+        let path = PathBuf::from(source);
+        vec![BatchInput { relative: PathBuf::from(path.file_name().unwrap_or_default()), path }]
+    } else {
+        manifest_inputs(source)?
+    };
+    inputs.sort_by(|a, b| a.path.cmp(&b.path));
+    inputs.dedup_by(|a, b| a.path == b.path);
+    Ok(inputs)
+}
+
+/// Path of the output of the input in the output directory, with the extension of the output appended to the one of the
+/// input, so that song.wav and song.mp3 don't both write song.png
+pub fn mirrored_output(output_dir: &str, input: &BatchInput, extension: &str) -> PathBuf {
+    let mut output = Path::new(output_dir).join(&input.relative).into_os_string();
+    output.push(".");
+    output.push(extension);
+    PathBuf::from(output)
+}
+
+/// Whether the output exists and was modified after the input. The options the output was made with aren't compared.
+pub fn up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+fn is_audio(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| valid_input_extension(path).is_ok())
+}
+
+fn list_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(directory).map_err(|e| format!("Could not list {}: {}", directory.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Could not list {}: {}", directory.display(), e))?.path();
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn glob_inputs(pattern: &str) -> Result<Vec<BatchInput>, String> {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let literal = components.iter().take_while(|component| !component.contains(['*', '?'])).count();
+    let root = match components[..literal].join("/") {
+        root if root.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        root if root.is_empty() => ".".to_string(),
+        root => root,
+    };
+    let mut files = Vec::new();
+    if Path::new(&root).is_dir() {
+        list_files(Path::new(&root), &mut files)?;
+    }
+    Ok(files.into_iter()
+        .filter(|path| is_audio(path))
+        .filter_map(|path| {
+            let relative = path.strip_prefix(&root).ok()?.to_path_buf();
+            let relative_components: Vec<&str> = relative.iter().map(|component| component.to_str()).collect::<Option<_>>()?;
+            glob_match(&components[literal..], &relative_components).then_some(BatchInput { path, relative })
+        })
+        .collect())
+}
+
+/// Whether the path components match the pattern components
+fn glob_match(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => glob_match(&pattern[1..], path) || (!path.is_empty() && glob_match(pattern, &path[1..])),
+        (Some(component_pattern), Some(component)) => {
+            wildcard_match(component_pattern.as_bytes(), component.as_bytes()) && glob_match(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+/// Whether the text matches the pattern, where `*` matches any characters and `?` a single one
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => wildcard_match(&pattern[1..], text) || (!text.is_empty() && wildcard_match(pattern, &text[1..])),
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &text[1..]),
+        (Some(expected), Some(actual)) => expected == actual && wildcard_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+fn manifest_inputs(manifest: &str) -> Result<Vec<BatchInput>, String> {
+    let content = fs::read_to_string(manifest).map_err(|e| format!("Could not read the manifest {}: {}", manifest, e))?;
+    let directory = Path::new(manifest).parent().unwrap_or(Path::new(""));
+    Ok(content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let path = directory.join(line);
+            // paths out of the directory of the manifest are mirrored from the root
+            let relative = match Path::new(line).is_relative() && !Path::new(line).components().any(|c| c == Component::ParentDir) {
+                true => PathBuf::from(line),
+                false => Path::new(line).components().filter(|c| matches!(c, Component::Normal(_))).collect(),
+            };
+            BatchInput { path, relative }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::utils::batch::{batch_inputs, glob_match, mirrored_output, up_to_date};
+
+    #[test]
+    fn globs_match_paths() {
+        assert!(glob_match(&["*.wav"], &["a.wav"]));
+        assert!(!glob_match(&["*.wav"], &["dir", "a.wav"]));
+        assert!(glob_match(&["**", "*.wav"], &["a.wav"]));
+        assert!(glob_match(&["**", "*.wav"], &["dir", "sub", "a.wav"]));
+        assert!(glob_match(&["take?", "*.mp3"], &["take2", "song.mp3"]));
+        assert!(!glob_match(&["take?", "*.mp3"], &["take12", "song.mp3"]));
+    }
+
+    #[test]
+    fn lists_and_mirrors_inputs() {
+        let root = std::env::temp_dir().join(format!("subtune-batch-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("album/disc 2")).unwrap();
+        for file in ["album/one.wav", "album/one.mp3", "album/notes.txt", "album/disc 2/two.mp3", "three.wav"] {
+            fs::write(root.join(file), []).unwrap();
+        }
+        fs::write(root.join("manifest.txt"), "# nightly\nalbum/one.wav\n\n../elsewhere/four.wav\n").unwrap();
+        let path = |relative: &str| root.join(relative).to_str().unwrap().to_string();
+        let relatives = |source: &str| batch_inputs(source).unwrap().into_iter().map(|input| input.relative).collect::<Vec<_>>();
+
+        assert_eq!(relatives(&path("album")),
+                   vec![PathBuf::from("disc 2/two.mp3"), PathBuf::from("one.mp3"), PathBuf::from("one.wav")]);
+        assert_eq!(relatives(&path("**/*.wav")), vec![PathBuf::from("album/one.wav"), PathBuf::from("three.wav")]);
+        assert_eq!(relatives(&path("manifest.txt")), vec![PathBuf::from("elsewhere/four.wav"), PathBuf::from("album/one.wav")]);
+        assert!(batch_inputs(&path("missing.txt")).is_err());
+
+        let input = &batch_inputs(&path("album")).unwrap()[0];
+        let output = mirrored_output(&path("out"), input, "png");
+        assert_eq!(output, root.join("out/disc 2/two.mp3.png"));
+        let outputs: Vec<_> = batch_inputs(&path("album")).unwrap().iter().map(|input| mirrored_output("out", input, "png")).collect();
+        assert_eq!(outputs[1..], [PathBuf::from("out/one.mp3.png"), PathBuf::from("out/one.wav.png")]);
+        assert!(!up_to_date(&input.path, &output));
+        fs::create_dir_all(output.parent().unwrap()).unwrap();
+        fs::write(&output, []).unwrap();
+        assert!(up_to_date(&input.path, &output));
+        assert!(!up_to_date(Path::new("missing.wav"), &output));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use num_complex::Complex;
+use crate::signals::Scalogram;
+use crate::utils::math::{cast, Float};
+
+/// Bumped whenever the transform output or the file layout changes, so that old cache files are ignored
+const FORMAT_VERSION: u64 = 2;
+const MAGIC: &[u8; 8] = b"SUBTUNE\0";
+const HEADER_LEN: usize = MAGIC.len() + 8 * 6;
+
+/// Everything the transform output depends on, besides the input file content
+pub struct CacheKey<'a, T> {
+    pub frequencies: &'a [T],
+    /// None when the hop size is derived from the pixels per second, which then identify it for a given input file
+    pub hop_size: Option<usize>,
+    pub pixels_per_second: u32,
+    /// Rate the input is resampled to before the transform, None to keep its own
+    pub sample_rate: Option<u32>,
+    pub multirate: bool,
+    pub analytic: bool,
+    pub spectral: bool,
+}
+
+/// Path of the cached scalogram for the content of input_file and the given transform parameters
+pub fn cache_path<T: Float>(cache_dir: &str, input_file: &str, key: &CacheKey<T>) -> Result<PathBuf, String> {
+    let input = fs::read(input_file).map_err(|e| format!("Could not read {}: {}", input_file, e))?;
+
+    let mut hash = Fnv::new();
+    hash.write(&input);
+    hash.write(&FORMAT_VERSION.to_le_bytes());
+    hash.write(&(size_of::<T>() as u64).to_le_bytes());
+    for frequency in key.frequencies {
+        hash.write(&frequency.to_f64().unwrap().to_le_bytes());
+    }
+    match key.hop_size {
+        Some(hop_size) => hash.write(&[&[0], &(hop_size as u64).to_le_bytes()[..]].concat()),
+        None => hash.write(&[&[1], &(key.pixels_per_second as u64).to_le_bytes()[..]].concat()),
+    }
+    hash.write(&(key.sample_rate.unwrap_or(0) as u64).to_le_bytes());
+    hash.write(&[key.multirate as u8, key.analytic as u8, key.spectral as u8]);
+
+    Ok(Path::new(cache_dir).join(format!("{:016x}.scalogram", hash.finish())))
+}
+
+/// Reads a cached scalogram, None if it doesn't exist, was written with a different precision or format, or doesn't
+/// hold one row per frequency of the key
+pub fn load<T: Float>(path: &Path, key: &CacheKey<T>) -> Option<Scalogram<Complex<T>>> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    let header: Vec<u64> = bytes[MAGIC.len()..HEADER_LEN].chunks(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    let (version, float_size, sample_rate, hop_size, frames, multirate) =
+        (header[0], header[1] as usize, header[2], header[3], header[4], header[5]);
+    if version != FORMAT_VERSION || float_size != size_of::<T>() || frames == 0
+        || (bytes.len() - HEADER_LEN) as u64 != frames * key.frequencies.len() as u64 * 2 * float_size as u64 {
+        return None;
+    }
+
+    let values: Vec<T> = bytes[HEADER_LEN..].chunks(float_size)
+        .map(|chunk| match float_size {
+            4 => cast(f32::from_le_bytes(chunk.try_into().unwrap())),
+            _ => cast(f64::from_le_bytes(chunk.try_into().unwrap())),
+        })
+        .collect();
+    Some(Scalogram {
+        sample_rate: sample_rate as u32,
+        hop_size: hop_size as usize,
+        frames: frames as usize,
+        multirate: multirate != 0,
+        coefficients: values.chunks(2).map(|value| Complex::new(value[0], value[1])).collect(),
+    })
+}
+
+/// Writes the scalogram to path, creating the cache directory if needed
+pub fn store<T: Float>(path: &Path, scalogram: &Scalogram<Complex<T>>) -> Result<(), String> {
+    let float_size = size_of::<T>();
+    let mut bytes = Vec::with_capacity(HEADER_LEN + scalogram.coefficients.len() * 2 * float_size);
+    bytes.extend_from_slice(MAGIC);
+    for value in [FORMAT_VERSION, float_size as u64, scalogram.sample_rate as u64, scalogram.hop_size as u64,
+                  scalogram.frames as u64, scalogram.multirate as u64] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for coefficient in &scalogram.coefficients {
+        for value in [coefficient.re, coefficient.im] {
+            match float_size {
+                4 => bytes.extend_from_slice(&value.to_f32().unwrap().to_le_bytes()),
+                _ => bytes.extend_from_slice(&value.to_f64().unwrap().to_le_bytes()),
+            }
+        }
+    }
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+    }
+    fs::write(path, bytes).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+/// 64-bit FNV-1a, stable across platforms and Rust versions unlike the std hashers
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use num_complex::Complex;
+    use crate::signals::Scalogram;
+    use crate::utils::cache::{cache_path, CacheKey, load, store};
+
+    #[test]
+    fn scalogram_round_trip() {
+        let path = std::env::temp_dir().join(format!("subtune-cache-test-{}", std::process::id())).join("round-trip.scalogram");
+        fn key<T>(frequencies: &[T]) -> CacheKey<'_, T> {
+            CacheKey { frequencies, hop_size: Some(128), pixels_per_second: 32, sample_rate: None, multirate: false, analytic: true, spectral: false }
+        }
+        let scalogram = Scalogram {
+            sample_rate: 44100,
+            hop_size: 128,
+            frames: 2,
+            multirate: true,
+            coefficients: vec![Complex::new(0.5, -0.25), Complex::new(1.0, 2.0), Complex::new(-3.0, 0.0), Complex::new(0.0, 1e-7)],
+        };
+
+        store(&path, &scalogram).unwrap();
+        let loaded = load::<f64>(&path, &key(&[110.0, 220.0])).unwrap();
+        assert_eq!(loaded.sample_rate, 44100);
+        assert_eq!(loaded.hop_size, 128);
+        assert_eq!(loaded.frames, 2);
+        assert!(loaded.multirate);
+        assert_eq!(loaded.coefficients, scalogram.coefficients);
+        assert!(load::<f32>(&path, &key(&[110.0f32, 220.0])).is_none());
+        assert!(load::<f64>(&path, &key(&[110.0])).is_none());
+        assert!(load::<f64>(&path, &key(&[110.0, 220.0, 440.0])).is_none());
+
+        store(&path, &Scalogram::<Complex<f64>> { sample_rate: 44100, hop_size: 128, frames: 0, multirate: false, coefficients: vec![] }).unwrap();
+        assert!(load::<f64>(&path, &key::<f64>(&[])).is_none());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn cache_path_depends_on_parameters() {
+        let input = std::env::temp_dir().join(format!("subtune-cache-test-input-{}.wav", std::process::id()));
+        fs::write(&input, [1, 2, 3, 4]).unwrap();
+        let input = input.to_str().unwrap();
+        let frequencies = [110.0, 220.0];
+        let key = |hop_size, multirate| CacheKey { frequencies: &frequencies, hop_size, pixels_per_second: 32, sample_rate: None, multirate, analytic: true, spectral: false };
+
+        let path = cache_path::<f32>("cache", input, &key(None, false)).unwrap();
+        assert_eq!(path, cache_path::<f32>("cache", input, &key(None, false)).unwrap());
+        assert!(path.starts_with("cache"));
+        let double_frequencies = [110.0, 220.0];
+        let double_key = CacheKey { frequencies: &double_frequencies, hop_size: None, pixels_per_second: 32, sample_rate: None, multirate: false, analytic: true, spectral: false };
+        assert_ne!(path, cache_path::<f64>("cache", input, &double_key).unwrap());
+        assert_ne!(path, cache_path::<f32>("cache", input, &key(Some(64), false)).unwrap());
+        assert_ne!(path, cache_path::<f32>("cache", input, &key(None, true)).unwrap());
+        assert_ne!(path, cache_path::<f32>("cache", input, &CacheKey { pixels_per_second: 64, ..key(None, false) }).unwrap());
+        assert_eq!(cache_path::<f32>("cache", input, &key(Some(64), false)).unwrap(),
+                   cache_path::<f32>("cache", input, &CacheKey { pixels_per_second: 64, ..key(Some(64), false) }).unwrap());
+        assert_ne!(path, cache_path::<f32>("cache", input, &CacheKey { spectral: true, ..key(None, false) }).unwrap());
+        assert_ne!(path, cache_path::<f32>("cache", input, &CacheKey { sample_rate: Some(48000), ..key(None, false) }).unwrap());
+        assert_ne!(path, cache_path::<f32>("cache", input, &CacheKey { frequencies: &[110.0], hop_size: None, pixels_per_second: 32, sample_rate: None, multirate: false, analytic: true, spectral: false }).unwrap());
+
+        fs::write(input, [1, 2, 3, 5]).unwrap();
+        assert_ne!(path, cache_path::<f32>("cache", input, &key(None, false)).unwrap());
+        fs::remove_file(input).unwrap();
+    }
+}
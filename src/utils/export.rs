@@ -0,0 +1,100 @@
+use std::fmt::Write;
+use std::fs;
+use crate::utils::file_extension;
+
+/// Cell of an exported table
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Missing,
+}
+
+#[derive(Clone, Copy)]
+pub enum TableFormat {
+    Csv,
+    Json,
+}
+
+impl TableFormat {
+    /// Format of a table file from its extension (.csv or .json)
+    pub fn from_file_name(file_name: &str) -> Result<Self, String> {
+        match file_extension(file_name) {
+            Some("csv") => Ok(TableFormat::Csv),
+            Some("json") => Ok(TableFormat::Json),
+            _ => Err(format!("Only .csv and .json formats are supported for exports, but export file is {}!", file_name)),
+        }
+    }
+}
+
+/// Writes the rows as CSV with a header line, or as a JSON array of objects, depending on the extension of the file
+pub fn write_table(file_name: &str, columns: &[&str], rows: &[Vec<Value>]) -> Result<(), String> {
+    let text = table_text(TableFormat::from_file_name(file_name)?, columns, rows);
+    fs::write(file_name, text).map_err(|e| format!("Could not write {}: {}", file_name, e))
+}
+
+fn table_text(format: TableFormat, columns: &[&str], rows: &[Vec<Value>]) -> String {
+    let mut text = String::new();
+    match format {
+        TableFormat::Csv => {
+            writeln!(text, "{}", columns.join(",")).unwrap();
+            for row in rows {
+                let cells: Vec<String> = row.iter()
+                    .map(|value| match value {
+                        Value::Number(number) => format_number(*number),
+                        Value::Text(text) if text.contains([',', '"', '\n']) => format!("\"{}\"", text.replace('"', "\"\"")),
+                        Value::Text(text) => text.clone(),
+                        Value::Missing => String::new(),
+                    })
+                    .collect();
+                writeln!(text, "{}", cells.join(",")).unwrap();
+            }
+        }
+        TableFormat::Json => {
+            text.push('[');
+            for (index, row) in rows.iter().enumerate() {
+                let fields: Vec<String> = columns.iter().zip(row)
+                    .map(|(column, value)| {
+                        let value = match value {
+                            Value::Number(number) if number.is_finite() => format_number(*number),
+                            Value::Text(text) => format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"")),
+                            _ => "null".to_string(),
+                        };
+                        format!("\"{}\": {}", column, value)
+                    })
+                    .collect();
+                write!(text, "{}\n  {{{}}}", if index == 0 { "" } else { "," }, fields.join(", ")).unwrap();
+            }
+            text.push_str("\n]\n");
+        }
+    }
+    text
+}
+
+/// Numbers are rounded to 4 decimals, well below what any of the analyses resolve
+fn format_number(number: f64) -> String {
+    let rounded = (number * 1e4).round() / 1e4;
+    if rounded == 0.0 { "0".to_string() } else { rounded.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::export::{table_text, TableFormat, Value};
+
+    fn rows() -> Vec<Vec<Value>> {
+        vec![vec![Value::Number(0.0), Value::Number(220.123456), Value::Text("A3".to_string())],
+             vec![Value::Number(0.01), Value::Missing, Value::Text("a \"b\", c".to_string())]]
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(table_text(TableFormat::Csv, &["time", "frequency", "note"], &rows()),
+                   "time,frequency,note\n0,220.1235,A3\n0.01,,\"a \"\"b\"\", c\"\n");
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(table_text(TableFormat::Json, &["time", "frequency", "note"], &rows()),
+                   "[\n  {\"time\": 0, \"frequency\": 220.1235, \"note\": \"A3\"},\n  \
+                    {\"time\": 0.01, \"frequency\": null, \"note\": \"a \\\"b\\\", c\"}\n]\n");
+    }
+}
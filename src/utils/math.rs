@@ -1,21 +1,59 @@
-use num_complex::{Complex};
+use std::iter::Sum;
+use std::str::FromStr;
+#[cfg(test)]
+use num_complex::Complex;
+use num_traits::{FloatConst, ToPrimitive};
+use rustfft::FftNum;
 
-pub(crate) type FloatType = f32;
+pub type FloatType = f32;
 
+/// Floating point types the wavelet transform and the visualization can be computed with (f32 or f64)
+pub trait Float: FftNum + num_traits::Float + FloatConst + Sum + Default {}
+
+impl Float for f32 {}
+
+impl Float for f64 {}
+
+pub enum Precision {
+    Single,
+    Double,
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "f32" => Ok(Precision::Single),
+            "f64" => Ok(Precision::Double),
+            _ => Err(format!("Invalid precision '{}'", s).to_string()),
+        }
+    }
+}
+
+/// Converts a number (constants, lengths, sample rates) to the given float type
+pub fn cast<T: Float>(value: impl ToPrimitive) -> T {
+    T::from(value).unwrap()
+}
+
+#[cfg(test)]
 pub(crate) fn i(i: FloatType) -> Complex<FloatType> {
     Complex { re: 0.0, im: i }
 }
 
+#[cfg(test)]
 pub(crate) fn re(re: FloatType) -> Complex<FloatType> {
     Complex { re, im: 0.0 }
 }
 
+#[cfg(test)]
 pub(crate) fn assert_epsilon(actual: FloatType, expected: FloatType) {
     if (expected - actual).abs() > 1e-6 {
         panic!("Expected {:?} to be equal to {:?} with an epsilon of 1e-6", actual, expected);
     }
 }
 
+#[cfg(test)]
 pub(crate) fn assert_complex_vec(actual: &[Complex<FloatType>], expected: &[Complex<FloatType>]) {
     if actual.len() != expected.len() {
         panic!("Expected size {:?} but got {:?}", expected.len(), actual.len());
@@ -25,6 +63,7 @@ pub(crate) fn assert_complex_vec(actual: &[Complex<FloatType>], expected: &[Comp
     }
 }
 
+#[cfg(test)]
 pub(crate) fn assert_complex(actual: Complex<FloatType>, expected: Complex<FloatType>, index: usize) {
     if (expected.re - actual.re).abs() > 1e-6 || (expected.im - actual.im).abs() > 1e-6 {
         panic!("Expected {:?} to be equal to {:?} with an epsilon of 1e-6 at index {}", actual, expected, index);
@@ -1,19 +1,38 @@
 use std::str::FromStr;
 use image::{ImageFormat, save_buffer_with_format};
-use num_complex::ComplexFloat;
 use num_complex::Complex;
-use crate::utils::math::FloatType;
+use rayon::prelude::*;
+use crate::notes::tuning::Tuning;
+use crate::signals::Scalogram;
+use crate::signals::wavelets::morlet_center;
+use crate::utils::math::{cast, Float, FloatType};
+use crate::utils::visualization::font::{draw_text, GLYPH_HEIGHT, text_width};
 
-pub(crate) enum ResamplingStrategy {
+mod font;
+
+/// Frequency whose rows the strip under the scalogram and the tick marks are aligned with (middle C), as the rows are
+/// shifted in time by the length of their wavelet
+const TIMELINE_FREQUENCY: f64 = 261.63;
+
+#[derive(Clone, Copy)]
+pub enum ResamplingStrategy {
     Map,
     Avg,
 }
 
 impl ResamplingStrategy {
-    pub fn sample(&self, previous: FloatType, value: Complex<FloatType>, partition_size: usize) -> FloatType {
+    pub fn sample<T: Float>(&self, previous: T, value: Complex<T>, partition_size: usize) -> T {
         match self {
-            ResamplingStrategy::Map => value.abs().max(previous),
-            ResamplingStrategy::Avg => previous + value.abs() / (partition_size as FloatType),
+            ResamplingStrategy::Map => value.norm().max(previous),
+            ResamplingStrategy::Avg => previous + value.norm() / cast(partition_size),
+        }
+    }
+
+    /// Same as sample, for the signed real parts of the coefficients: max keeps the value farthest from zero
+    pub fn sample_signed<T: Float>(&self, previous: T, value: Complex<T>, partition_size: usize) -> T {
+        match self {
+            ResamplingStrategy::Map => if value.re.abs() > previous.abs() { value.re } else { previous },
+            ResamplingStrategy::Avg => previous + value.re / cast(partition_size),
         }
     }
 }
@@ -30,9 +49,12 @@ impl FromStr for ResamplingStrategy {
     }
 }
 
-pub(crate) enum ColorScheme {
+#[derive(Clone, Copy)]
+pub enum ColorScheme {
     HeatMap,
     Grayscale,
+    /// Blue below 0.5, white at 0.5 and red above, for signed values
+    Diverging,
 }
 
 impl ColorScheme {
@@ -43,6 +65,14 @@ impl ColorScheme {
                 let b = (value * 255.0).round() as u8;
                 (b, b, b)
             }
+            ColorScheme::Diverging => {
+                let fade = |distance: FloatType| (255.0 * (1.0 - 2.0 * distance.abs()).clamp(0.0, 1.0)).round() as u8;
+                if value < 0.5 {
+                    (fade(value - 0.5), fade(value - 0.5), 255)
+                } else {
+                    (255, fade(value - 0.5), fade(value - 0.5))
+                }
+            }
         }
     }
 
@@ -82,25 +112,50 @@ impl FromStr for ColorScheme {
         match s {
             "heatmap" => Ok(ColorScheme::HeatMap),
             "grayscale" => Ok(ColorScheme::Grayscale),
+            "diverging" => Ok(ColorScheme::Diverging),
             _ => Err(format!("Invalid color scheme '{}'", s).to_string()),
         }
     }
 }
 
-pub(crate) struct VisualizationParameters {
+/// Annotation drawn over the scalogram, at times in seconds and frequencies in Hz
+pub enum Overlay {
+    /// Line through the points, interrupted where the frequency is missing
+    Contour { points: Vec<(f64, Option<f64>)>, color: (u8, u8, u8) },
+    /// Vertical marks of the given length in pixels hanging from the top of the scalogram at the times
+    Ticks { times: Vec<f64>, length: usize, color: (u8, u8, u8) },
+    /// Dashed horizontal line across the scalogram at the frequency
+    Level { frequency: f64, color: (u8, u8, u8) },
+    /// Text starting at the time, just above the row of the frequency
+    Label { time: f64, frequency: f64, text: String, color: (u8, u8, u8) },
+}
+
+pub struct VisualizationParameters {
     pub file_name: String,
     pub frequencies: Vec<FloatType>,
-    pub sample_rate: u32,
     pub resampling_strategy: ResamplingStrategy,
     pub color_scheme: ColorScheme,
     pub pixels_per_second: u32,
     pub pixels_per_frequency: u32,
     pub add_piano_roll: bool,
+    /// Tuning the piano roll keys are matched against
+    pub tuning: Tuning,
+    /// One label per frequency, in the same order, drawn left of the rows when there's room for them
+    pub labels: Option<Vec<String>>,
+    pub overlays: Vec<Overlay>,
+    /// Whether the real parts of the coefficients are drawn around zero, for signed values such as differences,
+    /// rather than their magnitudes
+    pub signed: bool,
+    /// Whether the rows show the transform as computed, early by half the length of their wavelet, rather than
+    /// aligned on the times they describe
+    pub wavelet_delay: bool,
+    /// Labeled time spans as (start, end, label) in seconds, drawn in a strip under the scalogram
+    pub strip: Option<Vec<(f64, f64, String)>>,
     pub image_format: ImageFormat,
 }
 
-pub(crate) fn output_image(wavelet_transform: &[Vec<Complex<FloatType>>],
-                           visualization_parameters: &VisualizationParameters) {
+pub fn output_image<T: Float>(wavelet_transform: &Scalogram<Complex<T>>,
+                              visualization_parameters: &VisualizationParameters) -> Result<(), String> {
     let (image_data, width, height) =
         transform_to_image(wavelet_transform, visualization_parameters);
 
@@ -109,63 +164,256 @@ pub(crate) fn output_image(wavelet_transform: &[Vec<Complex<FloatType>>],
                             width as u32,
                             height as u32,
                             image::ColorType::Rgb8,
-                            visualization_parameters.image_format).unwrap();
+                            visualization_parameters.image_format)
+        .map_err(|e| format!("Could not save {}: {}", visualization_parameters.file_name, e))
 }
 
-fn transform_to_image(transform: &[Vec<Complex<FloatType>>],
-                      visualization_parameters: &VisualizationParameters) -> (Vec<u8>, usize, usize) {
+/// Saves an image of the matrix, with its first row at the bottom and one square of the given size in pixels per cell,
+/// colored from its largest values (coolest) to its smallest ones (hottest), with the path of (row, column) cells over it
+pub fn output_matrix_image(matrix: &[Vec<f64>], path: &[(usize, usize)], pixels_per_cell: usize, color_scheme: ColorScheme,
+                           file_name: &str, image_format: ImageFormat) -> Result<(), String> {
+    let (rows, columns) = (matrix.len(), matrix.first().map(|row| row.len()).unwrap_or(0));
+    let (width, height) = (columns * pixels_per_cell, rows * pixels_per_cell);
+    let max = matrix.iter().flatten().copied().fold(0.0, f64::max).max(f64::MIN_POSITIVE);
+    let mut image = vec![0; width * height * 3];
+    image.par_chunks_mut(width * 3).enumerate().for_each(|(y, pixels)| {
+        let row = &matrix[rows - 1 - y / pixels_per_cell];
+        for (x, pixel) in pixels.chunks_mut(3).enumerate() {
+            let (r, g, b) = color_scheme.color(cast(1.0 - row[x / pixels_per_cell] / max));
+            pixel.copy_from_slice(&[r, g, b]);
+        }
+    });
+    // a pixel wide line through the middle of the cells
+    let center = |(row, column): (usize, usize)| {
+        (column * pixels_per_cell + pixels_per_cell / 2, (rows - 1 - row) * pixels_per_cell + pixels_per_cell / 2)
+    };
+    for (x, y) in path.windows(2).flat_map(|step| {
+        let ((x0, y0), (x1, y1)) = (center(step[0]), center(step[1]));
+        (0..pixels_per_cell).map(move |pixel| (x0 + (x1 - x0) * pixel / pixels_per_cell, y0 - (y0 - y1) * pixel / pixels_per_cell))
+    }).chain(path.last().map(|cell| center(*cell))) {
+        image[3 * (y * width + x)..3 * (y * width + x) + 3].fill(255);
+    }
+    save_buffer_with_format(file_name, &image, width as u32, height as u32, image::ColorType::Rgb8, image_format)
+        .map_err(|e| format!("Could not save {}: {}", file_name, e))
+}
+
+fn transform_to_image<T: Float>(transform: &Scalogram<Complex<T>>,
+                                visualization_parameters: &VisualizationParameters) -> (Vec<u8>, usize, usize) {
     let piano_roll_length = if visualization_parameters.add_piano_roll {
         24.max(visualization_parameters.pixels_per_second / 2).min(128) as usize
     } else { 0 };
-    let chunk_size = (visualization_parameters.sample_rate / visualization_parameters.pixels_per_second) as usize;
-    let new_width = piano_roll_length + transform[0].len() / chunk_size;
-    let new_height = transform.len() * visualization_parameters.pixels_per_frequency as usize;
-
-    let mut sampled = Vec::with_capacity(new_height);
-    for vec in transform {
-        let mut row = Vec::with_capacity(new_width - piano_roll_length);
-        for chunk_index in 0..(new_width - piano_roll_length) {
-            let chunk_offset = chunk_index * chunk_size;
-            let mut value = 0.0;
-            for k in 0..chunk_size {
-                value = visualization_parameters.resampling_strategy.sample(value, vec[chunk_offset + k], chunk_size);
+    let labels_length = visualization_parameters.labels.as_ref()
+        .map(|labels| labels.iter().map(|label| text_width(label)).max().unwrap_or(0) + 2)
+        .unwrap_or(0);
+    let margin_length = labels_length + piano_roll_length;
+    let hop_size = transform.hop_size;
+    let frames = transform.frames;
+    let chunk_size = (transform.sample_rate / visualization_parameters.pixels_per_second).max(1) as usize;
+    let new_width = margin_length + frames * hop_size / chunk_size;
+    let scalogram_height = transform.rows().len() * visualization_parameters.pixels_per_frequency as usize;
+    let strip_height = if visualization_parameters.strip.is_some() { GLYPH_HEIGHT + 4 } else { 0 };
+    let new_height = scalogram_height + strip_height;
+
+    let pixels_per_frequency = visualization_parameters.pixels_per_frequency as usize;
+    let sampled: Vec<Vec<T>> = transform.coefficients
+        .par_chunks(frames)
+        .map(|vec| {
+            let mut row = Vec::with_capacity(new_width - margin_length);
+            for chunk_index in 0..(new_width - margin_length) {
+                // frames whose first sample falls in the chunk, or the closest one if hop size is larger than the chunk
+                let first_frame = (chunk_index * chunk_size).div_ceil(hop_size).min(frames - 1);
+                let last_frame = ((chunk_index + 1) * chunk_size).div_ceil(hop_size).clamp(first_frame + 1, frames);
+                let mut value = T::zero();
+                let strategy = visualization_parameters.resampling_strategy;
+                for coefficient in &vec[first_frame..last_frame] {
+                    value = if visualization_parameters.signed {
+                        strategy.sample_signed(value, *coefficient, last_frame - first_frame)
+                    } else {
+                        strategy.sample(value, *coefficient, last_frame - first_frame)
+                    };
+                }
+                row.push(value);
             }
-            row.push(value);
-        }
-        for _ in 0..visualization_parameters.pixels_per_frequency {
-            sampled.push(row.clone());
-        }
-    }
+            row
+        })
+        .collect();
 
     let frequencies = &visualization_parameters.frequencies;
-    let max = find_max(&sampled, &std::convert::identity);
-    let mut resized_data = Vec::with_capacity(new_height * new_width * 3);
-    for i in 0..new_height {
-        (0..piano_roll_length).for_each(|k| {
-            let frequency = frequencies[frequencies.len() - 1 - (i / (visualization_parameters.pixels_per_frequency as usize))];
-            let note = ((12.0 * (frequency / 16.35).log2()) % 12.0).round() as i32;
+    let max = find_max(&sampled, &|value: T| value.abs());
+    let mut resized_data = vec![0; new_height * new_width * 3];
+    resized_data[..scalogram_height * new_width * 3].par_chunks_mut(new_width * 3).enumerate().for_each(|(i, pixels)| {
+        let (labels, pixels) = pixels.split_at_mut(labels_length * 3);
+        labels.fill(255);
+        let (piano_roll, pixels) = pixels.split_at_mut(piano_roll_length * 3);
+        let frequency = frequencies[frequencies.len() - 1 - (i / pixels_per_frequency)];
+        let note = visualization_parameters.tuning.nearest_note(frequency as f64).rem_euclid(12);
+        for (k, pixel) in piano_roll.chunks_mut(3).enumerate() {
             if k < (piano_roll_length as f32 * 0.8) as usize && (note == 1 || note == 3 || note == 6 || note == 8 || note == 10) {
-                resized_data.push(0);
-                resized_data.push(0);
-                resized_data.push(0);
+                pixel.fill(0);
             } else {
-                resized_data.push(255);
-                resized_data.push(255);
-                resized_data.push(255);
+                pixel.fill(255);
             }
-        });
-        for k in 0..(new_width - piano_roll_length) {
-            let (r, g, b) = visualization_parameters.color_scheme.color(sampled[i][k] / max);
-            resized_data.push(r);
-            resized_data.push(g);
-            resized_data.push(b);
         }
+        for (value, pixel) in sampled[i / pixels_per_frequency].iter().zip(pixels.chunks_mut(3)) {
+            let value = match visualization_parameters.signed {
+                // identical inputs differ by zero everywhere
+                true if max == T::zero() => cast(0.5),
+                true => (*value / max + T::one()) / cast(2.0),
+                false => *value / max,
+            };
+            let (r, g, b) = visualization_parameters.color_scheme.color(cast(value));
+            pixel.copy_from_slice(&[r, g, b]);
+        }
+    });
+    if let Some(labels) = &visualization_parameters.labels {
+        draw_labels(&mut resized_data, new_width, labels, pixels_per_frequency);
+    }
+    let geometry = ImageGeometry {
+        width: new_width,
+        margin: margin_length,
+        pixels_per_sample: 1.0 / chunk_size as f64,
+        sample_rate: transform.sample_rate as f64,
+        pixels_per_frequency,
+        frequencies: frequencies.iter().map(|frequency| *frequency as f64).collect(),
+        wavelet_delay: visualization_parameters.wavelet_delay,
+    };
+    for overlay in &visualization_parameters.overlays {
+        draw_overlay(&mut resized_data, &geometry, overlay);
+    }
+    if let Some(segments) = &visualization_parameters.strip {
+        draw_strip(&mut resized_data[scalogram_height * new_width * 3..], &geometry, segments);
     }
     (resized_data, new_width, new_height)
 }
 
-fn find_max<T: Copy>(result: &Vec<Vec<T>>, transform_fn: &impl Fn(T) -> FloatType) -> FloatType {
-    let mut max = 0.0;
+/// Where times and frequencies fall on the image
+struct ImageGeometry {
+    width: usize,
+    /// Width of the labels and piano roll left of the scalogram
+    margin: usize,
+    pixels_per_sample: f64,
+    sample_rate: f64,
+    pixels_per_frequency: usize,
+    frequencies: Vec<f64>,
+    wavelet_delay: bool,
+}
+
+impl ImageGeometry {
+    /// Column of an event at a time and frequency. Coefficients of the transform are drawn at the start of their
+    /// wavelet, so events appear earlier than they happen by half the length of the wavelet of their frequency.
+    fn x(&self, time: f64, frequency: f64) -> f64 {
+        let delay = if self.wavelet_delay { morlet_center(frequency) } else { 0.0 };
+        self.margin as f64 + (time - delay) * self.sample_rate * self.pixels_per_sample
+    }
+
+    /// Row of a frequency, interpolated in log frequency between the centers of the rows, None outside of them
+    fn y(&self, frequency: f64) -> Option<f64> {
+        let frequencies = &self.frequencies;
+        let above = frequencies.partition_point(|row| *row < frequency);
+        if above == frequencies.len() || (above == 0 && frequency < frequencies[0]) {
+            return None;
+        }
+        let position = if above == 0 {
+            0.0
+        } else {
+            above as f64 - 1.0 + (frequency / frequencies[above - 1]).ln() / (frequencies[above] / frequencies[above - 1]).ln()
+        };
+        Some((frequencies.len() as f64 - 1.0 - position + 0.5) * self.pixels_per_frequency as f64)
+    }
+
+    fn set_pixel(&self, image: &mut [u8], x: f64, y: f64, color: (u8, u8, u8)) {
+        let (x, y) = (x.round(), y.round());
+        if x < self.margin as f64 || x >= self.width as f64 || y < 0.0 || y as usize >= image.len() / (3 * self.width) {
+            return;
+        }
+        let index = 3 * (y as usize * self.width + x as usize);
+        image[index..index + 3].copy_from_slice(&[color.0, color.1, color.2]);
+    }
+}
+
+/// Draws the segments in an image of the strip: a white band with a line at the start of every segment followed by
+/// its label, if there's room for it before the next one. Segments are aligned with the rows of middle C.
+fn draw_strip(strip: &mut [u8], geometry: &ImageGeometry, segments: &[(f64, f64, String)]) {
+    let width = geometry.width;
+    let height = strip.len() / (3 * width);
+    strip.fill(255);
+    for (start, end, label) in segments {
+        let x0 = geometry.x(*start, TIMELINE_FREQUENCY).round().max(geometry.margin as f64) as usize;
+        let x1 = geometry.x(*end, TIMELINE_FREQUENCY).round().min(width as f64) as usize;
+        if x0 >= x1 {
+            continue;
+        }
+        for y in 0..height {
+            strip[3 * (y * width + x0)..3 * (y * width + x0) + 3].fill(0);
+        }
+        if x0 + 2 + text_width(label) <= x1 {
+            draw_text(strip, width, x0 + 2, 2, label, (0, 0, 0));
+        }
+    }
+}
+
+fn draw_overlay(image: &mut [u8], geometry: &ImageGeometry, overlay: &Overlay) {
+    match overlay {
+        Overlay::Contour { points, color } => {
+            let positions: Vec<Option<(f64, f64)>> = points.iter()
+                .map(|(time, frequency)| {
+                    let frequency = (*frequency)?;
+                    Some((geometry.x(*time, frequency), geometry.y(frequency)?))
+                })
+                .collect();
+            for segment in positions.windows(2) {
+                let (Some((x0, y0)), Some((x1, y1))) = (segment[0], segment[1]) else { continue };
+                // two pixels thick so that the line stands out of the scalogram
+                let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+                for step in 0..=steps {
+                    let position = step as f64 / steps as f64;
+                    let (x, y) = (x0 + position * (x1 - x0), y0 + position * (y1 - y0));
+                    geometry.set_pixel(image, x, y, *color);
+                    geometry.set_pixel(image, x, y + 1.0, *color);
+                }
+            }
+        }
+        Overlay::Ticks { times, length, color } => {
+            for time in times {
+                let x = geometry.x(*time, TIMELINE_FREQUENCY);
+                for y in 0..*length {
+                    geometry.set_pixel(image, x, y as f64, *color);
+                }
+            }
+        }
+        Overlay::Level { frequency, color } => {
+            let Some(y) = geometry.y(*frequency) else { return };
+            for x in (geometry.margin..geometry.width).filter(|x| (x - geometry.margin) % 8 < 5) {
+                geometry.set_pixel(image, x as f64, y, *color);
+                geometry.set_pixel(image, x as f64, y + 1.0, *color);
+            }
+        }
+        Overlay::Label { time, frequency, text, color } => {
+            let (x, Some(y)) = (geometry.x(*time, *frequency).round(), geometry.y(*frequency)) else { return };
+            let top = y - (geometry.pixels_per_frequency / 2 + GLYPH_HEIGHT + 1) as f64;
+            if x >= geometry.margin as f64 && top >= 0.0 {
+                draw_text(image, geometry.width, x as usize, top.round() as usize, text, *color);
+            }
+        }
+    }
+}
+
+/// Draws the labels of the rows (given from the lowest frequency) vertically centered on them, skipping the ones that
+/// would overlap the previous label
+fn draw_labels(image: &mut [u8], width: usize, labels: &[String], pixels_per_frequency: usize) {
+    let mut next_free_y = 0;
+    for (row, label) in labels.iter().rev().enumerate() {
+        let y = row * pixels_per_frequency + pixels_per_frequency.saturating_sub(GLYPH_HEIGHT) / 2;
+        if label.is_empty() || y < next_free_y {
+            continue;
+        }
+        draw_text(image, width, 1, y, label, (0, 0, 0));
+        next_free_y = y + GLYPH_HEIGHT + 1;
+    }
+}
+
+fn find_max<T: Copy, F: Float>(result: &Vec<Vec<T>>, transform_fn: &impl Fn(T) -> F) -> F {
+    let mut max = F::zero();
     for row in result {
         for value in row {
             let value = transform_fn(*value);
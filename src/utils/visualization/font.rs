@@ -0,0 +1,63 @@
+/// Width and height in pixels of a character, without spacing
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+/// Rows of a 3x5 glyph, most significant of the 3 bits on the left. Unknown characters are blank.
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    match character {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'N' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'a' => [0b000, 0b011, 0b101, 0b101, 0b011],
+        'd' => [0b001, 0b011, 0b101, 0b101, 0b011],
+        'i' => [0b010, 0b000, 0b010, 0b010, 0b010],
+        'j' => [0b001, 0b000, 0b001, 0b101, 0b010],
+        'm' => [0b000, 0b110, 0b111, 0b101, 0b101],
+        's' => [0b000, 0b011, 0b100, 0b001, 0b110],
+        'u' => [0b000, 0b101, 0b101, 0b101, 0b011],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        'k' => [0b100, 0b101, 0b110, 0b101, 0b101],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Width in pixels of text, with one pixel between characters
+pub fn text_width(text: &str) -> usize {
+    let characters = text.chars().count();
+    if characters == 0 { 0 } else { characters * (GLYPH_WIDTH + 1) - 1 }
+}
+
+/// Draws text in an RGB image of the given width, with its top left corner at (x, y), clipped to the image
+pub fn draw_text(image: &mut [u8], width: usize, x: usize, y: usize, text: &str, color: (u8, u8, u8)) {
+    let height = image.len() / (width * 3);
+    for (index, character) in text.chars().enumerate() {
+        let left = x + index * (GLYPH_WIDTH + 1);
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                let (px, py) = (left + column, y + row);
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 && px < width && py < height {
+                    let offset = (py * width + px) * 3;
+                    image[offset..offset + 3].copy_from_slice(&[color.0, color.1, color.2]);
+                }
+            }
+        }
+    }
+}